@@ -1,3 +1,7 @@
 fn main() {
-    prost_build::compile_protos(&["src/gtfs.proto"], &["src/"]).unwrap();
+    prost_build::Config::new()
+        // Lets the realtime feeds be re-published as JSON.
+        .type_attribute(".transit_realtime", "#[derive(serde::Serialize)]")
+        .compile_protos(&["src/gtfs.proto", "src/vector_tile.proto"], &["src/"])
+        .unwrap();
 }
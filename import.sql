@@ -1,6 +1,6 @@
-\COPY agency FROM './seq_gtfs/agency.txt' (FORMAT CSV, HEADER)
+\COPY agency (agency_name, agency_url, agency_timezone, agency_lang, agency_phone) FROM './seq_gtfs/agency.txt' (FORMAT CSV, HEADER)
 \COPY stops FROM './seq_gtfs/stops.txt' (FORMAT CSV, HEADER)
-\COPY routes FROM './seq_gtfs/routes.txt' (FORMAT CSV, HEADER)
+\COPY routes (route_id, route_short_name, route_long_name, route_desc, route_type, route_url, route_color, route_text_color) FROM './seq_gtfs/routes.txt' (FORMAT CSV, HEADER)
 \COPY trips FROM './seq_gtfs/trips.txt' (FORMAT CSV, HEADER)
 \COPY stop_times FROM './seq_gtfs/stop_times.txt' (FORMAT CSV, HEADER)
 \COPY calendar FROM './seq_gtfs/calendar.txt' (FORMAT CSV, HEADER)
@@ -0,0 +1,9 @@
+-- Trigram indexes backing the fuzzy stop and route search.
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS stops_stop_name_trgm_idx ON stops USING gin (stop_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS stops_stop_code_trgm_idx ON stops USING gin (stop_code gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS stops_parent_station_idx ON stops (parent_station);
+CREATE INDEX IF NOT EXISTS routes_route_short_name_trgm_idx ON routes USING gin (route_short_name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS routes_route_long_name_trgm_idx ON routes USING gin (route_long_name gin_trgm_ops);
@@ -0,0 +1,7 @@
+-- agency_id links routes to their agency, so realtime data can be filtered by agency.
+-- Both are optional, as feeds with a single agency may leave them out.
+
+ALTER TABLE agency ADD COLUMN agency_id text NULL UNIQUE;
+ALTER TABLE routes ADD COLUMN agency_id text NULL;
+
+CREATE INDEX IF NOT EXISTS routes_agency_id_idx ON routes (agency_id);
@@ -0,0 +1,11 @@
+-- Walking transfers between nearby stops, generated after each static import.
+-- The feed's transfers.txt isn't usable, so these are what the journey planner walks.
+
+CREATE TABLE IF NOT EXISTS generated_transfers
+(
+  from_stop_id           text NOT NULL REFERENCES stops ON DELETE CASCADE ON UPDATE CASCADE,
+  to_stop_id             text NOT NULL REFERENCES stops ON DELETE CASCADE ON UPDATE CASCADE,
+  distance_m             double precision NOT NULL CHECK (distance_m >= 0),
+  walk_time              integer NOT NULL CHECK (walk_time >= 0),
+  PRIMARY KEY (from_stop_id, to_stop_id)
+);
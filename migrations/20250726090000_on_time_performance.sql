@@ -0,0 +1,88 @@
+-- What the realtime feed last said about each stop of each trip, kept after the trip is gone.
+-- Each poll overwrites the last, so once a stop has been passed this is (close to) what happened.
+-- No foreign keys, as the archive has to outlive the static feed it was recorded against.
+
+CREATE TABLE IF NOT EXISTS stop_time_observations
+(
+  trip_id                text NOT NULL,
+  service_date           date NOT NULL,
+  stop_sequence          integer NOT NULL,
+  arrival_time           timestamp NULL,
+  departure_time         timestamp NULL,
+  arrival_delay          integer NULL,
+  departure_delay        integer NULL,
+  observed_at            timestamp NOT NULL,
+  PRIMARY KEY (trip_id, service_date, stop_sequence)
+);
+
+CREATE INDEX IF NOT EXISTS stop_time_observations_service_date_idx ON stop_time_observations (service_date);
+
+-- Seconds late (negative for early) leaving each observed stop, against stop_times.
+-- Only stops already passed when last observed are included.
+CREATE OR REPLACE VIEW stop_time_delays AS
+SELECT * FROM (
+  SELECT
+    o.trip_id,
+    o.service_date,
+    o.stop_sequence,
+    st.stop_id,
+    t.route_id,
+    floor(EXTRACT(EPOCH FROM st.departure_time) / 3600)::integer AS hour,
+    COALESCE(
+      EXTRACT(EPOCH FROM o.departure_time - (o.service_date + st.departure_time)),
+      o.departure_delay,
+      EXTRACT(EPOCH FROM o.arrival_time - (o.service_date + COALESCE(st.arrival_time, st.departure_time))),
+      o.arrival_delay
+    )::integer AS delay,
+    o.service_date + st.departure_time AS scheduled,
+    o.observed_at
+  FROM stop_time_observations o
+  JOIN stop_times st ON st.trip_id = o.trip_id AND st.stop_sequence = o.stop_sequence
+  JOIN trips t ON t.trip_id = o.trip_id
+) d
+WHERE delay IS NOT NULL AND scheduled + make_interval(secs => delay) <= observed_at;
+
+-- Daily summaries, rebuilt by a scheduled job. early/late are outside the on-time window.
+
+CREATE TABLE IF NOT EXISTS route_performance
+(
+  service_date           date NOT NULL,
+  route_id               text NOT NULL,
+  observations           integer NOT NULL,
+  on_time                integer NOT NULL,
+  early                  integer NOT NULL,
+  late                   integer NOT NULL,
+  mean_delay             double precision NOT NULL,
+  median_delay           double precision NOT NULL,
+  p90_delay              double precision NOT NULL,
+  PRIMARY KEY (service_date, route_id)
+);
+
+CREATE TABLE IF NOT EXISTS stop_performance
+(
+  service_date           date NOT NULL,
+  stop_id                text NOT NULL,
+  observations           integer NOT NULL,
+  on_time                integer NOT NULL,
+  early                  integer NOT NULL,
+  late                   integer NOT NULL,
+  mean_delay             double precision NOT NULL,
+  median_delay           double precision NOT NULL,
+  p90_delay              double precision NOT NULL,
+  PRIMARY KEY (service_date, stop_id)
+);
+
+-- hour is of the scheduled departure, and goes past 23 for trips running after midnight.
+CREATE TABLE IF NOT EXISTS hourly_performance
+(
+  service_date           date NOT NULL,
+  hour                   integer NOT NULL,
+  observations           integer NOT NULL,
+  on_time                integer NOT NULL,
+  early                  integer NOT NULL,
+  late                   integer NOT NULL,
+  mean_delay             double precision NOT NULL,
+  median_delay           double precision NOT NULL,
+  p90_delay              double precision NOT NULL,
+  PRIMARY KEY (service_date, hour)
+);
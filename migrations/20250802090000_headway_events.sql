@@ -0,0 +1,21 @@
+-- Bunching (vehicles too close together) and gaps (too far apart) seen at stops on frequent routes.
+-- Rebuilt for a service date at a time from stop_time_delays, like the performance summaries.
+
+CREATE TABLE IF NOT EXISTS headway_events
+(
+  service_date           date NOT NULL,
+  route_id               text NOT NULL,
+  direction_id           boolean NULL,
+  stop_id                text NOT NULL,
+  stop_sequence          integer NOT NULL,
+  trip_id                text NOT NULL,
+  previous_trip_id       text NOT NULL,
+  kind                   text NOT NULL CHECK (kind IN ('bunching', 'gap')),
+  departed               timestamp NOT NULL,
+  -- Seconds since the previous vehicle left the stop, and since it was scheduled to.
+  headway                integer NOT NULL,
+  scheduled_headway      integer NOT NULL,
+  PRIMARY KEY (service_date, trip_id, stop_sequence)
+);
+
+CREATE INDEX IF NOT EXISTS headway_events_route_idx ON headway_events (route_id, service_date);
@@ -0,0 +1,37 @@
+-- Every trip the realtime feed has mentioned, by service date, kept after it drops out of the feed.
+-- schedule_relationship is the latest one from the TripDescriptor, e.g. SCHEDULED, CANCELED or ADDED.
+
+CREATE TABLE IF NOT EXISTS trip_observations
+(
+  trip_id                text NOT NULL,
+  service_date           date NOT NULL,
+  route_id               text NULL,
+  schedule_relationship  text NOT NULL,
+  in_trip_updates        boolean NOT NULL,
+  in_vehicle_positions   boolean NOT NULL,
+  first_seen             timestamp NOT NULL,
+  last_seen              timestamp NOT NULL,
+  PRIMARY KEY (trip_id, service_date)
+);
+
+-- Trips that didn't run as scheduled: scheduled but never seen (missing),
+-- cancelled in the feed, or added by it. Rebuilt a service date at a time.
+CREATE TABLE IF NOT EXISTS trip_statuses
+(
+  service_date           date NOT NULL,
+  trip_id                text NOT NULL,
+  route_id               text NULL,
+  status                 text NOT NULL CHECK (status IN ('missing', 'cancelled', 'added')),
+  scheduled_start        timestamp NULL,
+  PRIMARY KEY (service_date, trip_id)
+);
+
+CREATE TABLE IF NOT EXISTS daily_trip_reports
+(
+  service_date           date PRIMARY KEY,
+  scheduled              integer NOT NULL,
+  seen                   integer NOT NULL,
+  missing                integer NOT NULL,
+  cancelled              integer NOT NULL,
+  added                  integer NOT NULL
+);
@@ -0,0 +1,16 @@
+-- Progress of the static import, a row per table. Rows only last until the import finishes,
+-- so any left over are from one that was interrupted, and tables it completed can be skipped
+-- when the same file comes round again. checksum is the file's CRC-32 from the zip.
+
+CREATE TABLE IF NOT EXISTS import_progress
+(
+  feed_region            text NOT NULL,
+  table_name             text NOT NULL,
+  checksum               bigint NULL,
+  rows_done              bigint NOT NULL,
+  rows_total             bigint NULL,
+  started_at             timestamp NOT NULL,
+  updated_at             timestamp NOT NULL,
+  completed_at           timestamp NULL,
+  PRIMARY KEY (feed_region, table_name)
+);
@@ -0,0 +1,15 @@
+-- Staging tables for the static import. Each table of a new feed is loaded into its
+-- staging table on its own connection, then they're all merged into the live tables in
+-- one transaction, so nobody ever sees half a feed. They're emptied once that commits.
+-- An interrupted import leaves them as they are, so the tables it completed (see
+-- import_progress) don't have to be loaded again. Columns have to match the live tables.
+
+CREATE TABLE IF NOT EXISTS agency_staging (LIKE agency INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (agency_name));
+CREATE TABLE IF NOT EXISTS stops_staging (LIKE stops INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (stop_id));
+CREATE TABLE IF NOT EXISTS routes_staging (LIKE routes INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (route_id));
+CREATE TABLE IF NOT EXISTS trips_staging (LIKE trips INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (trip_id));
+CREATE TABLE IF NOT EXISTS stop_times_staging (LIKE stop_times INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (trip_id, stop_sequence));
+CREATE TABLE IF NOT EXISTS calendar_staging (LIKE calendar INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (service_id));
+CREATE TABLE IF NOT EXISTS calendar_dates_staging (LIKE calendar_dates INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (service_id, date));
+CREATE TABLE IF NOT EXISTS shapes_staging (LIKE shapes INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (shape_id, shape_pt_sequence));
+CREATE TABLE IF NOT EXISTS feed_info_staging (LIKE feed_info INCLUDING DEFAULTS INCLUDING CONSTRAINTS, PRIMARY KEY (feed_publisher_name));
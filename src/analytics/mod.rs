@@ -0,0 +1,220 @@
+//! ANALYTICS
+//!
+//! Archives what each realtime poll says about every trip and stop time,
+//! then summarises the archive against the schedule on a timer: on-time
+//! performance, bunching and gaps on frequent routes, and trips that were
+//! missed, cancelled or added. Everything is per day, so it can be rebuilt
+//! as many times as needed.
+
+#[cfg(test)]
+mod tests;
+
+use std::collections::{HashMap, hash_map::Entry};
+
+use anyhow::Result;
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use tracing::{info, instrument};
+
+use crate::{
+    db::{
+        Db, queries,
+        types::{StopTimeObservation, TripObservation},
+    },
+    gtfs::RealtimeGtfs,
+    routing::realtime::parse_date,
+    transit_realtime::{
+        TripDescriptor,
+        trip_update::{StopTimeEvent, stop_time_update::ScheduleRelationship as StopRelationship},
+    },
+    vars,
+};
+
+/// A realtime timestamp as a time in the feed's timezone, which the schedule is in.
+fn local_time(timestamp: i64, timezone: Tz) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(timestamp, 0).map(|t| t.with_timezone(&timezone).naive_local())
+}
+
+/// Every stop time prediction in a poll, as of when its feed was generated.
+/// Updates without a start date are taken to be for today, and stop time
+/// updates without a stop_sequence are left out (SEQ always sends one).
+/// Times are in now's timezone, which should be the feed's.
+/// A stop time mentioned more than once is only kept as of the latest feed, as
+/// the upsert can't touch the same row twice.
+pub fn observations(realtime: &RealtimeGtfs, now: DateTime<Tz>) -> Vec<StopTimeObservation> {
+    let (timezone, today) = (now.timezone(), now.date_naive());
+    let mut observations: Vec<StopTimeObservation> = vec![];
+    let mut seen: HashMap<(String, i32, NaiveDate), usize> = HashMap::new();
+    for message in &realtime.0 {
+        let observed_at = message
+            .header
+            .timestamp
+            .and_then(|t| local_time(t as i64, timezone))
+            .unwrap_or(now.naive_local());
+        let updates = message
+            .entity
+            .iter()
+            .filter(|e| !e.is_deleted())
+            .filter_map(|e| e.trip_update.as_ref());
+        for update in updates {
+            let Some(trip_id) = &update.trip.trip_id else {
+                continue;
+            };
+            let service_date = parse_date(&update.trip.start_date).unwrap_or(today);
+            for stop_time in &update.stop_time_update {
+                if !matches!(
+                    stop_time.schedule_relationship(),
+                    StopRelationship::Scheduled | StopRelationship::Unscheduled
+                ) {
+                    continue;
+                }
+                let Some(stop_sequence) = stop_time.stop_sequence else {
+                    continue;
+                };
+                let time = |e: &Option<StopTimeEvent>| {
+                    e.as_ref()?.time.and_then(|t| local_time(t, timezone))
+                };
+                let delay = |e: &Option<StopTimeEvent>| e.as_ref()?.delay;
+                let observation = StopTimeObservation {
+                    trip_id: trip_id.clone(),
+                    service_date,
+                    stop_sequence: stop_sequence as i32,
+                    arrival_time: time(&stop_time.arrival),
+                    departure_time: time(&stop_time.departure),
+                    arrival_delay: delay(&stop_time.arrival),
+                    departure_delay: delay(&stop_time.departure),
+                    observed_at,
+                };
+                match seen.entry((trip_id.clone(), observation.stop_sequence, service_date)) {
+                    Entry::Occupied(i) => {
+                        let kept = &mut observations[*i.get()];
+                        if observation.observed_at >= kept.observed_at {
+                            *kept = observation;
+                        }
+                    }
+                    Entry::Vacant(i) => {
+                        i.insert(observations.len());
+                        observations.push(observation);
+                    }
+                }
+            }
+        }
+    }
+    observations
+}
+
+/// Every trip mentioned by a trip update or vehicle position in a poll, once each.
+pub fn trip_observations(realtime: &RealtimeGtfs, now: DateTime<Tz>) -> Vec<TripObservation> {
+    let (today, now) = (now.date_naive(), now.naive_local());
+    let mut trips: HashMap<(String, NaiveDate), TripObservation> = HashMap::new();
+    let mut observe = |trip: &TripDescriptor, trip_update: bool| {
+        let Some(trip_id) = &trip.trip_id else {
+            return;
+        };
+        let service_date = parse_date(&trip.start_date).unwrap_or(today);
+        let observation = trips
+            .entry((trip_id.clone(), service_date))
+            .or_insert_with(|| TripObservation {
+                trip_id: trip_id.clone(),
+                service_date,
+                route_id: None,
+                schedule_relationship: trip.schedule_relationship().as_str_name().to_owned(),
+                in_trip_updates: false,
+                in_vehicle_positions: false,
+                first_seen: now,
+                last_seen: now,
+            });
+        observation.route_id = observation.route_id.take().or(trip.route_id.clone());
+        if trip_update {
+            // Trip updates are what cancel and add trips, so they have the final say.
+            observation.schedule_relationship =
+                trip.schedule_relationship().as_str_name().to_owned();
+            observation.in_trip_updates = true;
+        } else {
+            observation.in_vehicle_positions = true;
+        }
+    };
+    for entity in realtime.0.iter().flat_map(|m| &m.entity) {
+        if entity.is_deleted() {
+            continue;
+        }
+        if let Some(update) = &entity.trip_update {
+            observe(&update.trip, true);
+        }
+        if let Some(trip) = entity.vehicle.as_ref().and_then(|v| v.trip.as_ref()) {
+            observe(trip, false);
+        }
+    }
+    trips.into_values().collect()
+}
+
+/// Stores a poll's trips and stop times, over whatever earlier polls said.
+/// fetched_at is when the poll was fetched, which is in the past when replaying.
+#[instrument(skip_all)]
+pub async fn archive(db: &Db, realtime: &RealtimeGtfs, fetched_at: DateTime<Utc>) -> Result<()> {
+    let now = fetched_at.with_timezone(&db.timezone().await?);
+    let trips = trip_observations(realtime, now);
+    let stop_times = observations(realtime, now);
+    queries::upsert_trip_observations(&trips, &db.0).await?;
+    queries::upsert_stop_time_observations(&stop_times, &db.0).await?;
+    info!(
+        trips = trips.len(),
+        stop_times = stop_times.len(),
+        "Archived realtime poll"
+    );
+    Ok(())
+}
+
+/// Rebuilds the on-time performance summaries for a service date.
+#[instrument(skip(db))]
+pub async fn summarise(db: &Db, service_date: NaiveDate) -> Result<()> {
+    let (early, late) = (vars::on_time_early_secs(), vars::on_time_late_secs());
+    let mut tx = db.0.begin().await?;
+    queries::summarise_route_performance(service_date, early, late, &mut tx).await?;
+    queries::summarise_stop_performance(service_date, early, late, &mut tx).await?;
+    queries::summarise_hourly_performance(service_date, early, late, &mut tx).await?;
+    tx.commit().await?;
+    info!("Summarised on-time performance");
+    Ok(())
+}
+
+/// Rebuilds the bunching and gap events for a service date.
+#[instrument(skip(db))]
+pub async fn detect_headway_events(db: &Db, service_date: NaiveDate) -> Result<()> {
+    let mut tx = db.0.begin().await?;
+    queries::detect_headway_events(
+        service_date,
+        vars::headway_bunching_secs(),
+        vars::headway_gap_factor(),
+        vars::headway_max_scheduled_secs(),
+        &mut tx,
+    )
+    .await?;
+    tx.commit().await?;
+    info!("Detected headway events");
+    Ok(())
+}
+
+/// Rebuilds the missed, cancelled and added trips for a service date, and its report.
+#[instrument(skip(db))]
+pub async fn report_trips(db: &Db, service_date: NaiveDate) -> Result<()> {
+    let service_ids = queries::get_active_service_ids(service_date, &db.0).await?;
+    let now = Utc::now().with_timezone(&db.timezone().await?);
+    let mut tx = db.0.begin().await?;
+    queries::detect_trip_statuses(service_date, &service_ids, now.naive_local(), &mut tx).await?;
+    queries::summarise_daily_trip_report(service_date, &service_ids, &mut tx).await?;
+    tx.commit().await?;
+    info!("Reported trips");
+    Ok(())
+}
+
+/// Summarises today so far, and yesterday, whose late trips may only just have finished.
+pub async fn summarise_recent(db: &Db) -> Result<()> {
+    let today = Utc::now().with_timezone(&db.timezone().await?).date_naive();
+    for date in [today - Days::new(1), today] {
+        summarise(db, date).await?;
+        detect_headway_events(db, date).await?;
+        report_trips(db, date).await?;
+    }
+    Ok(())
+}
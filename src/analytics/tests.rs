@@ -0,0 +1,202 @@
+//! Analytics tests
+//!
+//! Tests turning realtime polls into observations.
+
+use chrono::{DateTime, NaiveDate, TimeZone};
+use chrono_tz::{Australia::Brisbane, Tz};
+
+use super::{observations, trip_observations};
+use crate::gtfs::RealtimeGtfs;
+use crate::transit_realtime::{
+    FeedEntity, FeedHeader, FeedMessage, TripDescriptor, TripUpdate, VehiclePosition,
+    trip_descriptor::ScheduleRelationship as TripRelationship,
+    trip_update::{
+        StopTimeEvent, StopTimeUpdate, stop_time_update::ScheduleRelationship as StopRelationship,
+    },
+};
+
+fn now() -> DateTime<Tz> {
+    Brisbane.with_ymd_and_hms(2025, 7, 1, 8, 30, 0).unwrap()
+}
+
+fn update(trip_id: &str, start_date: Option<&str>, stop_times: Vec<StopTimeUpdate>) -> FeedEntity {
+    FeedEntity {
+        id: trip_id.into(),
+        trip_update: Some(TripUpdate {
+            trip: TripDescriptor {
+                trip_id: Some(trip_id.into()),
+                start_date: start_date.map(Into::into),
+                ..Default::default()
+            },
+            stop_time_update: stop_times,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn delayed(stop_sequence: Option<u32>, delay: i32) -> StopTimeUpdate {
+    StopTimeUpdate {
+        stop_sequence,
+        departure: Some(StopTimeEvent {
+            delay: Some(delay),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_observations_from_trip_updates() {
+    let mut skipped = delayed(Some(3), 0);
+    skipped.set_schedule_relationship(StopRelationship::Skipped);
+    let realtime = RealtimeGtfs(vec![FeedMessage {
+        entity: vec![
+            update(
+                "a",
+                None,
+                vec![delayed(Some(1), 60), skipped, delayed(None, 30)],
+            ),
+            update("b", Some("20250630"), vec![delayed(Some(7), -45)]),
+        ],
+        ..Default::default()
+    }]);
+
+    let observations = observations(&realtime, now());
+    let observed: Vec<_> = observations
+        .iter()
+        .map(|o| {
+            (
+                o.trip_id.as_str(),
+                o.service_date,
+                o.stop_sequence,
+                o.departure_delay,
+            )
+        })
+        .collect();
+    // Skipped stops and stops without a sequence are left out.
+    assert_eq!(
+        observed,
+        vec![
+            ("a", now().date_naive(), 1, Some(60)),
+            (
+                "b",
+                NaiveDate::from_ymd_opt(2025, 6, 30).unwrap(),
+                7,
+                Some(-45)
+            ),
+        ]
+    );
+    // Without a feed timestamp, the time of the poll is used.
+    assert!(
+        observations
+            .iter()
+            .all(|o| o.observed_at == now().naive_local())
+    );
+}
+
+#[test]
+fn test_trip_observations_merge_feeds() {
+    let mut cancelled = update("cancelled", None, vec![]);
+    if let Some(update) = &mut cancelled.trip_update {
+        update
+            .trip
+            .set_schedule_relationship(TripRelationship::Canceled);
+    }
+    let vehicle = |trip_id: &str| FeedEntity {
+        id: trip_id.into(),
+        vehicle: Some(VehiclePosition {
+            trip: Some(TripDescriptor {
+                trip_id: Some(trip_id.into()),
+                route_id: Some("1".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let realtime = RealtimeGtfs(vec![
+        FeedMessage {
+            entity: vec![update("both", None, vec![]), cancelled],
+            ..Default::default()
+        },
+        FeedMessage {
+            entity: vec![vehicle("both"), vehicle("vehicle")],
+            ..Default::default()
+        },
+    ]);
+
+    let mut trips: Vec<_> = trip_observations(&realtime, now())
+        .into_iter()
+        .map(|t| {
+            (
+                t.trip_id,
+                t.route_id,
+                t.schedule_relationship,
+                t.in_trip_updates,
+                t.in_vehicle_positions,
+            )
+        })
+        .collect();
+    trips.sort();
+    let some = |s: &str| Some(s.to_owned());
+    assert_eq!(
+        trips,
+        vec![
+            ("both".into(), some("1"), "SCHEDULED".into(), true, true),
+            ("cancelled".into(), None, "CANCELED".into(), true, false),
+            ("vehicle".into(), some("1"), "SCHEDULED".into(), false, true),
+        ]
+    );
+}
+
+#[test]
+fn test_observations_in_feed_timezone() {
+    // 22:00 UTC is 08:00 the next day in Brisbane, whatever zone the server is in.
+    let generated = Brisbane.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap();
+    let mut stop_time = delayed(Some(1), 60);
+    if let Some(departure) = &mut stop_time.departure {
+        departure.time = Some(generated.timestamp() + 600);
+    }
+    let realtime = RealtimeGtfs(vec![FeedMessage {
+        header: FeedHeader {
+            timestamp: Some(generated.timestamp() as u64),
+            ..Default::default()
+        },
+        entity: vec![update("a", None, vec![stop_time])],
+    }]);
+
+    let observations = observations(&realtime, now());
+    assert_eq!(observations[0].observed_at, generated.naive_local());
+    assert_eq!(
+        observations[0].departure_time,
+        Some(
+            NaiveDate::from_ymd_opt(2025, 7, 1)
+                .unwrap()
+                .and_hms_opt(8, 10, 0)
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_observations_keep_latest_duplicate() {
+    let message = |minutes: u32, delay: i32| FeedMessage {
+        header: FeedHeader {
+            timestamp: Some(
+                Brisbane
+                    .with_ymd_and_hms(2025, 7, 1, 8, minutes, 0)
+                    .unwrap()
+                    .timestamp() as u64,
+            ),
+            ..Default::default()
+        },
+        entity: vec![update("a", None, vec![delayed(Some(1), delay)])],
+    };
+    // The same stop time can turn up in two feeds, or twice in one.
+    let realtime = RealtimeGtfs(vec![message(20, 120), message(10, 60), message(15, 90)]);
+
+    let observations = observations(&realtime, now());
+    assert_eq!(observations.len(), 1);
+    assert_eq!(observations[0].departure_delay, Some(120));
+}
@@ -0,0 +1,23 @@
+use axum::{
+    Json,
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+
+use super::ApiError;
+use crate::export::geojson::{self, FeatureCollection};
+
+fn geojson_response(collection: FeatureCollection) -> Response {
+    ([(CONTENT_TYPE, "application/geo+json")], Json(collection)).into_response()
+}
+
+/// GET /geojson/stops
+pub async fn stops(State(state): State<crate::State>) -> Result<Response, ApiError> {
+    Ok(geojson_response(geojson::stops(&state.db).await?))
+}
+
+/// GET /geojson/routes
+pub async fn routes(State(state): State<crate::State>) -> Result<Response, ApiError> {
+    Ok(geojson_response(geojson::routes(&state.db).await?))
+}
@@ -0,0 +1,87 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+
+use super::{ApiError, live::parse_bbox};
+use crate::{
+    export::{
+        gtfs::{cached_gtfs, temp_gtfs},
+        subset::FeedSubset,
+    },
+    vars,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct GtfsParams {
+    /// Comma separated route_ids.
+    routes: Option<String>,
+    agency: Option<String>,
+    /// min_lon,min_lat,max_lon,max_lat
+    bbox: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// GET /gtfs.zip?routes=27-3251,28-3251&agency=&bbox=152.9,-27.6,153.1,-27.4&from=2025-07-01&to=2025-07-07
+///
+/// The stored static feed, written back out as GTFS.
+/// Any of the params cut it down to a self-contained subset.
+/// The zip is written to a file and streamed from there, the whole feed's
+/// only once per import.
+pub async fn feed(
+    State(state): State<crate::State>,
+    Query(params): Query<GtfsParams>,
+) -> Result<Response, ApiError> {
+    let bbox = match params.bbox.as_deref().map(parse_bbox) {
+        Some(None) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "bbox should be min_lon,min_lat,max_lon,max_lat",
+            )
+                .into_response());
+        }
+        Some(bbox) => bbox,
+        None => None,
+    };
+    let dates = match (params.from, params.to) {
+        (Some(from), Some(to)) if from <= to => Some((from, to)),
+        (None, None) => None,
+        _ => {
+            return Ok((StatusCode::BAD_REQUEST, "Give both from and to, in order").into_response());
+        }
+    };
+    let subset = FeedSubset {
+        route_ids: params
+            .routes
+            .map(|r| r.split(',').map(|r| r.trim().to_owned()).collect()),
+        agency: params.agency,
+        bbox,
+        dates,
+    };
+
+    let zip = if subset.is_everything() {
+        cached_gtfs(&state.db, &vars::gtfs_cache_dir()).await?
+    } else {
+        temp_gtfs(&state.db, &subset).await?
+    };
+    let len = zip.metadata()?.len();
+    let body = Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(zip)));
+    Ok((
+        [
+            (CONTENT_TYPE, "application/zip"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"gtfs.zip\""),
+        ],
+        [(CONTENT_LENGTH, len.to_string())],
+        body,
+    )
+        .into_response())
+}
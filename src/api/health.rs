@@ -0,0 +1,31 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::health::{self, Report};
+
+/// GET /healthz
+///
+/// 200 while the db answers, 503 otherwise, with the check as json.
+pub async fn healthz(State(state): State<crate::State>) -> Response {
+    respond(health::live(&state.db).await)
+}
+
+/// GET /readyz
+///
+/// 200 once the schema is migrated, a current static feed is loaded and
+/// realtime polls are succeeding, 503 otherwise, with every check as json.
+pub async fn readyz(State(state): State<crate::State>) -> Response {
+    respond(health::ready(&state.db, &state.last_poll).await)
+}
+
+fn respond(report: Report) -> Response {
+    let status = match report.ok {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report)).into_response()
+}
@@ -0,0 +1,66 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use super::ApiError;
+use crate::{
+    export::geojson,
+    routing::{Endpoint, parse_departure},
+};
+
+/// Longest band anyone can ask for, in minutes. Bigger ones cover the whole network.
+const MAX_BAND_MINS: u32 = 180;
+
+#[derive(Debug, Deserialize)]
+pub struct IsochroneParams {
+    from: String,
+    date: Option<NaiveDate>,
+    /// HH:MM or HH:MM:SS.
+    time: Option<String>,
+    /// Comma separated minutes, 15,30,45,60 if not given.
+    bands: Option<String>,
+}
+
+/// GET /isochrone?from=lat,lon|stop_id&date=2025-07-01&time=08:00&bands=15,30
+///
+/// A GeoJSON FeatureCollection with a MultiPolygon per band and a Point per reached stop.
+pub async fn isochrone(
+    State(state): State<crate::State>,
+    Query(params): Query<IsochroneParams>,
+) -> Result<Response, ApiError> {
+    let Ok(from) = params.from.parse::<Endpoint>() else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid from").into_response());
+    };
+
+    let bands: Option<Vec<u32>> = params
+        .bands
+        .as_deref()
+        .unwrap_or("15,30,45,60")
+        .split(',')
+        .map(|b| b.trim().parse().ok())
+        .collect();
+    let Some(bands) = bands.filter(|b| b.iter().all(|&m| m > 0 && m <= MAX_BAND_MINS)) else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid bands").into_response());
+    };
+
+    let Ok((date, time)) = parse_departure(
+        params.date,
+        params.time.as_deref(),
+        Utc::now()
+            .with_timezone(&state.db.timezone().await?)
+            .naive_local(),
+    ) else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid time").into_response());
+    };
+
+    let isochrone = state
+        .planner
+        .isochrone(&state.db, from, date, time, bands)
+        .await?;
+    Ok(Json(geojson::isochrone(&isochrone)).into_response())
+}
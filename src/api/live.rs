@@ -0,0 +1,71 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::{StreamExt, future, stream};
+use serde::Deserialize;
+
+use super::ApiError;
+use crate::{
+    live::{LiveFilter, changes},
+    tiles::geometry::BBox,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LiveParams {
+    route: Option<String>,
+    /// min_lon,min_lat,max_lon,max_lat
+    bbox: Option<String>,
+}
+
+pub(super) fn parse_bbox(bbox: &str) -> Option<BBox> {
+    let parts: Vec<f64> = bbox
+        .split(',')
+        .map(|p| p.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    let [min_lon, min_lat, max_lon, max_lat] = parts[..] else {
+        return None;
+    };
+    Some(BBox {
+        min_lon,
+        min_lat,
+        max_lon,
+        max_lat,
+    })
+}
+
+/// GET /live?route=66-3454&bbox=152.9,-27.6,153.1,-27.4
+///
+/// Server-sent events. Sends the current state on connect, then changes after every poll.
+/// A client that falls too far behind is disconnected, and gets the state again on reconnecting.
+pub async fn live(
+    State(state): State<crate::State>,
+    Query(params): Query<LiveParams>,
+) -> Result<Response, ApiError> {
+    let bbox = match params.bbox.as_deref().map(parse_bbox) {
+        Some(None) => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "bbox should be min_lon,min_lat,max_lon,max_lat",
+            )
+                .into_response());
+        }
+        Some(bbox) => bbox,
+        None => None,
+    };
+    let mut filter = LiveFilter::new(params.route, bbox);
+
+    let (snapshot, receiver) = state.live.subscribe().await;
+    let events = stream::iter(snapshot)
+        .chain(changes(receiver))
+        .filter(move |event| future::ready(filter.matches(event)))
+        .map(|event| Event::default().event(event.kind()).json_data(&event));
+
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
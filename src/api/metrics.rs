@@ -0,0 +1,16 @@
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use prometheus::TEXT_FORMAT;
+
+use super::ApiError;
+
+/// GET /metrics
+///
+/// Static import and realtime poll metrics, in the Prometheus text format.
+pub async fn metrics(State(state): State<crate::State>) -> Result<Response, ApiError> {
+    let body = state.metrics.render(&state.db)?;
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], body).into_response())
+}
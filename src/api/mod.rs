@@ -0,0 +1,72 @@
+//! API
+//!
+//! The HTTP API, served alongside the pollers.
+//! Each submodule owns the handlers for one group of endpoints.
+
+mod geojson;
+mod gtfs;
+mod health;
+mod isochrone;
+mod live;
+mod metrics;
+mod plan;
+mod realtime;
+mod search;
+mod tiles;
+
+use anyhow::Result;
+use axum::{
+    Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use tokio::net::TcpListener;
+use tracing::{error, info, instrument};
+
+use crate::{State, vars};
+
+/// Builds the router for every endpoint.
+pub fn router(state: State) -> Router {
+    Router::new()
+        .route("/search", get(search::search))
+        .route("/geojson/stops", get(geojson::stops))
+        .route("/geojson/routes", get(geojson::routes))
+        .route("/gtfs.zip", get(gtfs::feed))
+        .route("/tiles/{z}/{x}/{y}", get(tiles::tile))
+        .route("/live", get(live::live))
+        .route("/realtime/{feed}", get(realtime::feed))
+        .route("/plan", get(plan::plan))
+        .route("/isochrone", get(isochrone::isochrone))
+        .route("/metrics", get(metrics::metrics))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .with_state(state)
+}
+
+/// Binds to API_ADDR and serves the API until the process exits.
+#[instrument(skip(state))]
+pub async fn serve(state: State) -> Result<()> {
+    let addr = vars::api_addr();
+    let listener = TcpListener::bind(&addr).await?;
+    info!(addr, "Serving API");
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+/// Wraps anyhow errors so handlers can just use `?`.
+/// Everything turns into a 500, the details only go to the logs.
+pub struct ApiError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError(e.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        error!(e=?self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+    }
+}
@@ -0,0 +1,59 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use super::ApiError;
+use crate::routing::{Endpoint, parse_departure};
+
+/// Widest range anyone can ask for, in minutes. A day of departures is plenty.
+const MAX_RANGE_MINS: u32 = 24 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct PlanParams {
+    from: String,
+    to: String,
+    date: Option<NaiveDate>,
+    /// HH:MM or HH:MM:SS.
+    time: Option<String>,
+    /// Minutes after the departure time to give journeys for.
+    range: Option<u32>,
+}
+
+/// GET /plan?from=lat,lon|stop_id&to=lat,lon|stop_id&date=2025-07-01&time=08:30&range=60
+///
+/// Leaves now, in the feed's timezone, if no date or time is given.
+pub async fn plan(
+    State(state): State<crate::State>,
+    Query(params): Query<PlanParams>,
+) -> Result<Response, ApiError> {
+    let (Ok(from), Ok(to)) = (
+        params.from.parse::<Endpoint>(),
+        params.to.parse::<Endpoint>(),
+    ) else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid from or to").into_response());
+    };
+    if params.range.is_some_and(|range| range > MAX_RANGE_MINS) {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid range").into_response());
+    }
+
+    let Ok((date, time)) = parse_departure(
+        params.date,
+        params.time.as_deref(),
+        Utc::now()
+            .with_timezone(&state.db.timezone().await?)
+            .naive_local(),
+    ) else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid time").into_response());
+    };
+
+    let itineraries = state
+        .planner
+        .plan(&state.db, from, to, date, time, params.range)
+        .await?;
+    Ok(Json(itineraries).into_response())
+}
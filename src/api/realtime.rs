@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use prost::Message;
+use serde::Deserialize;
+
+use super::ApiError;
+use crate::{
+    db::queries,
+    gtfs::realtime::{FeedKind, RealtimeFilter},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct RealtimeParams {
+    route: Option<String>,
+    agency: Option<String>,
+    /// "pb" or "json", if not given by the path.
+    format: Option<String>,
+}
+
+/// GET /realtime/{trip_updates|vehicle_positions|alerts}[.pb|.json]?route=&agency=
+///
+/// The latest poll re-published as a single FeedMessage. Protobuf unless asked for json.
+pub async fn feed(
+    State(state): State<crate::State>,
+    Path(feed): Path<String>,
+    Query(params): Query<RealtimeParams>,
+) -> Result<Response, ApiError> {
+    let (feed, format) = match feed.rsplit_once('.') {
+        Some((feed, format)) => (feed, Some(format)),
+        None => (feed.as_str(), params.format.as_deref()),
+    };
+    let Ok(kind) = feed.parse::<FeedKind>() else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let Some(realtime) = state.realtime.get().await else {
+        return Ok((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No realtime data polled yet",
+        )
+            .into_response());
+    };
+
+    let mut route_ids: Option<HashSet<String>> = params.route.map(|r| HashSet::from([r]));
+    if let Some(agency) = &params.agency {
+        let agency_routes: HashSet<String> = queries::get_agency_route_ids(agency, &state.db.0)
+            .await?
+            .into_iter()
+            .collect();
+        route_ids = Some(match route_ids {
+            Some(route_ids) => &route_ids & &agency_routes,
+            None => agency_routes,
+        });
+    }
+    let filter = RealtimeFilter {
+        route_ids,
+        agency: params.agency,
+    };
+
+    let message = realtime.merged(kind, &filter);
+    Ok(match format {
+        Some("json") => Json(message).into_response(),
+        Some("pb") | None => (
+            [(CONTENT_TYPE, "application/x-protobuf")],
+            message.encode_to_vec(),
+        )
+            .into_response(),
+        Some(_) => StatusCode::NOT_FOUND.into_response(),
+    })
+}
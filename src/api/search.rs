@@ -0,0 +1,21 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+
+use super::ApiError;
+use crate::search::{self, SearchResults};
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: String,
+}
+
+/// GET /search?q=roma st
+pub async fn search(
+    State(state): State<crate::State>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResults>, ApiError> {
+    Ok(Json(search::search(&params.q, &state.db).await?))
+}
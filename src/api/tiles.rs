@@ -0,0 +1,30 @@
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+
+use super::ApiError;
+use crate::tiles::{self, geometry::TileId};
+
+/// GET /tiles/{z}/{x}/{y}.mvt
+pub async fn tile(
+    State(state): State<crate::State>,
+    Path((z, x, y)): Path<(u8, u32, String)>,
+) -> Result<Response, ApiError> {
+    let Some(tile) = y
+        .strip_suffix(".mvt")
+        .and_then(|y| y.parse().ok())
+        .and_then(|y| TileId::new(z, x, y))
+    else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let data = state.tiles.get(&state.db).await?;
+    let bytes = tokio::task::spawn_blocking(move || tiles::render(&data, tile)).await?;
+    Ok((
+        [(CONTENT_TYPE, "application/vnd.mapbox-vector-tile")],
+        bytes,
+    )
+        .into_response())
+}
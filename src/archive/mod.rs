@@ -0,0 +1,199 @@
+//! ARCHIVE
+//!
+//! Keeps every raw realtime payload on disk, so polls can be replayed later
+//! through the same pipeline without a network. Each day gets its own gzip
+//! file, with one gzip member appended per poll, and old days are deleted.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Days, Local, NaiveDate, Utc};
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    gtfs::{RawFeed, RealtimeGtfs},
+    vars,
+};
+
+/// A payload as archived, with the timestamp from its FeedHeader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedFeed {
+    pub feed: RawFeed,
+    pub timestamp: Option<u64>,
+}
+
+/// Every feed fetched in one poll.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedPoll {
+    pub fetched_at: DateTime<Utc>,
+    pub feeds: Vec<ArchivedFeed>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawArchive {
+    dir: PathBuf,
+    /// Days kept, including today.
+    retention_days: u64,
+}
+
+impl RawArchive {
+    pub fn new(dir: PathBuf, retention_days: u64) -> RawArchive {
+        RawArchive {
+            dir,
+            retention_days,
+        }
+    }
+
+    /// The archive set up by REALTIME_ARCHIVE_DIR, if it is.
+    pub fn from_env() -> Option<RawArchive> {
+        Some(RawArchive::new(
+            vars::realtime_archive_dir()?,
+            vars::realtime_archive_days(),
+        ))
+    }
+
+    fn path(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join(format!("realtime-{date}.gz"))
+    }
+
+    /// Appends a poll to its day's file. feeds and realtime are in the same order.
+    #[instrument(skip_all)]
+    pub async fn append(&self, feeds: &[RawFeed], realtime: &RealtimeGtfs) -> Result<()> {
+        let feeds: Vec<ArchivedFeed> = feeds
+            .iter()
+            .zip(&realtime.0)
+            .map(|(feed, message)| ArchivedFeed {
+                feed: feed.clone(),
+                timestamp: message.header.timestamp,
+            })
+            .collect();
+        let archive = self.clone();
+        tokio::task::spawn_blocking(move || archive.append_blocking(&feeds, Local::now())).await?
+    }
+
+    fn append_blocking(&self, feeds: &[ArchivedFeed], now: DateTime<Local>) -> Result<()> {
+        if feeds.is_empty() {
+            return Ok(());
+        }
+        let path = self.path(now.date_naive());
+        if !path.exists() {
+            fs::create_dir_all(&self.dir)?;
+            self.prune(now.date_naive())?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(&encode_poll(feeds))?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Deletes the files of days that have gone past the retention period.
+    fn prune(&self, today: NaiveDate) -> Result<()> {
+        let Some(oldest) = today.checked_sub_days(Days::new(self.retention_days.max(1) - 1)) else {
+            return Ok(());
+        };
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let date = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("realtime-")?.strip_suffix(".gz"))
+                .and_then(|d| d.parse::<NaiveDate>().ok());
+            if date.is_some_and(|d| d < oldest) {
+                info!(?path, "Deleting old realtime archive");
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every poll archived on a day, in order, read one at a time as they're
+    /// wanted. A poll cut off partway through (say by a crash) ends the day
+    /// early rather than failing it.
+    pub fn read_day(&self, date: NaiveDate) -> Result<impl Iterator<Item = ArchivedPoll> + use<>> {
+        let path = self.path(date);
+        let mut reader = match path.exists() {
+            true => Some(BufReader::new(MultiGzDecoder::new(File::open(path)?))),
+            false => None,
+        };
+        let polls = std::iter::from_fn(move || match decode_poll(reader.as_mut()?) {
+            Ok(poll) => poll,
+            Err(e) => {
+                warn!(%date, e=?e, "Realtime archive ends with a partial poll");
+                None
+            }
+        });
+        Ok(polls.fuse())
+    }
+}
+
+// Polls are a u32 feed count, then per feed: fetch time (i64 unix millis), whether
+// there's a timestamp (u8) and the timestamp (u64), then the url and payload, each
+// a u32 length then bytes. All little endian.
+
+fn encode_poll(feeds: &[ArchivedFeed]) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.extend((feeds.len() as u32).to_le_bytes());
+    for ArchivedFeed { feed, timestamp } in feeds {
+        buf.extend(feed.fetched_at.timestamp_millis().to_le_bytes());
+        buf.push(timestamp.is_some() as u8);
+        buf.extend(timestamp.unwrap_or(0).to_le_bytes());
+        for bytes in [feed.url.as_bytes(), &feed.payload] {
+            buf.extend((bytes.len() as u32).to_le_bytes());
+            buf.extend(bytes);
+        }
+    }
+    buf
+}
+
+/// The next poll, or None at the end of the file.
+fn decode_poll(reader: &mut impl Read) -> io::Result<Option<ArchivedPoll>> {
+    let mut count = [0; 4];
+    match reader.read_exact(&mut count) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    let mut feeds = vec![];
+    for _ in 0..u32::from_le_bytes(count) {
+        let mut fetched_at = [0; 8];
+        reader.read_exact(&mut fetched_at)?;
+        let mut has_timestamp = [0; 1];
+        reader.read_exact(&mut has_timestamp)?;
+        let mut timestamp = [0; 8];
+        reader.read_exact(&mut timestamp)?;
+        let url = String::from_utf8(read_bytes(reader)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let payload = read_bytes(reader)?;
+
+        feeds.push(ArchivedFeed {
+            feed: RawFeed {
+                url,
+                fetched_at: DateTime::from_timestamp_millis(i64::from_le_bytes(fetched_at))
+                    .ok_or(io::ErrorKind::InvalidData)?,
+                payload,
+            },
+            timestamp: (has_timestamp[0] == 1).then_some(u64::from_le_bytes(timestamp)),
+        });
+    }
+    let Some(fetched_at) = feeds.iter().map(|f| f.feed.fetched_at).min() else {
+        return Err(io::ErrorKind::InvalidData.into());
+    };
+    Ok(Some(ArchivedPoll { fetched_at, feeds }))
+}
+
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
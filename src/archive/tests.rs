@@ -0,0 +1,110 @@
+//! Archive tests
+//!
+//! Tests writing polls to disk and reading them back.
+
+use std::{fs, path::PathBuf};
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+
+use super::{ArchivedFeed, RawArchive, encode_poll};
+use crate::gtfs::RawFeed;
+
+/// A fresh directory for a test to archive into.
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gtfs-archive-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn at(day: u32, h: u32, m: u32) -> DateTime<Local> {
+    Local.with_ymd_and_hms(2025, 7, day, h, m, 0).unwrap()
+}
+
+fn feed(url: &str, fetched_at: DateTime<Local>, timestamp: Option<u64>) -> ArchivedFeed {
+    ArchivedFeed {
+        feed: RawFeed {
+            url: url.into(),
+            fetched_at: fetched_at.to_utc(),
+            payload: url.as_bytes().repeat(3),
+        },
+        timestamp,
+    }
+}
+
+#[test]
+fn test_archive_round_trip() {
+    let archive = RawArchive::new(dir("round-trip"), 30);
+    let first = vec![
+        feed("SEQ/TripUpdates", at(1, 8, 0), Some(1751320800)),
+        feed("SEQ/alerts", at(1, 8, 0), None),
+    ];
+    let second = vec![feed("SEQ/TripUpdates", at(1, 8, 1), Some(1751320860))];
+    archive.append_blocking(&first, at(1, 8, 0)).unwrap();
+    archive.append_blocking(&second, at(1, 8, 1)).unwrap();
+    archive.append_blocking(&second, at(2, 0, 5)).unwrap();
+
+    let polls = archive
+        .read_day(at(1, 0, 0).date_naive())
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(polls.len(), 2);
+    assert_eq!(polls[0].fetched_at, at(1, 8, 0).to_utc());
+    assert_eq!(polls[0].feeds, first);
+    assert_eq!(polls[1].feeds, second);
+    assert_eq!(
+        archive.read_day(at(2, 0, 0).date_naive()).unwrap().count(),
+        1
+    );
+    assert!(
+        archive
+            .read_day(NaiveDate::from_ymd_opt(2025, 7, 3).unwrap())
+            .unwrap()
+            .next()
+            .is_none()
+    );
+}
+
+#[test]
+fn test_archive_partial_poll() {
+    let archive = RawArchive::new(dir("partial"), 30);
+    let poll = vec![feed("SEQ/TripUpdates", at(1, 8, 0), None)];
+    archive.append_blocking(&poll, at(1, 8, 0)).unwrap();
+
+    // A second poll that only got halfway written.
+    let mut encoder = flate2::write::GzEncoder::new(
+        fs::OpenOptions::new()
+            .append(true)
+            .open(archive.path(at(1, 0, 0).date_naive()))
+            .unwrap(),
+        flate2::Compression::default(),
+    );
+    let encoded = encode_poll(&poll);
+    std::io::Write::write_all(&mut encoder, &encoded[..encoded.len() / 2]).unwrap();
+    encoder.finish().unwrap();
+
+    let polls = archive
+        .read_day(at(1, 0, 0).date_naive())
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(polls.len(), 1);
+    assert_eq!(polls[0].feeds, poll);
+}
+
+#[test]
+fn test_archive_deletes_old_days() {
+    let archive = RawArchive::new(dir("prune"), 2);
+    let poll = vec![feed("SEQ/TripUpdates", at(1, 8, 0), None)];
+    for day in 1..=3 {
+        archive.append_blocking(&poll, at(day, 8, 0)).unwrap();
+    }
+
+    let days: Vec<usize> = (1..=3)
+        .map(|day| {
+            archive
+                .read_day(at(day, 0, 0).date_naive())
+                .unwrap()
+                .count()
+        })
+        .collect();
+    assert_eq!(days, vec![0, 1, 1]);
+}
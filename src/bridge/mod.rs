@@ -1,6 +1,6 @@
 //! BRIDGE
 //!
-//! Handles briding data from gtfs-structures and protobufs (for realtime)
+//! Handles briding data from parsed static GTFS and protobufs (for realtime)
 //! to the DB types defined in db/types.rs
 
 pub mod realtime_bridge;
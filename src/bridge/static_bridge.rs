@@ -1,310 +1,587 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    db::{self, types::InsertDB},
-    gtfs::StaticGtfs,
-};
-use anyhow::{Context, Result, anyhow};
-use chrono::{NaiveDate, NaiveDateTime};
-use futures::{Stream, StreamExt};
-use gtfs_structures::FeedInfo;
-use rayon::prelude::*;
-use sqlx::{PgConnection, PgPool, Postgres, Transaction, postgres::types::PgInterval};
-use tokio::{
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
+    db::{
+        self, queries,
+        types::{
+            Agency, Calendar, CalendarDate, FeedInfo, ImportProgress, InsertDB, LastUpdate, Route,
+            Shape, StageDB, Stop, StopTime, Trip,
+        },
+    },
+    gtfs::{StaticGtfs, static_gtfs, static_gtfs::ParseStats},
 };
-use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{NaiveDate, Utc};
+use futures::{StreamExt, stream::BoxStream};
+use sqlx::postgres::types::PgInterval;
+use tokio::task::JoinHandle;
+use tracing::{Span, info, instrument};
 
-#[derive(Debug)]
+/// A static feed as streams of db rows, filled as the files are parsed.
 pub struct GtfsDbModel {
-    pub agencies: ReceiverStream<db::types::Agency>,
-    pub stops: ReceiverStream<db::types::Stop>,
-    pub routes: ReceiverStream<db::types::Route>,
-    pub trips: ReceiverStream<db::types::Trip>,
-    pub stop_times: ReceiverStream<db::types::StopTime>,
-    pub calendar: ReceiverStream<db::types::Calendar>,
-    pub calendar_dates: ReceiverStream<db::types::CalendarDate>,
-    pub shapes: ReceiverStream<db::types::Shape>,
-    pub feed_info: ReceiverStream<db::types::FeedInfo>,
+    pub agencies: TableStream<db::types::Agency>,
+    pub stops: TableStream<db::types::Stop>,
+    pub routes: TableStream<db::types::Route>,
+    pub trips: TableStream<db::types::Trip>,
+    pub stop_times: TableStream<db::types::StopTime>,
+    pub calendar: TableStream<db::types::Calendar>,
+    pub calendar_dates: TableStream<db::types::CalendarDate>,
+    pub shapes: TableStream<db::types::Shape>,
+    pub feed_info: TableStream<db::types::FeedInfo>,
 }
 
-fn convert<T, U>(items: Vec<T>, chunk_size: usize) -> UnboundedReceiver<U>
-where
-    T: ToDB<U> + Send + Sync + Clone + 'static,
-    U: Send + Sync + 'static,
-{
-    let (sender, receiver): (UnboundedSender<U>, UnboundedReceiver<U>) =
-        tokio::sync::mpsc::unbounded_channel();
-
-    tokio::task::spawn_blocking(move || {
-        items.chunks(chunk_size).for_each(|chunk| {
-            let converted: Vec<_> = chunk
-                .par_iter()
-                .filter_map(|item| item.clone().to_db().ok())
-                .collect();
-
-            for item in converted {
-                if sender.send(item).is_err() {
-                    break;
-                }
-            }
-        });
-    });
-
-    receiver
+/// One table's rows, and the parser sending them if they're coming from a file.
+pub struct TableStream<T> {
+    pub rows: BoxStream<'static, T>,
+    pub parser: Option<JoinHandle<Result<ParseStats>>>,
+    /// CRC-32 of the file the rows come from, so an interrupted import can tell it's the same.
+    pub checksum: Option<u32>,
+    /// How many rows there are, once it's known.
+    pub total: Arc<OnceLock<u64>>,
 }
 
-async fn spawn_stream_inserter<T: InsertDB + Send + Sync + 'static>(
-    tx: &mut PgConnection,
-    mut rx: UnboundedReceiver<T>,
-)
-//-> JoinHandle<()>
-{
-    // tokio::spawn(async move {
-    while let Some(item) = rx.recv().await {
-        item.insert(&mut *tx).await.unwrap();
+impl<T: Send + 'static> TableStream<T> {
+    /// Every row, once the parser has finished without error.
+    pub async fn collect(self) -> Result<Vec<T>> {
+        let rows = self.rows.collect().await;
+        finish_parser(self.parser).await?;
+        Ok(rows)
     }
-    // })
 }
 
-impl StaticGtfs {
-    pub async fn insert_db(self, db: db::Db) -> Result<()> {
-        let mut tx = db.0.begin().await?;
-        self.last_update.insert(&mut *tx);
-        spawn_stream_inserter(&mut *tx, convert(self.raw_gtfs.agencies?, 1024)).await;
-        tx.commit().await.ok();
-        let mut tx = db.0.begin().await?;
-        spawn_stream_inserter(&mut *tx, convert(self.raw_gtfs.stops?, 1024)).await;
-        spawn_stream_inserter(&mut *tx, convert(self.raw_gtfs.routes?, 1024)).await;
-        spawn_stream_inserter(&mut *tx, convert(self.raw_gtfs.trips?, 1024)).await;
-        spawn_stream_inserter(&mut *tx, convert(self.raw_gtfs.stop_times?, 1024)).await;
+async fn finish_parser(
+    parser: Option<JoinHandle<Result<ParseStats>>>,
+) -> Result<Option<ParseStats>> {
+    match parser {
+        Some(parser) => Ok(Some(parser.await??)),
+        None => Ok(None),
+    }
+}
 
-        if let Some(calendar) = self.raw_gtfs.calendar {
-            spawn_stream_inserter(&mut *tx, convert(calendar?, 1024)).await;
+impl<T: Send + 'static> From<Vec<T>> for TableStream<T> {
+    fn from(rows: Vec<T>) -> Self {
+        TableStream {
+            total: Arc::new(OnceLock::from(rows.len() as u64)),
+            rows: futures::stream::iter(rows).boxed(),
+            parser: None,
+            checksum: None,
         }
+    }
+}
 
-        if let Some(calendar_dates) = self.raw_gtfs.calendar_dates {
-            spawn_stream_inserter(&mut *tx, convert(calendar_dates?, 1024)).await;
-        }
+/// A static feed as db rows, all in memory, for when it needs looking at as a whole.
+#[derive(Debug, Default)]
+pub struct FeedRows {
+    pub agencies: Vec<db::types::Agency>,
+    pub stops: Vec<db::types::Stop>,
+    pub routes: Vec<db::types::Route>,
+    pub trips: Vec<db::types::Trip>,
+    pub stop_times: Vec<db::types::StopTime>,
+    pub calendar: Vec<db::types::Calendar>,
+    pub calendar_dates: Vec<db::types::CalendarDate>,
+    pub shapes: Vec<db::types::Shape>,
+    pub feed_info: Vec<db::types::FeedInfo>,
+}
 
-        if let Some(shapes) = self.raw_gtfs.shapes {
-            spawn_stream_inserter(&mut *tx, convert(shapes?, 1024)).await;
+impl From<FeedRows> for GtfsDbModel {
+    fn from(rows: FeedRows) -> Self {
+        GtfsDbModel {
+            agencies: rows.agencies.into(),
+            stops: rows.stops.into(),
+            routes: rows.routes.into(),
+            trips: rows.trips.into(),
+            stop_times: rows.stop_times.into(),
+            calendar: rows.calendar.into(),
+            calendar_dates: rows.calendar_dates.into(),
+            shapes: rows.shapes.into(),
+            feed_info: rows.feed_info.into(),
         }
+    }
+}
 
-        if let Some(feed_info) = self.raw_gtfs.feed_info {
-            spawn_stream_inserter(&mut *tx, convert(feed_info?, 1024)).await;
-        }
+/// Whether an import is into empty tables or brings existing ones in line with the feed.
+/// Rows are upserted either way, so only rows that changed are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// For a db without a feed yet, so there's nothing to delete.
+    Full,
+    /// Also deletes rows that aren't in the new feed.
+    Incremental,
+}
 
-        tx.commit().await.ok();
+/// How an import went, table by table.
+#[derive(Debug, Default, Clone)]
+pub struct ImportMetrics {
+    pub tables: Vec<TableMetrics>,
+    pub files: Vec<ParseStats>,
+    pub elapsed: Duration,
+    /// The process's peak resident memory, where the OS reports it.
+    pub peak_rss_bytes: Option<u64>,
+}
 
-        Ok(())
-    }
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableMetrics {
+    pub table: &'static str,
+    pub rows: u64,
+    /// Completed by an interrupted import of the same file, so left as it was.
+    pub skipped: bool,
+    /// Rows inserted or updated, leaving out the ones that were already the same.
+    pub changed: u64,
+    pub deleted: u64,
+    /// From the first row being waited on to it being staged, so it includes parsing.
+    pub elapsed: Duration,
 }
 
-pub trait ToDB<T>: Send {
-    fn to_db(self) -> Result<T>;
+impl TableMetrics {
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
 }
 
-// Just a ton of to_db implementations beyond this point:
+impl ImportMetrics {
+    pub fn rows(&self) -> u64 {
+        self.tables.iter().map(|t| t.rows).sum()
+    }
 
-impl ToDB<bool> for gtfs_structures::DirectionType {
-    fn to_db(self) -> Result<bool> {
-        Ok(matches!(self, gtfs_structures::DirectionType::Inbound))
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
     }
-}
 
-impl ToDB<i32> for gtfs_structures::LocationType {
-    fn to_db(self) -> Result<i32> {
-        Ok(match self {
-            gtfs_structures::LocationType::StopPoint => 0,
-            gtfs_structures::LocationType::StopArea => 1,
-            gtfs_structures::LocationType::StationEntrance => 2,
-            gtfs_structures::LocationType::GenericNode => 3,
-            gtfs_structures::LocationType::BoardingArea => 4,
-            gtfs_structures::LocationType::Unknown(i) => i,
+    fn table(&mut self, table: &str) -> &mut TableMetrics {
+        self.tables
+            .iter_mut()
+            .find(|t| t.table == table)
+            .expect("Every table is staged before it's merged")
+    }
+
+    fn log(&self) {
+        for table in &self.tables {
+            info!(
+                table.table,
+                table.rows,
+                table.skipped,
+                table.changed,
+                table.deleted,
+                elapsed = ?table.elapsed,
+                rows_per_sec = table.rows_per_sec() as u64,
+                "Inserted static GTFS table"
+            );
         }
-        .into())
+        info!(
+            rows = self.rows(),
+            elapsed = ?self.elapsed,
+            rows_per_sec = self.rows_per_sec() as u64,
+            peak_rss_mb = self.peak_rss_bytes.map(|b| b / 1024 / 1024),
+            "Inserted static GTFS"
+        );
     }
 }
 
-impl ToDB<i32> for gtfs_structures::RouteType {
-    fn to_db(self) -> Result<i32> {
-        Ok(match self {
-            gtfs_structures::RouteType::Tramway => 0,
-            gtfs_structures::RouteType::Subway => 1,
-            gtfs_structures::RouteType::Rail => 2,
-            gtfs_structures::RouteType::Bus => 3,
-            gtfs_structures::RouteType::Ferry => 4,
-            gtfs_structures::RouteType::CableCar => 5,
-            gtfs_structures::RouteType::Gondola => 6,
-            gtfs_structures::RouteType::Funicular => 7,
-            gtfs_structures::RouteType::Coach => 8,
-            gtfs_structures::RouteType::Air => 9,
-            gtfs_structures::RouteType::Taxi => 10,
-            gtfs_structures::RouteType::Other(i) => i,
+/// Reads VmHWM, the high water mark of resident memory, on Linux.
+fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+type Loaded = (TableMetrics, Option<ParseStats>);
+
+/// Tables an import stages at once, each holding a connection until it's staged.
+pub const STAGED_TABLES: u32 = 9;
+
+/// How often progress is logged and saved while a table loads.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What every table being loaded needs to know.
+struct Import<'a> {
+    db: &'a db::Db,
+    feed_region: &'a str,
+    /// Checksums of the tables an interrupted import completed.
+    completed: HashMap<String, i64>,
+}
+
+impl Import<'_> {
+    /// Loads a table into its staging table, in a transaction on its own connection
+    /// that commits only once its parser has finished without error. Rows are staged
+    /// as they come, so the stream's channel is what bounds how many are in memory.
+    #[instrument(skip_all, fields(table = table, rows = 0))]
+    async fn stage_table<T: StageDB + 'static>(
+        &self,
+        table: &'static str,
+        stream: TableStream<T>,
+    ) -> Result<Loaded> {
+        let start = Instant::now();
+        let checksum = stream.checksum.map(i64::from);
+        if checksum.is_some() && self.completed.get(table) == checksum.as_ref() {
+            info!("Skipping table, it was loaded by an interrupted import");
+            let metrics = TableMetrics {
+                table,
+                rows: 0,
+                skipped: true,
+                changed: 0,
+                deleted: 0,
+                elapsed: start.elapsed(),
+            };
+            return Ok((metrics, None));
         }
-        .into())
+
+        let now = Utc::now().naive_utc();
+        let progress = ImportProgress {
+            feed_region: self.feed_region.to_owned(),
+            table_name: table.to_owned(),
+            checksum,
+            rows_done: 0,
+            rows_total: stream.total.get().map(|&t| t as i64),
+            started_at: now,
+            updated_at: now,
+            completed_at: None,
+        };
+        queries::start_import_progress(&progress, &self.db.0).await?;
+
+        let mut tx = self.db.0.begin().await?;
+        T::clear_staged(&mut tx).await?;
+        let mut rows = stream.rows;
+        let mut count = 0;
+        let mut reported = Instant::now();
+        while let Some(row) = rows.next().await {
+            row.stage(&mut tx).await?;
+            count += 1;
+            if reported.elapsed() >= PROGRESS_INTERVAL {
+                self.report(table, count, stream.total.get().copied(), start)
+                    .await?;
+                reported = Instant::now();
+            }
+        }
+        let stats = finish_parser(stream.parser)
+            .await
+            .with_context(|| format!("Failed to load {table}"))?;
+        queries::complete_import_progress(self.feed_region, table, count as i64, &mut tx).await?;
+        tx.commit().await?;
+        Span::current().record("rows", count);
+
+        let metrics = TableMetrics {
+            table,
+            rows: count,
+            skipped: false,
+            changed: 0,
+            deleted: 0,
+            elapsed: start.elapsed(),
+        };
+        Ok((metrics, stats))
     }
-}
 
-impl ToDB<PgInterval> for u32 {
-    fn to_db(self) -> Result<PgInterval> {
-        PgInterval::try_from(Duration::from_secs(self.into()))
-            .map_err(|_| anyhow!("Failed to parse PgInterval"))
+    /// Logs and saves how far through a table is, and how long it's got to go.
+    async fn report(
+        &self,
+        table: &str,
+        rows: u64,
+        total: Option<u64>,
+        start: Instant,
+    ) -> Result<(), sqlx::Error> {
+        let rows_per_sec = rows as f64 / start.elapsed().as_secs_f64().max(f64::EPSILON);
+        let eta = total
+            .map(|total| Duration::from_secs_f64(total.saturating_sub(rows) as f64 / rows_per_sec));
+        Span::current().record("rows", rows);
+        info!(
+            rows,
+            total,
+            rows_per_sec = rows_per_sec as u64,
+            eta = ?eta,
+            "Loading static GTFS table"
+        );
+        queries::update_import_progress(
+            self.feed_region,
+            table,
+            rows as i64,
+            total.map(|t| t as i64),
+            &self.db.0,
+        )
+        .await
     }
 }
 
-impl ToDB<i32> for gtfs_structures::PickupDropOffType {
-    fn to_db(self) -> Result<i32> {
-        Ok(match self {
-            gtfs_structures::PickupDropOffType::Regular => 0,
-            gtfs_structures::PickupDropOffType::NotAvailable => 1,
-            gtfs_structures::PickupDropOffType::ArrangeByPhone => 2,
-            gtfs_structures::PickupDropOffType::CoordinateWithDriver => 3,
-            gtfs_structures::PickupDropOffType::Unknown(i) => i,
+impl GtfsDbModel {
+    /// Stages every table at once, each on its own connection, then merges them all
+    /// into the live tables in a single transaction, so readers only ever see a whole
+    /// feed. Tables are merged parents first, so everything a row references is already
+    /// there, and deleted from children first, so there's nothing left to cascade to.
+    ///
+    /// Every table is staged even if one fails, so an interrupted import leaves as much
+    /// behind as it can. The live tables and last update stay as they were, and the
+    /// next poll tries again, skipping the tables already staged from the same files.
+    #[instrument(skip_all, fields(?mode))]
+    pub async fn insert_db(
+        self,
+        db: &db::Db,
+        last_update: &LastUpdate,
+        mode: ImportMode,
+    ) -> Result<ImportMetrics> {
+        let start = Instant::now();
+        let feed_region = last_update.feed_region.as_str();
+        let completed = queries::get_import_progress(feed_region, &db.0)
+            .await?
+            .into_iter()
+            .filter(|p| p.completed_at.is_some())
+            .filter_map(|p| Some((p.table_name, p.checksum?)))
+            .collect();
+        let import = Import {
+            db,
+            feed_region,
+            completed,
+        };
+
+        let staged = tokio::join!(
+            import.stage_table("agency", self.agencies),
+            import.stage_table("stops", self.stops),
+            import.stage_table("routes", self.routes),
+            import.stage_table("trips", self.trips),
+            import.stage_table("stop_times", self.stop_times),
+            import.stage_table("calendar", self.calendar),
+            import.stage_table("calendar_dates", self.calendar_dates),
+            import.stage_table("shapes", self.shapes),
+            import.stage_table("feed_info", self.feed_info),
+        );
+        let mut metrics = ImportMetrics::default();
+        for staged in <[Result<Loaded>; STAGED_TABLES as usize]>::from(staged) {
+            let (table, stats) = staged?;
+            metrics.tables.push(table);
+            metrics.files.extend(stats);
         }
-        .into())
+
+        let mut tx = db.0.begin().await?;
+        metrics.table("agency").changed = Agency::merge_staged(&mut tx).await?;
+        metrics.table("stops").changed = Stop::merge_staged(&mut tx).await?;
+        metrics.table("routes").changed = Route::merge_staged(&mut tx).await?;
+        metrics.table("trips").changed = Trip::merge_staged(&mut tx).await?;
+        metrics.table("stop_times").changed = StopTime::merge_staged(&mut tx).await?;
+        metrics.table("calendar").changed = Calendar::merge_staged(&mut tx).await?;
+        metrics.table("calendar_dates").changed = CalendarDate::merge_staged(&mut tx).await?;
+        metrics.table("shapes").changed = Shape::merge_staged(&mut tx).await?;
+        metrics.table("feed_info").changed = FeedInfo::merge_staged(&mut tx).await?;
+        if mode == ImportMode::Incremental {
+            metrics.table("stop_times").deleted = StopTime::delete_unstaged(&mut tx).await?;
+            metrics.table("trips").deleted = Trip::delete_unstaged(&mut tx).await?;
+            metrics.table("routes").deleted = Route::delete_unstaged(&mut tx).await?;
+            metrics.table("stops").deleted = Stop::delete_unstaged(&mut tx).await?;
+            metrics.table("agency").deleted = Agency::delete_unstaged(&mut tx).await?;
+            metrics.table("calendar").deleted = Calendar::delete_unstaged(&mut tx).await?;
+            metrics.table("calendar_dates").deleted =
+                CalendarDate::delete_unstaged(&mut tx).await?;
+            metrics.table("shapes").deleted = Shape::delete_unstaged(&mut tx).await?;
+            metrics.table("feed_info").deleted = FeedInfo::delete_unstaged(&mut tx).await?;
+        }
+        Agency::clear_staged(&mut tx).await?;
+        Stop::clear_staged(&mut tx).await?;
+        Route::clear_staged(&mut tx).await?;
+        Trip::clear_staged(&mut tx).await?;
+        StopTime::clear_staged(&mut tx).await?;
+        Calendar::clear_staged(&mut tx).await?;
+        CalendarDate::clear_staged(&mut tx).await?;
+        Shape::clear_staged(&mut tx).await?;
+        FeedInfo::clear_staged(&mut tx).await?;
+        last_update.insert(&mut tx).await?;
+        queries::clear_import_progress(feed_region, &mut tx).await?;
+        tx.commit().await?;
+
+        metrics.elapsed = start.elapsed();
+        metrics.peak_rss_bytes = peak_rss_bytes();
+        metrics.log();
+        Ok(metrics)
     }
-}
 
-impl ToDB<i32> for gtfs_structures::Exception {
-    fn to_db(self) -> Result<i32> {
-        Ok(match self {
-            gtfs_structures::Exception::Added => 1,
-            gtfs_structures::Exception::Deleted => 2,
+    pub async fn collect(self) -> Result<FeedRows> {
+        Ok(FeedRows {
+            agencies: self.agencies.collect().await?,
+            stops: self.stops.collect().await?,
+            routes: self.routes.collect().await?,
+            trips: self.trips.collect().await?,
+            stop_times: self.stop_times.collect().await?,
+            calendar: self.calendar.collect().await?,
+            calendar_dates: self.calendar_dates.collect().await?,
+            shapes: self.shapes.collect().await?,
+            feed_info: self.feed_info.collect().await?,
         })
     }
 }
 
-impl ToDB<db::types::Trip> for gtfs_structures::RawTrip {
+impl FeedRows {
+    /// Inserts every row the same way as the import.
+    pub async fn insert_db(
+        self,
+        db: &db::Db,
+        last_update: &LastUpdate,
+        mode: ImportMode,
+    ) -> Result<ImportMetrics> {
+        GtfsDbModel::from(self)
+            .insert_db(db, last_update, mode)
+            .await
+    }
+}
+
+impl StaticGtfs {
+    pub async fn insert_db(self, db: db::Db, mode: ImportMode) -> Result<ImportMetrics> {
+        self.parse().insert_db(&db, &self.last_update, mode).await
+    }
+}
+
+pub trait ToDB<T>: Send {
+    fn to_db(self) -> Result<T>;
+}
+
+/// GTFS times are HH:MM:SS, going past 24 hours for trips that run after midnight.
+fn parse_time(time: &str) -> Result<PgInterval> {
+    let mut parts = time.splitn(3, ':').map(|p| p.parse::<u64>());
+    let (Some(Ok(h)), Some(Ok(m)), Some(Ok(s))) = (parts.next(), parts.next(), parts.next()) else {
+        bail!("Invalid time {time}");
+    };
+    PgInterval::try_from(Duration::from_secs(h * 3600 + m * 60 + s))
+        .map_err(|_| anyhow!("Failed to parse PgInterval"))
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    Ok(NaiveDate::parse_from_str(date, "%Y%m%d")?)
+}
+
+// Just a ton of to_db implementations beyond this point:
+
+impl ToDB<db::types::Trip> for static_gtfs::Trip {
     fn to_db(self) -> Result<db::types::Trip> {
         Ok(db::types::Trip {
-            trip_id: self.id,
+            trip_id: self.trip_id,
             service_id: self.service_id,
             route_id: self.route_id,
             trip_headsign: self.trip_headsign,
-            direction_id: self.direction_id.map(|d| d.to_db()).transpose()?,
+            direction_id: self.direction_id.map(|d| d == 1),
             block_id: self.block_id,
             shape_id: self.shape_id,
         })
     }
 }
 
-impl ToDB<db::types::StopTime> for gtfs_structures::RawStopTime {
+impl ToDB<db::types::StopTime> for static_gtfs::StopTime {
     fn to_db(self) -> Result<db::types::StopTime> {
         Ok(db::types::StopTime {
             trip_id: self.trip_id,
-            arrival_time: self.arrival_time.map(|t| t.to_db()).transpose()?,
-            departure_time: self
-                .departure_time
-                .context("Missing departure time")?
-                .to_db()?,
+            arrival_time: self.arrival_time.as_deref().map(parse_time).transpose()?,
+            departure_time: parse_time(
+                self.departure_time
+                    .as_deref()
+                    .context("Missing departure time")?,
+            )?,
             stop_id: self.stop_id,
-            stop_sequence: self.stop_sequence.try_into()?,
-            pickup_type: self.pickup_type.to_db()?,
-            drop_off_type: self.drop_off_type.to_db()?,
+            stop_sequence: self.stop_sequence,
+            pickup_type: self.pickup_type.unwrap_or(0),
+            drop_off_type: self.drop_off_type.unwrap_or(0),
         })
     }
 }
 
-impl ToDB<db::types::Agency> for gtfs_structures::Agency {
+impl ToDB<db::types::Agency> for static_gtfs::Agency {
     fn to_db(self) -> Result<db::types::Agency> {
         Ok(db::types::Agency {
-            agency_name: self.name,
-            agency_url: self.url,
-            agency_timezone: self.timezone,
-            agency_lang: self.lang,
-            agency_phone: self.phone,
+            agency_id: self.agency_id,
+            agency_name: self.agency_name,
+            agency_url: self.agency_url,
+            agency_timezone: self.agency_timezone,
+            agency_lang: self.agency_lang,
+            agency_phone: self.agency_phone,
         })
     }
 }
 
-impl ToDB<db::types::Stop> for gtfs_structures::Stop {
+impl ToDB<db::types::Stop> for static_gtfs::Stop {
     fn to_db(self) -> Result<db::types::Stop> {
         Ok(db::types::Stop {
-            stop_id: self.id,
-            stop_code: self.code,
-            stop_name: self.name,
-            stop_desc: self.description,
-            stop_lat: self.latitude,
-            stop_lon: self.longitude,
+            stop_id: self.stop_id,
+            stop_code: self.stop_code,
+            stop_name: self.stop_name,
+            stop_desc: self.stop_desc,
+            stop_lat: self.stop_lat,
+            stop_lon: self.stop_lon,
             zone_id: self.zone_id,
-            stop_url: self.url,
-            location_type: Some(self.location_type.to_db()?),
+            stop_url: self.stop_url,
+            location_type: Some(self.location_type.unwrap_or(0)),
             parent_station: self.parent_station,
             platform_code: self.platform_code,
         })
     }
 }
 
-impl ToDB<db::types::Route> for gtfs_structures::Route {
+impl ToDB<db::types::Route> for static_gtfs::Route {
     fn to_db(self) -> Result<db::types::Route> {
+        // Colours default to white with black text, per the spec.
+        let color = |color: Option<String>, default: &str| {
+            Some(color.map_or(default.to_owned(), |c| c.to_uppercase()))
+        };
         Ok(db::types::Route {
-            route_id: self.id,
-            route_short_name: self.short_name,
-            route_long_name: self.long_name,
-            route_desc: self.desc,
-            route_type: self.route_type.to_db()?,
-            route_url: self.url,
-            route_color: Some(format!(
-                "{:02X}{:02X}{:02X}",
-                self.color.r, self.color.g, self.color.b
-            )),
-            route_text_color: Some(format!(
-                "{:02X}{:02X}{:02X}",
-                self.text_color.r, self.text_color.g, self.text_color.b
-            )),
+            route_id: self.route_id,
+            agency_id: self.agency_id,
+            route_short_name: self.route_short_name,
+            route_long_name: self.route_long_name,
+            route_desc: self.route_desc,
+            route_type: self.route_type,
+            route_url: self.route_url,
+            route_color: color(self.route_color, "FFFFFF"),
+            route_text_color: color(self.route_text_color, "000000"),
         })
     }
 }
 
-impl ToDB<db::types::Calendar> for gtfs_structures::Calendar {
+impl ToDB<db::types::Calendar> for static_gtfs::Calendar {
     fn to_db(self) -> Result<db::types::Calendar> {
         Ok(db::types::Calendar {
-            service_id: self.id,
-            monday: self.monday,
-            tuesday: self.tuesday,
-            wednesday: self.wednesday,
-            thursday: self.thursday,
-            friday: self.friday,
-            saturday: self.saturday,
-            sunday: self.sunday,
-            start_date: self.start_date,
-            end_date: self.end_date,
+            service_id: self.service_id,
+            monday: self.monday == 1,
+            tuesday: self.tuesday == 1,
+            wednesday: self.wednesday == 1,
+            thursday: self.thursday == 1,
+            friday: self.friday == 1,
+            saturday: self.saturday == 1,
+            sunday: self.sunday == 1,
+            start_date: parse_date(&self.start_date)?,
+            end_date: parse_date(&self.end_date)?,
         })
     }
 }
 
-impl ToDB<db::types::CalendarDate> for gtfs_structures::CalendarDate {
+impl ToDB<db::types::CalendarDate> for static_gtfs::CalendarDate {
     fn to_db(self) -> Result<db::types::CalendarDate> {
         Ok(db::types::CalendarDate {
             service_id: self.service_id,
-            date: self.date,
-            exception_type: self.exception_type.to_db()?,
+            date: parse_date(&self.date)?,
+            exception_type: self.exception_type,
         })
     }
 }
 
-impl ToDB<db::types::Shape> for gtfs_structures::Shape {
+impl ToDB<db::types::Shape> for static_gtfs::Shape {
     fn to_db(self) -> Result<db::types::Shape> {
         Ok(db::types::Shape {
-            shape_id: self.id,
-            shape_pt_lat: self.latitude,
-            shape_pt_lon: self.longitude,
-            shape_pt_sequence: self.sequence.try_into()?,
+            shape_id: self.shape_id,
+            shape_pt_lat: self.shape_pt_lat,
+            shape_pt_lon: self.shape_pt_lon,
+            shape_pt_sequence: self.shape_pt_sequence,
         })
     }
 }
 
-impl ToDB<db::types::FeedInfo> for gtfs_structures::FeedInfo {
+impl ToDB<db::types::FeedInfo> for static_gtfs::FeedInfo {
     fn to_db(self) -> Result<db::types::FeedInfo> {
         Ok(db::types::FeedInfo {
-            feed_publisher_name: self.name,
-            feed_publisher_url: self.url,
-            feed_lang: Some(self.lang),
-            feed_start_date: self.start_date,
-            feed_end_date: self.end_date,
+            feed_publisher_name: self.feed_publisher_name,
+            feed_publisher_url: self.feed_publisher_url,
+            feed_lang: self.feed_lang,
+            feed_start_date: self
+                .feed_start_date
+                .as_deref()
+                .map(parse_date)
+                .transpose()?,
+            feed_end_date: self.feed_end_date.as_deref().map(parse_date).transpose()?,
         })
     }
 }
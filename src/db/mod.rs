@@ -4,10 +4,12 @@
 //! which abstracts away all the dirty Db operations.
 
 pub mod queries;
+#[cfg(test)]
 mod tests;
 pub mod types;
 
 use crate::{
+    bridge::static_bridge::STAGED_TABLES,
     db::{
         queries::{
             insert_agency, insert_calendar, insert_calendar_date, insert_feed_info, insert_route,
@@ -18,8 +20,9 @@ use crate::{
     vars,
 };
 use anyhow::Result;
-use sqlx::PgPool;
-use tracing::{info, instrument};
+use chrono_tz::Tz;
+use sqlx::{PgPool, migrate::Migrate, postgres::PgPoolOptions};
+use tracing::{info, instrument, warn};
 
 pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
 
@@ -29,11 +32,16 @@ pub struct Db(pub PgPool);
 
 impl Db {
     /// Attempt to connect to the database, using DATABASE_URL.
+    /// An import holds a connection per table while it stages them, so the pool
+    /// always has room for that with DB_SPARE_CONNECTIONS left over for everything else.
     #[instrument]
     pub async fn connect() -> Result<Db> {
         info!("Attempting to connect to db");
         let db_url = vars::db_url();
-        let pool = PgPool::connect(&db_url).await?;
+        let pool = PgPoolOptions::new()
+            .max_connections(STAGED_TABLES + vars::db_spare_connections())
+            .connect(&db_url)
+            .await?;
         info!("Connected to db");
         Ok(Db(pool))
     }
@@ -46,4 +54,23 @@ impl Db {
         info!("Db migrations complete");
         Ok(())
     }
+
+    /// The timezone the feed's times are in, from agency_timezone.
+    /// Service days and "now" should always be in this zone, never the server's.
+    pub async fn timezone(&self) -> Result<Tz> {
+        let Some(timezone) = queries::get_agency_timezone(&self.0).await? else {
+            return Ok(vars::DEFAULT_TIMEZONE);
+        };
+        Ok(timezone.parse().unwrap_or_else(|_| {
+            warn!(timezone, "Unknown agency_timezone, using the default");
+            vars::DEFAULT_TIMEZONE
+        }))
+    }
+
+    /// Versions of every migration that has been applied, without applying any.
+    pub async fn applied_migrations(&self) -> Result<Vec<i64>> {
+        let mut conn = self.0.acquire().await?;
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.into_iter().map(|m| m.version).collect())
+    }
 }
@@ -6,15 +6,25 @@
 use std::path::Path;
 
 use super::types::*;
+use crate::tiles::geometry::BBox;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use futures::stream::BoxStream;
 use sqlx::{PgConnection, PgPool, Postgres, Transaction};
 
-pub async fn insert_agency(agency: &Agency, pool: &mut PgConnection) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_agency(agency: &Agency, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
-        INSERT INTO agency (agency_name, agency_url, agency_timezone, agency_lang, agency_phone)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO agency (agency_id, agency_name, agency_url, agency_timezone, agency_lang, agency_phone)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (agency_name) DO UPDATE SET
+            agency_id = EXCLUDED.agency_id,
+            agency_url = EXCLUDED.agency_url,
+            agency_timezone = EXCLUDED.agency_timezone,
+            agency_lang = EXCLUDED.agency_lang,
+            agency_phone = EXCLUDED.agency_phone
+        WHERE agency IS DISTINCT FROM EXCLUDED
         "#,
+        agency.agency_id,
         agency.agency_name,
         agency.agency_url,
         agency.agency_timezone,
@@ -23,17 +33,29 @@ pub async fn insert_agency(agency: &Agency, pool: &mut PgConnection) -> Result<(
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn insert_stop(stop: &Stop, pool: &mut PgConnection) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_stop(stop: &Stop, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO stops (
             stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon,
             zone_id, stop_url, location_type, parent_station, platform_code
         )
         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
+        ON CONFLICT (stop_id) DO UPDATE SET
+            stop_code = EXCLUDED.stop_code,
+            stop_name = EXCLUDED.stop_name,
+            stop_desc = EXCLUDED.stop_desc,
+            stop_lat = EXCLUDED.stop_lat,
+            stop_lon = EXCLUDED.stop_lon,
+            zone_id = EXCLUDED.zone_id,
+            stop_url = EXCLUDED.stop_url,
+            location_type = EXCLUDED.location_type,
+            parent_station = EXCLUDED.parent_station,
+            platform_code = EXCLUDED.platform_code
+        WHERE stops IS DISTINCT FROM EXCLUDED
         "#,
         stop.stop_id,
         stop.stop_code,
@@ -49,19 +71,30 @@ pub async fn insert_stop(stop: &Stop, pool: &mut PgConnection) -> Result<(), sql
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn insert_route(route: &Route, pool: &mut PgConnection) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_route(route: &Route, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO routes (
-            route_id, route_short_name, route_long_name, route_desc, route_type,
+            route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
             route_url, route_color, route_text_color
         )
-        VALUES ($1,$2,$3,$4,$5,$6,$7,$8)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)
+        ON CONFLICT (route_id) DO UPDATE SET
+            agency_id = EXCLUDED.agency_id,
+            route_short_name = EXCLUDED.route_short_name,
+            route_long_name = EXCLUDED.route_long_name,
+            route_desc = EXCLUDED.route_desc,
+            route_type = EXCLUDED.route_type,
+            route_url = EXCLUDED.route_url,
+            route_color = EXCLUDED.route_color,
+            route_text_color = EXCLUDED.route_text_color
+        WHERE routes IS DISTINCT FROM EXCLUDED
         "#,
         route.route_id,
+        route.agency_id,
         route.route_short_name,
         route.route_long_name,
         route.route_desc,
@@ -72,17 +105,25 @@ pub async fn insert_route(route: &Route, pool: &mut PgConnection) -> Result<(),
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn insert_trip(trip: &Trip, pool: &mut PgConnection) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_trip(trip: &Trip, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO trips (
             route_id, service_id, trip_id, trip_headsign,
             direction_id, block_id, shape_id
         )
         VALUES ($1,$2,$3,$4,$5,$6,$7)
+        ON CONFLICT (trip_id) DO UPDATE SET
+            route_id = EXCLUDED.route_id,
+            service_id = EXCLUDED.service_id,
+            trip_headsign = EXCLUDED.trip_headsign,
+            direction_id = EXCLUDED.direction_id,
+            block_id = EXCLUDED.block_id,
+            shape_id = EXCLUDED.shape_id
+        WHERE trips IS DISTINCT FROM EXCLUDED
         "#,
         trip.route_id,
         trip.service_id,
@@ -94,20 +135,27 @@ pub async fn insert_trip(trip: &Trip, pool: &mut PgConnection) -> Result<(), sql
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
 pub async fn insert_stop_time(
     stop_time: &StopTime,
     pool: &mut PgConnection,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO stop_times (
             trip_id, arrival_time, departure_time, stop_id,
             stop_sequence, pickup_type, drop_off_type
         )
         VALUES ($1,$2,$3,$4,$5,$6,$7)
+        ON CONFLICT (trip_id, stop_sequence) DO UPDATE SET
+            arrival_time = EXCLUDED.arrival_time,
+            departure_time = EXCLUDED.departure_time,
+            stop_id = EXCLUDED.stop_id,
+            pickup_type = EXCLUDED.pickup_type,
+            drop_off_type = EXCLUDED.drop_off_type
+        WHERE stop_times IS DISTINCT FROM EXCLUDED
         "#,
         stop_time.trip_id,
         stop_time.arrival_time,
@@ -119,20 +167,31 @@ pub async fn insert_stop_time(
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
 pub async fn insert_calendar(
     calendar: &Calendar,
     pool: &mut PgConnection,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO calendar (
             service_id, monday, tuesday, wednesday, thursday,
             friday, saturday, sunday, start_date, end_date
         )
         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10)
+        ON CONFLICT (service_id) DO UPDATE SET
+            monday = EXCLUDED.monday,
+            tuesday = EXCLUDED.tuesday,
+            wednesday = EXCLUDED.wednesday,
+            thursday = EXCLUDED.thursday,
+            friday = EXCLUDED.friday,
+            saturday = EXCLUDED.saturday,
+            sunday = EXCLUDED.sunday,
+            start_date = EXCLUDED.start_date,
+            end_date = EXCLUDED.end_date
+        WHERE calendar IS DISTINCT FROM EXCLUDED
         "#,
         calendar.service_id,
         calendar.monday,
@@ -147,19 +206,22 @@ pub async fn insert_calendar(
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
 pub async fn insert_calendar_date(
     cd: &CalendarDate,
     pool: &mut PgConnection,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO calendar_dates (
             service_id, date, exception_type
         )
         VALUES ($1,$2,$3)
+        ON CONFLICT (service_id, date) DO UPDATE SET
+            exception_type = EXCLUDED.exception_type
+        WHERE calendar_dates IS DISTINCT FROM EXCLUDED
         "#,
         cd.service_id,
         cd.date,
@@ -167,16 +229,20 @@ pub async fn insert_calendar_date(
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn insert_shape(shape: &Shape, pool: &mut PgConnection) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_shape(shape: &Shape, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO shapes (
             shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence
         )
         VALUES ($1,$2,$3,$4)
+        ON CONFLICT (shape_id, shape_pt_sequence) DO UPDATE SET
+            shape_pt_lat = EXCLUDED.shape_pt_lat,
+            shape_pt_lon = EXCLUDED.shape_pt_lon
+        WHERE shapes IS DISTINCT FROM EXCLUDED
         "#,
         shape.shape_id,
         shape.shape_pt_lat,
@@ -185,17 +251,26 @@ pub async fn insert_shape(shape: &Shape, pool: &mut PgConnection) -> Result<(),
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn insert_feed_info(feed: &FeedInfo, pool: &mut PgConnection) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_feed_info(
+    feed: &FeedInfo,
+    pool: &mut PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO feed_info (
             feed_publisher_name, feed_publisher_url,
             feed_lang, feed_start_date, feed_end_date
         )
         VALUES ($1,$2,$3,$4,$5)
+        ON CONFLICT (feed_publisher_name) DO UPDATE SET
+            feed_publisher_url = EXCLUDED.feed_publisher_url,
+            feed_lang = EXCLUDED.feed_lang,
+            feed_start_date = EXCLUDED.feed_start_date,
+            feed_end_date = EXCLUDED.feed_end_date
+        WHERE feed_info IS DISTINCT FROM EXCLUDED
         "#,
         feed.feed_publisher_name,
         feed.feed_publisher_url,
@@ -205,43 +280,1800 @@ pub async fn insert_feed_info(feed: &FeedInfo, pool: &mut PgConnection) -> Resul
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
 pub async fn insert_last_update(
     last_update: &LastUpdate,
     pool: &mut PgConnection,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
         INSERT INTO last_update (
             feed_region, feed_last_update
         )
         VALUES ($1,$2)
+        ON CONFLICT (feed_region) DO UPDATE SET
+            feed_last_update = EXCLUDED.feed_last_update
+        WHERE last_update IS DISTINCT FROM EXCLUDED
         "#,
         last_update.feed_region,
         last_update.feed_last_update
     )
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn get_feed_last_update(
-    feed_region: String,
-    pool: &PgPool,
-) -> Result<Option<NaiveDateTime>, sqlx::Error> {
-    let row = sqlx::query!(
+// The staging queries load each table of a new feed into its *_staging table, so the
+// tables can load side by side without anyone seeing a half loaded feed. The merge_staged_*
+// and delete_unstaged_* queries then bring the live tables in line with them, all in the
+// one transaction.
+
+pub async fn stage_agency(agency: &Agency, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
-        SELECT feed_last_update
-        FROM last_update
-        WHERE feed_region = $1
+        INSERT INTO agency_staging (
+            agency_id, agency_name, agency_url, agency_timezone, agency_lang, agency_phone
+        )
+        VALUES ($1,$2,$3,$4,$5,$6)
+        ON CONFLICT (agency_name) DO UPDATE SET
+            agency_id = EXCLUDED.agency_id,
+            agency_url = EXCLUDED.agency_url,
+            agency_timezone = EXCLUDED.agency_timezone,
+            agency_lang = EXCLUDED.agency_lang,
+            agency_phone = EXCLUDED.agency_phone
         "#,
-        feed_region
+        agency.agency_id,
+        agency.agency_name,
+        agency.agency_url,
+        agency.agency_timezone,
+        agency.agency_lang,
+        agency.agency_phone
     )
-    .fetch_optional(pool)
+    .execute(pool)
     .await?;
+    Ok(result.rows_affected())
+}
 
-    // Default to 1970-01-01 00:00:00 (the "zero" NaiveDateTime)
-    Ok(row.map(|r| r.feed_last_update))
+pub async fn stage_stop(stop: &Stop, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO stops_staging (
+            stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon, zone_id, stop_url,
+            location_type, parent_station, platform_code
+        )
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
+        ON CONFLICT (stop_id) DO UPDATE SET
+            stop_code = EXCLUDED.stop_code,
+            stop_name = EXCLUDED.stop_name,
+            stop_desc = EXCLUDED.stop_desc,
+            stop_lat = EXCLUDED.stop_lat,
+            stop_lon = EXCLUDED.stop_lon,
+            zone_id = EXCLUDED.zone_id,
+            stop_url = EXCLUDED.stop_url,
+            location_type = EXCLUDED.location_type,
+            parent_station = EXCLUDED.parent_station,
+            platform_code = EXCLUDED.platform_code
+        "#,
+        stop.stop_id,
+        stop.stop_code,
+        stop.stop_name,
+        stop.stop_desc,
+        stop.stop_lat,
+        stop.stop_lon,
+        stop.zone_id,
+        stop.stop_url,
+        stop.location_type,
+        stop.parent_station,
+        stop.platform_code
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_route(route: &Route, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO routes_staging (
+            route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
+            route_url, route_color, route_text_color
+        )
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)
+        ON CONFLICT (route_id) DO UPDATE SET
+            agency_id = EXCLUDED.agency_id,
+            route_short_name = EXCLUDED.route_short_name,
+            route_long_name = EXCLUDED.route_long_name,
+            route_desc = EXCLUDED.route_desc,
+            route_type = EXCLUDED.route_type,
+            route_url = EXCLUDED.route_url,
+            route_color = EXCLUDED.route_color,
+            route_text_color = EXCLUDED.route_text_color
+        "#,
+        route.route_id,
+        route.agency_id,
+        route.route_short_name,
+        route.route_long_name,
+        route.route_desc,
+        route.route_type,
+        route.route_url,
+        route.route_color,
+        route.route_text_color
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_trip(trip: &Trip, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO trips_staging (
+            route_id, service_id, trip_id, trip_headsign, direction_id, block_id, shape_id
+        )
+        VALUES ($1,$2,$3,$4,$5,$6,$7)
+        ON CONFLICT (trip_id) DO UPDATE SET
+            route_id = EXCLUDED.route_id,
+            service_id = EXCLUDED.service_id,
+            trip_headsign = EXCLUDED.trip_headsign,
+            direction_id = EXCLUDED.direction_id,
+            block_id = EXCLUDED.block_id,
+            shape_id = EXCLUDED.shape_id
+        "#,
+        trip.route_id,
+        trip.service_id,
+        trip.trip_id,
+        trip.trip_headsign,
+        trip.direction_id,
+        trip.block_id,
+        trip.shape_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_stop_time(
+    stop_time: &StopTime,
+    pool: &mut PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO stop_times_staging (
+            trip_id, arrival_time, departure_time, stop_id, stop_sequence, pickup_type,
+            drop_off_type
+        )
+        VALUES ($1,$2,$3,$4,$5,$6,$7)
+        ON CONFLICT (trip_id, stop_sequence) DO UPDATE SET
+            arrival_time = EXCLUDED.arrival_time,
+            departure_time = EXCLUDED.departure_time,
+            stop_id = EXCLUDED.stop_id,
+            pickup_type = EXCLUDED.pickup_type,
+            drop_off_type = EXCLUDED.drop_off_type
+        "#,
+        stop_time.trip_id,
+        stop_time.arrival_time,
+        stop_time.departure_time,
+        stop_time.stop_id,
+        stop_time.stop_sequence,
+        stop_time.pickup_type,
+        stop_time.drop_off_type
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_calendar(
+    calendar: &Calendar,
+    pool: &mut PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO calendar_staging (
+            service_id, monday, tuesday, wednesday, thursday, friday, saturday, sunday,
+            start_date, end_date
+        )
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10)
+        ON CONFLICT (service_id) DO UPDATE SET
+            monday = EXCLUDED.monday,
+            tuesday = EXCLUDED.tuesday,
+            wednesday = EXCLUDED.wednesday,
+            thursday = EXCLUDED.thursday,
+            friday = EXCLUDED.friday,
+            saturday = EXCLUDED.saturday,
+            sunday = EXCLUDED.sunday,
+            start_date = EXCLUDED.start_date,
+            end_date = EXCLUDED.end_date
+        "#,
+        calendar.service_id,
+        calendar.monday,
+        calendar.tuesday,
+        calendar.wednesday,
+        calendar.thursday,
+        calendar.friday,
+        calendar.saturday,
+        calendar.sunday,
+        calendar.start_date,
+        calendar.end_date
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_calendar_date(
+    cd: &CalendarDate,
+    pool: &mut PgConnection,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO calendar_dates_staging (
+            service_id, date, exception_type
+        )
+        VALUES ($1,$2,$3)
+        ON CONFLICT (service_id, date) DO UPDATE SET
+            exception_type = EXCLUDED.exception_type
+        "#,
+        cd.service_id,
+        cd.date,
+        cd.exception_type
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_shape(shape: &Shape, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO shapes_staging (
+            shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence
+        )
+        VALUES ($1,$2,$3,$4)
+        ON CONFLICT (shape_id, shape_pt_sequence) DO UPDATE SET
+            shape_pt_lat = EXCLUDED.shape_pt_lat,
+            shape_pt_lon = EXCLUDED.shape_pt_lon
+        "#,
+        shape.shape_id,
+        shape.shape_pt_lat,
+        shape.shape_pt_lon,
+        shape.shape_pt_sequence
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn stage_feed_info(feed: &FeedInfo, pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO feed_info_staging (
+            feed_publisher_name, feed_publisher_url, feed_lang, feed_start_date,
+            feed_end_date
+        )
+        VALUES ($1,$2,$3,$4,$5)
+        ON CONFLICT (feed_publisher_name) DO UPDATE SET
+            feed_publisher_url = EXCLUDED.feed_publisher_url,
+            feed_lang = EXCLUDED.feed_lang,
+            feed_start_date = EXCLUDED.feed_start_date,
+            feed_end_date = EXCLUDED.feed_end_date
+        "#,
+        feed.feed_publisher_name,
+        feed.feed_publisher_url,
+        feed.feed_lang,
+        feed.feed_start_date,
+        feed.feed_end_date
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn clear_staged_agencies(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE agency_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_staged_stops(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE stops_staging").execute(pool).await?;
+    Ok(())
+}
+
+pub async fn clear_staged_routes(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE routes_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_staged_trips(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE trips_staging").execute(pool).await?;
+    Ok(())
+}
+
+pub async fn clear_staged_stop_times(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE stop_times_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_staged_calendars(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE calendar_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_staged_calendar_dates(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE calendar_dates_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_staged_shapes(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE shapes_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_staged_feed_info(pool: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("TRUNCATE feed_info_staging")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn merge_staged_agencies(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO agency (
+            agency_id, agency_name, agency_url, agency_timezone, agency_lang, agency_phone
+        )
+        SELECT
+            agency_id, agency_name, agency_url, agency_timezone, agency_lang, agency_phone
+        FROM agency_staging
+        ON CONFLICT (agency_name) DO UPDATE SET
+            agency_id = EXCLUDED.agency_id,
+            agency_url = EXCLUDED.agency_url,
+            agency_timezone = EXCLUDED.agency_timezone,
+            agency_lang = EXCLUDED.agency_lang,
+            agency_phone = EXCLUDED.agency_phone
+        WHERE agency IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_stops(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO stops (
+            stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon, zone_id, stop_url,
+            location_type, parent_station, platform_code
+        )
+        SELECT
+            stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon, zone_id, stop_url,
+            location_type, parent_station, platform_code
+        FROM stops_staging
+        ON CONFLICT (stop_id) DO UPDATE SET
+            stop_code = EXCLUDED.stop_code,
+            stop_name = EXCLUDED.stop_name,
+            stop_desc = EXCLUDED.stop_desc,
+            stop_lat = EXCLUDED.stop_lat,
+            stop_lon = EXCLUDED.stop_lon,
+            zone_id = EXCLUDED.zone_id,
+            stop_url = EXCLUDED.stop_url,
+            location_type = EXCLUDED.location_type,
+            parent_station = EXCLUDED.parent_station,
+            platform_code = EXCLUDED.platform_code
+        WHERE stops IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_routes(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO routes (
+            route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
+            route_url, route_color, route_text_color
+        )
+        SELECT
+            route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
+            route_url, route_color, route_text_color
+        FROM routes_staging
+        ON CONFLICT (route_id) DO UPDATE SET
+            agency_id = EXCLUDED.agency_id,
+            route_short_name = EXCLUDED.route_short_name,
+            route_long_name = EXCLUDED.route_long_name,
+            route_desc = EXCLUDED.route_desc,
+            route_type = EXCLUDED.route_type,
+            route_url = EXCLUDED.route_url,
+            route_color = EXCLUDED.route_color,
+            route_text_color = EXCLUDED.route_text_color
+        WHERE routes IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_trips(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO trips (
+            route_id, service_id, trip_id, trip_headsign, direction_id, block_id, shape_id
+        )
+        SELECT
+            route_id, service_id, trip_id, trip_headsign, direction_id, block_id, shape_id
+        FROM trips_staging
+        ON CONFLICT (trip_id) DO UPDATE SET
+            route_id = EXCLUDED.route_id,
+            service_id = EXCLUDED.service_id,
+            trip_headsign = EXCLUDED.trip_headsign,
+            direction_id = EXCLUDED.direction_id,
+            block_id = EXCLUDED.block_id,
+            shape_id = EXCLUDED.shape_id
+        WHERE trips IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_stop_times(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO stop_times (
+            trip_id, arrival_time, departure_time, stop_id, stop_sequence, pickup_type,
+            drop_off_type
+        )
+        SELECT
+            trip_id, arrival_time, departure_time, stop_id, stop_sequence, pickup_type,
+            drop_off_type
+        FROM stop_times_staging
+        ON CONFLICT (trip_id, stop_sequence) DO UPDATE SET
+            arrival_time = EXCLUDED.arrival_time,
+            departure_time = EXCLUDED.departure_time,
+            stop_id = EXCLUDED.stop_id,
+            pickup_type = EXCLUDED.pickup_type,
+            drop_off_type = EXCLUDED.drop_off_type
+        WHERE stop_times IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_calendars(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO calendar (
+            service_id, monday, tuesday, wednesday, thursday, friday, saturday, sunday,
+            start_date, end_date
+        )
+        SELECT
+            service_id, monday, tuesday, wednesday, thursday, friday, saturday, sunday,
+            start_date, end_date
+        FROM calendar_staging
+        ON CONFLICT (service_id) DO UPDATE SET
+            monday = EXCLUDED.monday,
+            tuesday = EXCLUDED.tuesday,
+            wednesday = EXCLUDED.wednesday,
+            thursday = EXCLUDED.thursday,
+            friday = EXCLUDED.friday,
+            saturday = EXCLUDED.saturday,
+            sunday = EXCLUDED.sunday,
+            start_date = EXCLUDED.start_date,
+            end_date = EXCLUDED.end_date
+        WHERE calendar IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_calendar_dates(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO calendar_dates (
+            service_id, date, exception_type
+        )
+        SELECT
+            service_id, date, exception_type
+        FROM calendar_dates_staging
+        ON CONFLICT (service_id, date) DO UPDATE SET
+            exception_type = EXCLUDED.exception_type
+        WHERE calendar_dates IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_shapes(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO shapes (
+            shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence
+        )
+        SELECT
+            shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence
+        FROM shapes_staging
+        ON CONFLICT (shape_id, shape_pt_sequence) DO UPDATE SET
+            shape_pt_lat = EXCLUDED.shape_pt_lat,
+            shape_pt_lon = EXCLUDED.shape_pt_lon
+        WHERE shapes IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn merge_staged_feed_info(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO feed_info (
+            feed_publisher_name, feed_publisher_url, feed_lang, feed_start_date,
+            feed_end_date
+        )
+        SELECT
+            feed_publisher_name, feed_publisher_url, feed_lang, feed_start_date,
+            feed_end_date
+        FROM feed_info_staging
+        ON CONFLICT (feed_publisher_name) DO UPDATE SET
+            feed_publisher_url = EXCLUDED.feed_publisher_url,
+            feed_lang = EXCLUDED.feed_lang,
+            feed_start_date = EXCLUDED.feed_start_date,
+            feed_end_date = EXCLUDED.feed_end_date
+        WHERE feed_info IS DISTINCT FROM EXCLUDED
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_agencies(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM agency a
+        WHERE NOT EXISTS (
+            SELECT 1 FROM agency_staging staged
+            WHERE staged.agency_name = a.agency_name
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_stops(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM stops s
+        WHERE NOT EXISTS (
+            SELECT 1 FROM stops_staging staged
+            WHERE staged.stop_id = s.stop_id
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_routes(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM routes r
+        WHERE NOT EXISTS (
+            SELECT 1 FROM routes_staging staged
+            WHERE staged.route_id = r.route_id
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_trips(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM trips t
+        WHERE NOT EXISTS (
+            SELECT 1 FROM trips_staging staged
+            WHERE staged.trip_id = t.trip_id
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_stop_times(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM stop_times st
+        WHERE NOT EXISTS (
+            SELECT 1 FROM stop_times_staging staged
+            WHERE staged.trip_id = st.trip_id AND staged.stop_sequence = st.stop_sequence
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_calendars(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM calendar c
+        WHERE NOT EXISTS (
+            SELECT 1 FROM calendar_staging staged
+            WHERE staged.service_id = c.service_id
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_calendar_dates(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM calendar_dates cd
+        WHERE NOT EXISTS (
+            SELECT 1 FROM calendar_dates_staging staged
+            WHERE staged.service_id = cd.service_id AND staged.date = cd.date
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_shapes(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM shapes s
+        WHERE NOT EXISTS (
+            SELECT 1 FROM shapes_staging staged
+            WHERE staged.shape_id = s.shape_id AND staged.shape_pt_sequence = s.shape_pt_sequence
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn delete_unstaged_feed_info(pool: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM feed_info fi
+        WHERE NOT EXISTS (
+            SELECT 1 FROM feed_info_staging staged
+            WHERE staged.feed_publisher_name = fi.feed_publisher_name
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn get_feed_last_update(
+    feed_region: String,
+    pool: &PgPool,
+) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT feed_last_update
+        FROM last_update
+        WHERE feed_region = $1
+        "#,
+        feed_region
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    // Default to 1970-01-01 00:00:00 (the "zero" NaiveDateTime)
+    Ok(row.map(|r| r.feed_last_update))
+}
+
+/// Gets the feed's timezone. GTFS has every agency in a feed share one.
+pub async fn get_agency_timezone(pool: &PgPool) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT agency_timezone FROM agency LIMIT 1")
+        .fetch_optional(pool)
+        .await
+}
+
+/// Progress left by the last import into a region, if it didn't finish.
+pub async fn get_import_progress(
+    feed_region: &str,
+    pool: &PgPool,
+) -> Result<Vec<ImportProgress>, sqlx::Error> {
+    sqlx::query_as!(
+        ImportProgress,
+        r#"
+        SELECT * FROM import_progress
+        WHERE feed_region = $1
+        ORDER BY table_name
+        "#,
+        feed_region
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Starts a table's progress over.
+pub async fn start_import_progress(
+    progress: &ImportProgress,
+    pool: &PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO import_progress (
+            feed_region, table_name, checksum, rows_done, rows_total,
+            started_at, updated_at, completed_at
+        )
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8)
+        ON CONFLICT (feed_region, table_name) DO UPDATE SET
+            checksum = EXCLUDED.checksum,
+            rows_done = EXCLUDED.rows_done,
+            rows_total = EXCLUDED.rows_total,
+            started_at = EXCLUDED.started_at,
+            updated_at = EXCLUDED.updated_at,
+            completed_at = EXCLUDED.completed_at
+        "#,
+        progress.feed_region,
+        progress.table_name,
+        progress.checksum,
+        progress.rows_done,
+        progress.rows_total,
+        progress.started_at,
+        progress.updated_at,
+        progress.completed_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn update_import_progress(
+    feed_region: &str,
+    table_name: &str,
+    rows_done: i64,
+    rows_total: Option<i64>,
+    pool: &PgPool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE import_progress
+        SET rows_done = $3, rows_total = $4, updated_at = now() AT TIME ZONE 'UTC'
+        WHERE feed_region = $1 AND table_name = $2
+        "#,
+        feed_region,
+        table_name,
+        rows_done,
+        rows_total
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks a table as done. Run in the table's own transaction, so it's only
+/// marked if its rows are in.
+pub async fn complete_import_progress(
+    feed_region: &str,
+    table_name: &str,
+    rows_done: i64,
+    pool: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE import_progress
+        SET rows_done = $3,
+            updated_at = now() AT TIME ZONE 'UTC',
+            completed_at = now() AT TIME ZONE 'UTC'
+        WHERE feed_region = $1 AND table_name = $2
+        "#,
+        feed_region,
+        table_name,
+        rows_done
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Clears a region's progress once its import has finished.
+pub async fn clear_import_progress(
+    feed_region: &str,
+    pool: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM import_progress WHERE feed_region = $1",
+        feed_region
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A search query with LIKE's wildcards escaped, so it only ever matches itself.
+fn escape_like(query: &str) -> String {
+    query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Fuzzy matches stops on name and code, best match first.
+pub async fn search_stops(
+    query: &str,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<(Stop, f32)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon,
+            zone_id, stop_url, location_type, parent_station, platform_code,
+            GREATEST(
+                word_similarity($1, COALESCE(stop_name, '')),
+                CASE WHEN stop_code = $1 THEN 1.0 ELSE 0.0 END
+            )::real AS "score!"
+        FROM stops
+        WHERE $1 <% stop_name
+           OR stop_name ILIKE '%' || $3 || '%'
+           OR stop_code = $1
+        ORDER BY 12 DESC, stop_id
+        LIMIT $2
+        "#,
+        query,
+        limit,
+        escape_like(query)
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            (
+                Stop {
+                    stop_id: r.stop_id,
+                    stop_code: r.stop_code,
+                    stop_name: r.stop_name,
+                    stop_desc: r.stop_desc,
+                    stop_lat: r.stop_lat,
+                    stop_lon: r.stop_lon,
+                    zone_id: r.zone_id,
+                    stop_url: r.stop_url,
+                    location_type: r.location_type,
+                    parent_station: r.parent_station,
+                    platform_code: r.platform_code,
+                },
+                r.score,
+            )
+        })
+        .collect())
+}
+
+/// Fuzzy matches routes on short and long name, best match first.
+/// Exact short name matches ("66") always rank above partial ones.
+pub async fn search_routes(
+    query: &str,
+    limit: i64,
+    pool: &PgPool,
+) -> Result<Vec<(Route, f32)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
+            route_url, route_color, route_text_color,
+            GREATEST(
+                CASE
+                    WHEN lower(route_short_name) = lower($1) THEN 1.0
+                    WHEN route_short_name ILIKE $3 || '%' THEN 0.8
+                    ELSE 0.0
+                END,
+                0.9 * word_similarity($1, COALESCE(route_long_name, ''))
+            )::real AS "score!"
+        FROM routes
+        WHERE route_short_name ILIKE $3 || '%'
+           OR $1 <% route_long_name
+           OR route_long_name ILIKE '%' || $3 || '%'
+        ORDER BY 10 DESC, route_id
+        LIMIT $2
+        "#,
+        query,
+        limit,
+        escape_like(query)
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            (
+                Route {
+                    route_id: r.route_id,
+                    agency_id: r.agency_id,
+                    route_short_name: r.route_short_name,
+                    route_long_name: r.route_long_name,
+                    route_desc: r.route_desc,
+                    route_type: r.route_type,
+                    route_url: r.route_url,
+                    route_color: r.route_color,
+                    route_text_color: r.route_text_color,
+                },
+                r.score,
+            )
+        })
+        .collect())
+}
+
+pub async fn get_stops_by_id(stop_ids: &[String], pool: &PgPool) -> Result<Vec<Stop>, sqlx::Error> {
+    sqlx::query_as!(
+        Stop,
+        "SELECT * FROM stops WHERE stop_id = ANY($1)",
+        stop_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Gets every stop whose parent_station is one of the given stations.
+pub async fn get_child_stops(
+    parent_stations: &[String],
+    pool: &PgPool,
+) -> Result<Vec<Stop>, sqlx::Error> {
+    sqlx::query_as!(
+        Stop,
+        r#"
+        SELECT * FROM stops
+        WHERE parent_station = ANY($1)
+        ORDER BY platform_code, stop_id
+        "#,
+        parent_stations
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_stops(pool: &PgPool) -> Result<Vec<Stop>, sqlx::Error> {
+    sqlx::query_as!(Stop, "SELECT * FROM stops ORDER BY stop_id")
+        .fetch_all(pool)
+        .await
+}
+
+/// Gets every shape point, ordered by shape then sequence.
+pub async fn get_shapes(pool: &PgPool) -> Result<Vec<Shape>, sqlx::Error> {
+    sqlx::query_as!(
+        Shape,
+        "SELECT * FROM shapes ORDER BY shape_id, shape_pt_sequence"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Gets the route of every shape that trips reference.
+/// A shape can (rarely) be shared between routes, so shape ids may repeat.
+pub async fn get_shape_routes(pool: &PgPool) -> Result<Vec<(String, Route)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT
+            t.shape_id AS "shape_id!", r.route_id, r.agency_id, r.route_short_name, r.route_long_name,
+            r.route_desc, r.route_type, r.route_url, r.route_color, r.route_text_color
+        FROM trips t
+        JOIN routes r ON r.route_id = t.route_id
+        WHERE t.shape_id IS NOT NULL
+        ORDER BY 1, 2
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            (
+                r.shape_id,
+                Route {
+                    route_id: r.route_id,
+                    agency_id: r.agency_id,
+                    route_short_name: r.route_short_name,
+                    route_long_name: r.route_long_name,
+                    route_desc: r.route_desc,
+                    route_type: r.route_type,
+                    route_url: r.route_url,
+                    route_color: r.route_color,
+                    route_text_color: r.route_text_color,
+                },
+            )
+        })
+        .collect())
+}
+
+pub async fn get_trips_by_id(trip_ids: &[String], pool: &PgPool) -> Result<Vec<Trip>, sqlx::Error> {
+    sqlx::query_as!(
+        Trip,
+        "SELECT * FROM trips WHERE trip_id = ANY($1)",
+        trip_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Gets the ids of every route run by an agency, matched on agency_id or name.
+/// Routes without an agency_id belong to the agency if it is the only one.
+pub async fn get_agency_route_ids(agency: &str, pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT r.route_id
+        FROM routes r
+        JOIN agency a
+          ON a.agency_id = r.agency_id
+          OR (r.agency_id IS NULL AND (SELECT count(*) FROM agency) = 1)
+        WHERE a.agency_id = $1 OR a.agency_name = $1
+        "#,
+        agency
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.route_id).collect())
+}
+
+/// Gets the service_ids running on a date, from calendar and calendar_dates.
+pub async fn get_active_service_ids(
+    date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT service_id AS "service_id!" FROM calendar
+        WHERE $1 BETWEEN start_date AND end_date
+          AND CASE EXTRACT(ISODOW FROM $1::date)
+              WHEN 1 THEN monday
+              WHEN 2 THEN tuesday
+              WHEN 3 THEN wednesday
+              WHEN 4 THEN thursday
+              WHEN 5 THEN friday
+              WHEN 6 THEN saturday
+              ELSE sunday
+          END
+        UNION
+        SELECT service_id FROM calendar_dates WHERE date = $1 AND exception_type = 1
+        EXCEPT
+        SELECT service_id FROM calendar_dates WHERE date = $1 AND exception_type = 2
+        "#,
+        date
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.service_id).collect())
+}
+
+pub async fn get_trips_by_service(
+    service_ids: &[String],
+    pool: &PgPool,
+) -> Result<Vec<Trip>, sqlx::Error> {
+    sqlx::query_as!(
+        Trip,
+        "SELECT * FROM trips WHERE service_id = ANY($1) ORDER BY trip_id",
+        service_ids
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Gets the stop times of every trip on the given services, ordered by trip then sequence.
+/// With past_midnight_only, only trips still running 24 hours after the day started are included.
+pub async fn get_stop_times_by_service(
+    service_ids: &[String],
+    past_midnight_only: bool,
+    pool: &PgPool,
+) -> Result<Vec<StopTime>, sqlx::Error> {
+    sqlx::query_as!(
+        StopTime,
+        r#"
+        SELECT st.* FROM stop_times st
+        JOIN trips t ON t.trip_id = st.trip_id
+        WHERE t.service_id = ANY($1)
+          AND (NOT $2 OR st.trip_id IN (
+              SELECT trip_id FROM stop_times WHERE departure_time >= interval '24 hours'
+          ))
+        ORDER BY st.trip_id, st.stop_sequence
+        "#,
+        service_ids,
+        past_midnight_only
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_routes(pool: &PgPool) -> Result<Vec<Route>, sqlx::Error> {
+    sqlx::query_as!(Route, "SELECT * FROM routes ORDER BY route_id")
+        .fetch_all(pool)
+        .await
+}
+
+/// Replaces every generated transfer with the given ones.
+pub async fn replace_generated_transfers(
+    transfers: &[GeneratedTransfer],
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM generated_transfers")
+        .execute(&mut *db)
+        .await?;
+
+    let (mut from, mut to, mut distance, mut walk_time) = (vec![], vec![], vec![], vec![]);
+    for transfer in transfers {
+        from.push(transfer.from_stop_id.clone());
+        to.push(transfer.to_stop_id.clone());
+        distance.push(transfer.distance_m);
+        walk_time.push(transfer.walk_time);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO generated_transfers (from_stop_id, to_stop_id, distance_m, walk_time)
+        SELECT * FROM UNNEST($1::text[], $2::text[], $3::float8[], $4::int4[])
+        "#,
+        &from,
+        &to,
+        &distance,
+        &walk_time
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_generated_transfers(pool: &PgPool) -> Result<Vec<GeneratedTransfer>, sqlx::Error> {
+    sqlx::query_as!(
+        GeneratedTransfer,
+        "SELECT * FROM generated_transfers ORDER BY from_stop_id, walk_time"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Stores the latest observation of each stop time, replacing any earlier one.
+pub async fn upsert_stop_time_observations(
+    observations: &[StopTimeObservation],
+    pool: &PgPool,
+) -> Result<(), sqlx::Error> {
+    let mut trip_id = vec![];
+    let mut service_date = vec![];
+    let mut stop_sequence = vec![];
+    let (mut arrival_time, mut departure_time) = (vec![], vec![]);
+    let (mut arrival_delay, mut departure_delay) = (vec![], vec![]);
+    let mut observed_at = vec![];
+    for o in observations {
+        trip_id.push(o.trip_id.clone());
+        service_date.push(o.service_date);
+        stop_sequence.push(o.stop_sequence);
+        arrival_time.push(o.arrival_time);
+        departure_time.push(o.departure_time);
+        arrival_delay.push(o.arrival_delay);
+        departure_delay.push(o.departure_delay);
+        observed_at.push(o.observed_at);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO stop_time_observations (
+            trip_id, service_date, stop_sequence, arrival_time, departure_time,
+            arrival_delay, departure_delay, observed_at
+        )
+        SELECT * FROM UNNEST(
+            $1::text[], $2::date[], $3::int4[], $4::timestamp[], $5::timestamp[],
+            $6::int4[], $7::int4[], $8::timestamp[]
+        )
+        ON CONFLICT (trip_id, service_date, stop_sequence) DO UPDATE SET
+            arrival_time = EXCLUDED.arrival_time,
+            departure_time = EXCLUDED.departure_time,
+            arrival_delay = EXCLUDED.arrival_delay,
+            departure_delay = EXCLUDED.departure_delay,
+            observed_at = EXCLUDED.observed_at
+        "#,
+        &trip_id,
+        &service_date,
+        &stop_sequence,
+        &arrival_time as &[Option<NaiveDateTime>],
+        &departure_time as &[Option<NaiveDateTime>],
+        &arrival_delay as &[Option<i32>],
+        &departure_delay as &[Option<i32>],
+        &observed_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Rebuilds a day's route_performance from stop_time_delays.
+/// Delays from -early_secs to late_secs count as on time.
+pub async fn summarise_route_performance(
+    service_date: NaiveDate,
+    early_secs: i32,
+    late_secs: i32,
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM route_performance WHERE service_date = $1",
+        service_date
+    )
+    .execute(&mut *db)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO route_performance
+        SELECT
+            service_date, route_id, count(*)::int,
+            count(*) FILTER (WHERE delay BETWEEN -$2::int AND $3::int)::int,
+            count(*) FILTER (WHERE delay < -$2::int)::int,
+            count(*) FILTER (WHERE delay > $3::int)::int,
+            avg(delay)::float8,
+            percentile_cont(0.5) WITHIN GROUP (ORDER BY delay),
+            percentile_cont(0.9) WITHIN GROUP (ORDER BY delay)
+        FROM stop_time_delays
+        WHERE service_date = $1
+        GROUP BY service_date, route_id
+        "#,
+        service_date,
+        early_secs,
+        late_secs
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+/// Rebuilds a day's stop_performance from stop_time_delays.
+/// Delays from -early_secs to late_secs count as on time.
+pub async fn summarise_stop_performance(
+    service_date: NaiveDate,
+    early_secs: i32,
+    late_secs: i32,
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM stop_performance WHERE service_date = $1",
+        service_date
+    )
+    .execute(&mut *db)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO stop_performance
+        SELECT
+            service_date, stop_id, count(*)::int,
+            count(*) FILTER (WHERE delay BETWEEN -$2::int AND $3::int)::int,
+            count(*) FILTER (WHERE delay < -$2::int)::int,
+            count(*) FILTER (WHERE delay > $3::int)::int,
+            avg(delay)::float8,
+            percentile_cont(0.5) WITHIN GROUP (ORDER BY delay),
+            percentile_cont(0.9) WITHIN GROUP (ORDER BY delay)
+        FROM stop_time_delays
+        WHERE service_date = $1
+        GROUP BY service_date, stop_id
+        "#,
+        service_date,
+        early_secs,
+        late_secs
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+/// Rebuilds a day's hourly_performance from stop_time_delays.
+/// Delays from -early_secs to late_secs count as on time.
+pub async fn summarise_hourly_performance(
+    service_date: NaiveDate,
+    early_secs: i32,
+    late_secs: i32,
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM hourly_performance WHERE service_date = $1",
+        service_date
+    )
+    .execute(&mut *db)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO hourly_performance
+        SELECT
+            service_date, hour, count(*)::int,
+            count(*) FILTER (WHERE delay BETWEEN -$2::int AND $3::int)::int,
+            count(*) FILTER (WHERE delay < -$2::int)::int,
+            count(*) FILTER (WHERE delay > $3::int)::int,
+            avg(delay)::float8,
+            percentile_cont(0.5) WITHIN GROUP (ORDER BY delay),
+            percentile_cont(0.9) WITHIN GROUP (ORDER BY delay)
+        FROM stop_time_delays
+        WHERE service_date = $1
+        GROUP BY service_date, hour
+        "#,
+        service_date,
+        early_secs,
+        late_secs
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_route_performance(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Vec<RoutePerformance>, sqlx::Error> {
+    sqlx::query_as!(
+        RoutePerformance,
+        "SELECT * FROM route_performance WHERE service_date = $1 ORDER BY route_id",
+        service_date
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_stop_performance(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Vec<StopPerformance>, sqlx::Error> {
+    sqlx::query_as!(
+        StopPerformance,
+        "SELECT * FROM stop_performance WHERE service_date = $1 ORDER BY stop_id",
+        service_date
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_hourly_performance(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Vec<HourlyPerformance>, sqlx::Error> {
+    sqlx::query_as!(
+        HourlyPerformance,
+        "SELECT * FROM hourly_performance WHERE service_date = $1 ORDER BY hour",
+        service_date
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Rebuilds a day's headway_events from stop_time_delays, comparing each departure
+/// from a stop with the one before it on the same route and direction.
+/// Only stops scheduled at most max_scheduled_secs apart are looked at.
+pub async fn detect_headway_events(
+    service_date: NaiveDate,
+    bunching_secs: i32,
+    gap_factor: f64,
+    max_scheduled_secs: i32,
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM headway_events WHERE service_date = $1",
+        service_date
+    )
+    .execute(&mut *db)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO headway_events
+        SELECT
+            service_date, route_id, direction_id, stop_id, stop_sequence, trip_id, previous_trip_id,
+            CASE WHEN scheduled_headway - headway > $2::int THEN 'bunching' ELSE 'gap' END,
+            departed, headway, scheduled_headway
+        FROM (
+            SELECT
+                *,
+                lag(trip_id) OVER w AS previous_trip_id,
+                EXTRACT(EPOCH FROM departed - lag(departed) OVER w)::int AS headway,
+                EXTRACT(EPOCH FROM scheduled - lag(scheduled) OVER w)::int AS scheduled_headway
+            FROM (
+                SELECT d.*, t.direction_id, d.scheduled + make_interval(secs => d.delay) AS departed
+                FROM stop_time_delays d
+                JOIN trips t ON t.trip_id = d.trip_id
+                WHERE d.service_date = $1
+            ) departures
+            WINDOW w AS (PARTITION BY route_id, direction_id, stop_id ORDER BY departed)
+        ) headways
+        WHERE previous_trip_id IS NOT NULL
+          AND abs(scheduled_headway) <= $4::int
+          AND (scheduled_headway - headway > $2::int OR headway > scheduled_headway * $3::float8)
+        "#,
+        service_date,
+        bunching_secs,
+        gap_factor,
+        max_scheduled_secs
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_headway_events(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Vec<HeadwayEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        HeadwayEvent,
+        "SELECT * FROM headway_events WHERE service_date = $1 ORDER BY departed, stop_id",
+        service_date
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Stores the trips seen in a poll, keeping when each was first seen and where.
+pub async fn upsert_trip_observations(
+    observations: &[TripObservation],
+    pool: &PgPool,
+) -> Result<(), sqlx::Error> {
+    let (mut trip_id, mut service_date, mut route_id) = (vec![], vec![], vec![]);
+    let mut schedule_relationship = vec![];
+    let (mut in_trip_updates, mut in_vehicle_positions) = (vec![], vec![]);
+    let (mut first_seen, mut last_seen) = (vec![], vec![]);
+    for o in observations {
+        trip_id.push(o.trip_id.clone());
+        service_date.push(o.service_date);
+        route_id.push(o.route_id.clone());
+        schedule_relationship.push(o.schedule_relationship.clone());
+        in_trip_updates.push(o.in_trip_updates);
+        in_vehicle_positions.push(o.in_vehicle_positions);
+        first_seen.push(o.first_seen);
+        last_seen.push(o.last_seen);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO trip_observations AS o (
+            trip_id, service_date, route_id, schedule_relationship,
+            in_trip_updates, in_vehicle_positions, first_seen, last_seen
+        )
+        SELECT * FROM UNNEST(
+            $1::text[], $2::date[], $3::text[], $4::text[],
+            $5::bool[], $6::bool[], $7::timestamp[], $8::timestamp[]
+        )
+        ON CONFLICT (trip_id, service_date) DO UPDATE SET
+            route_id = COALESCE(EXCLUDED.route_id, o.route_id),
+            schedule_relationship = EXCLUDED.schedule_relationship,
+            in_trip_updates = o.in_trip_updates OR EXCLUDED.in_trip_updates,
+            in_vehicle_positions = o.in_vehicle_positions OR EXCLUDED.in_vehicle_positions,
+            first_seen = LEAST(o.first_seen, EXCLUDED.first_seen),
+            last_seen = GREATEST(o.last_seen, EXCLUDED.last_seen)
+        "#,
+        &trip_id,
+        &service_date,
+        &route_id as &[Option<String>],
+        &schedule_relationship,
+        &in_trip_updates,
+        &in_vehicle_positions,
+        &first_seen,
+        &last_seen
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Rebuilds a day's trip_statuses. Trips on the given (active) services are
+/// missing if they were never seen and should have finished before until.
+pub async fn detect_trip_statuses(
+    service_date: NaiveDate,
+    service_ids: &[String],
+    until: NaiveDateTime,
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM trip_statuses WHERE service_date = $1",
+        service_date
+    )
+    .execute(&mut *db)
+    .await?;
+    sqlx::query!(
+        r#"
+        WITH scheduled AS (
+            SELECT
+                t.trip_id, t.route_id,
+                $1::date + min(st.departure_time) AS start,
+                $1::date + max(st.departure_time) AS finish
+            FROM trips t
+            JOIN stop_times st ON st.trip_id = t.trip_id
+            WHERE t.service_id = ANY($2)
+            GROUP BY t.trip_id
+        )
+        INSERT INTO trip_statuses
+        SELECT $1, s.trip_id, s.route_id, 'missing', s.start
+        FROM scheduled s
+        WHERE s.finish < $3
+          AND NOT EXISTS (
+              SELECT 1 FROM trip_observations o
+              WHERE o.trip_id = s.trip_id AND o.service_date = $1
+          )
+        UNION ALL
+        SELECT
+            $1, o.trip_id, COALESCE(o.route_id, s.route_id),
+            CASE WHEN o.schedule_relationship IN ('CANCELED', 'DELETED') THEN 'cancelled' ELSE 'added' END,
+            s.start
+        FROM trip_observations o
+        LEFT JOIN scheduled s ON s.trip_id = o.trip_id
+        WHERE o.service_date = $1
+          AND o.schedule_relationship IN ('CANCELED', 'DELETED', 'ADDED', 'NEW')
+        "#,
+        service_date,
+        service_ids,
+        until
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+/// Rebuilds a day's daily_trip_report from trip_statuses.
+pub async fn summarise_daily_trip_report(
+    service_date: NaiveDate,
+    service_ids: &[String],
+    db: &mut PgConnection,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO daily_trip_reports
+        SELECT
+            $1,
+            (SELECT count(*) FROM trips WHERE service_id = ANY($2))::int,
+            (SELECT count(*) FROM trip_observations WHERE service_date = $1)::int,
+            count(*) FILTER (WHERE status = 'missing')::int,
+            count(*) FILTER (WHERE status = 'cancelled')::int,
+            count(*) FILTER (WHERE status = 'added')::int
+        FROM trip_statuses
+        WHERE service_date = $1
+        ON CONFLICT (service_date) DO UPDATE SET
+            scheduled = EXCLUDED.scheduled,
+            seen = EXCLUDED.seen,
+            missing = EXCLUDED.missing,
+            cancelled = EXCLUDED.cancelled,
+            added = EXCLUDED.added
+        "#,
+        service_date,
+        service_ids
+    )
+    .execute(&mut *db)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_trip_statuses(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Vec<TripStatus>, sqlx::Error> {
+    sqlx::query_as!(
+        TripStatus,
+        "SELECT * FROM trip_statuses WHERE service_date = $1 ORDER BY trip_id",
+        service_date
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_daily_trip_report(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> Result<Option<DailyTripReport>, sqlx::Error> {
+    sqlx::query_as!(
+        DailyTripReport,
+        "SELECT * FROM daily_trip_reports WHERE service_date = $1",
+        service_date
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Streams of whole tables, for exports too big to hold in memory at once.
+
+pub fn stream_agencies(pool: &PgPool) -> BoxStream<'_, Result<Agency, sqlx::Error>> {
+    sqlx::query_as!(Agency, "SELECT * FROM agency ORDER BY agency_name").fetch(pool)
+}
+
+pub fn stream_stops(pool: &PgPool) -> BoxStream<'_, Result<Stop, sqlx::Error>> {
+    sqlx::query_as!(Stop, "SELECT * FROM stops ORDER BY stop_id").fetch(pool)
+}
+
+pub fn stream_routes(pool: &PgPool) -> BoxStream<'_, Result<Route, sqlx::Error>> {
+    sqlx::query_as!(Route, "SELECT * FROM routes ORDER BY route_id").fetch(pool)
+}
+
+pub fn stream_trips(pool: &PgPool) -> BoxStream<'_, Result<Trip, sqlx::Error>> {
+    sqlx::query_as!(Trip, "SELECT * FROM trips ORDER BY trip_id").fetch(pool)
+}
+
+pub fn stream_stop_times(pool: &PgPool) -> BoxStream<'_, Result<StopTime, sqlx::Error>> {
+    sqlx::query_as!(
+        StopTime,
+        "SELECT * FROM stop_times ORDER BY trip_id, stop_sequence"
+    )
+    .fetch(pool)
+}
+
+pub fn stream_calendars(pool: &PgPool) -> BoxStream<'_, Result<Calendar, sqlx::Error>> {
+    sqlx::query_as!(Calendar, "SELECT * FROM calendar ORDER BY service_id").fetch(pool)
+}
+
+pub fn stream_calendar_dates(pool: &PgPool) -> BoxStream<'_, Result<CalendarDate, sqlx::Error>> {
+    sqlx::query_as!(
+        CalendarDate,
+        "SELECT * FROM calendar_dates ORDER BY service_id, date"
+    )
+    .fetch(pool)
+}
+
+pub fn stream_shapes(pool: &PgPool) -> BoxStream<'_, Result<Shape, sqlx::Error>> {
+    sqlx::query_as!(
+        Shape,
+        "SELECT * FROM shapes ORDER BY shape_id, shape_pt_sequence"
+    )
+    .fetch(pool)
+}
+
+pub fn stream_feed_info(pool: &PgPool) -> BoxStream<'_, Result<FeedInfo, sqlx::Error>> {
+    sqlx::query_as!(FeedInfo, "SELECT * FROM feed_info").fetch(pool)
+}
+
+pub fn stream_generated_transfers(
+    pool: &PgPool,
+) -> BoxStream<'_, Result<GeneratedTransfer, sqlx::Error>> {
+    sqlx::query_as!(
+        GeneratedTransfer,
+        "SELECT * FROM generated_transfers ORDER BY from_stop_id, to_stop_id"
+    )
+    .fetch(pool)
+}
+
+/// Stop times of the trips on the given routes, running on some day between the dates.
+/// With a bbox, only stops inside it are kept, and trips that don't stop
+/// there at least twice are dropped. None matches everything.
+pub fn stream_subset_stop_times<'a>(
+    route_ids: Option<&'a [String]>,
+    bbox: Option<BBox>,
+    dates: Option<(NaiveDate, NaiveDate)>,
+    pool: &'a PgPool,
+) -> BoxStream<'a, Result<StopTime, sqlx::Error>> {
+    sqlx::query_as!(
+        StopTime,
+        r#"
+        WITH services AS (
+          SELECT c.service_id
+          FROM calendar c, generate_series($6::date, $7::date, interval '1 day') AS day
+          WHERE day BETWEEN c.start_date AND c.end_date
+            AND CASE EXTRACT(ISODOW FROM day)
+                WHEN 1 THEN monday
+                WHEN 2 THEN tuesday
+                WHEN 3 THEN wednesday
+                WHEN 4 THEN thursday
+                WHEN 5 THEN friday
+                WHEN 6 THEN saturday
+                ELSE sunday
+            END
+            AND NOT EXISTS (
+              SELECT 1 FROM calendar_dates cd
+              WHERE cd.service_id = c.service_id AND cd.date = day AND cd.exception_type = 2
+            )
+          UNION
+          SELECT service_id FROM calendar_dates
+          WHERE exception_type = 1 AND date BETWEEN $6 AND $7
+        ),
+        kept AS (
+          SELECT st.*, count(*) OVER (PARTITION BY st.trip_id) AS stops
+          FROM stop_times st
+          JOIN trips t USING (trip_id)
+          JOIN stops s USING (stop_id)
+          WHERE ($1::text[] IS NULL OR t.route_id = ANY($1))
+            AND ($2::float8 IS NULL OR s.stop_lon BETWEEN $2 AND $4 AND s.stop_lat BETWEEN $3 AND $5)
+            AND ($6::date IS NULL OR t.service_id IN (SELECT service_id FROM services))
+        )
+        SELECT
+          trip_id AS "trip_id!",
+          arrival_time,
+          departure_time AS "departure_time!",
+          stop_id AS "stop_id!",
+          stop_sequence AS "stop_sequence!",
+          pickup_type AS "pickup_type!",
+          drop_off_type AS "drop_off_type!"
+        FROM kept
+        WHERE $2::float8 IS NULL OR stops >= 2
+        ORDER BY trip_id, stop_sequence
+        "#,
+        route_ids,
+        bbox.map(|b| b.min_lon),
+        bbox.map(|b| b.min_lat),
+        bbox.map(|b| b.max_lon),
+        bbox.map(|b| b.max_lat),
+        dates.map(|(start, _)| start),
+        dates.map(|(_, end)| end)
+    )
+    .fetch(pool)
+}
+
+/// Every service date with archived realtime observations.
+pub async fn get_observed_service_dates(pool: &PgPool) -> Result<Vec<NaiveDate>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT service_date AS "service_date!" FROM stop_time_observations
+        UNION
+        SELECT service_date FROM trip_observations
+        ORDER BY 1
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.service_date).collect())
+}
+
+pub fn stream_stop_time_observations(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> BoxStream<'_, Result<StopTimeObservation, sqlx::Error>> {
+    sqlx::query_as!(
+        StopTimeObservation,
+        "SELECT * FROM stop_time_observations WHERE service_date = $1 ORDER BY trip_id, stop_sequence",
+        service_date
+    )
+    .fetch(pool)
+}
+
+pub fn stream_trip_observations(
+    service_date: NaiveDate,
+    pool: &PgPool,
+) -> BoxStream<'_, Result<TripObservation, sqlx::Error>> {
+    sqlx::query_as!(
+        TripObservation,
+        "SELECT * FROM trip_observations WHERE service_date = $1 ORDER BY trip_id",
+        service_date
+    )
+    .fetch(pool)
+}
+
+/// Round trips to the db and back, to check it's reachable.
+pub async fn ping(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT 1 AS "one!""#)
+        .fetch_one(pool)
+        .await?;
+    Ok(())
+}
+
+/// The latest feed_end_date in feed_info, None if there isn't one.
+pub async fn get_feed_end_date(pool: &PgPool) -> Result<Option<NaiveDate>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT MAX(feed_end_date) FROM feed_info")
+        .fetch_one(pool)
+        .await
 }
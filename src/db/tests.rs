@@ -5,12 +5,18 @@
 
 use crate::db::queries::{get_feed_last_update, insert_last_update};
 
-#[cfg(test)]
 use super::queries::{
-    insert_agency, insert_calendar, insert_calendar_date, insert_feed_info, insert_route,
-    insert_shape, insert_stop, insert_stop_time, insert_trip,
+    detect_headway_events, detect_trip_statuses, get_active_service_ids, get_agency_route_ids,
+    get_child_stops, get_daily_trip_report, get_generated_transfers, get_headway_events,
+    get_hourly_performance, get_route_performance, get_shape_routes, get_shapes,
+    get_stop_performance, get_trip_statuses, insert_agency, insert_calendar, insert_calendar_date,
+    insert_feed_info, insert_route, insert_shape, insert_stop, insert_stop_time, insert_trip,
+    replace_generated_transfers, search_routes, search_stops, summarise_daily_trip_report,
+    summarise_hourly_performance, summarise_route_performance, summarise_stop_performance,
+    upsert_stop_time_observations, upsert_trip_observations,
 };
 use super::types::*;
+use crate::bridge::static_bridge::ImportMode;
 use chrono::{NaiveDate, TimeDelta, Timelike, Utc};
 use sqlx::PgPool;
 use tracing_test::traced_test;
@@ -20,6 +26,7 @@ use tracing_test::traced_test;
 async fn test_agency(pool: PgPool) -> sqlx::Result<()> {
     let mut pool = pool.begin().await?;
     let agency = Agency {
+        agency_id: Some("TL".into()),
         agency_name: "Translink".into(),
         agency_url: "https://translink.com.au/".into(),
         agency_timezone: "Australia/Brisbane".into(),
@@ -157,6 +164,7 @@ async fn test_route(pool: PgPool) -> sqlx::Result<()> {
     let mut pool = pool.begin().await?;
     let route = Route {
         route_id: "19-4158".into(),
+        agency_id: None,
         route_short_name: Some("19".into()),
         route_long_name: Some("Salisbury - PA Hospital StationLink".into()),
         route_desc: None,
@@ -247,6 +255,7 @@ async fn test_trip(pool: PgPool) -> sqlx::Result<()> {
     let mut pool = pool.begin().await?;
     let route = Route {
         route_id: "R600-3454".into(),
+        agency_id: None,
         route_short_name: Some("19".into()),
         route_long_name: Some("Salisbury - PA Hospital StationLink".into()),
         route_desc: None,
@@ -303,6 +312,8 @@ async fn test_stop_time(pool: PgPool) -> sqlx::Result<()> {
 
     let route = Route {
         route_id: "R600-3454".into(),
+
+        agency_id: None,
         route_short_name: Some("19".into()),
         route_long_name: Some("Salisbury - PA Hospital StationLink".into()),
         route_desc: None,
@@ -378,3 +389,1294 @@ async fn test_get_feed_last_update(pool: PgPool) -> sqlx::Result<()> {
     assert_eq!(expected_last_update, actual_last_update.unwrap());
     Ok(())
 }
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_search_stops(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let station = Stop {
+        stop_id: "place_romast".into(),
+        stop_code: None,
+        stop_name: Some("Roma Street station".into()),
+        stop_desc: None,
+        stop_lat: Some(-27.465),
+        stop_lon: Some(153.019),
+        zone_id: Some("1".into()),
+        stop_url: None,
+        location_type: Some(1),
+        parent_station: None,
+        platform_code: None,
+    };
+    let platform = Stop {
+        stop_id: "600016".into(),
+        stop_code: Some("600016".into()),
+        stop_name: Some("Roma Street station, platform 1".into()),
+        stop_desc: None,
+        stop_lat: Some(-27.465),
+        stop_lon: Some(153.019),
+        zone_id: Some("1".into()),
+        stop_url: None,
+        location_type: Some(0),
+        parent_station: Some(station.stop_id.clone()),
+        platform_code: Some("1".into()),
+    };
+    insert_stop(&station, &mut *transaction).await?;
+    insert_stop(&platform, &mut *transaction).await?;
+    transaction.commit().await?;
+
+    let matches = search_stops("roma st", 10, &pool).await?;
+    assert_eq!(matches.len(), 2);
+    assert!(matches.iter().all(|(_, score)| *score > 0.5));
+
+    let by_code = search_stops("600016", 10, &pool).await?;
+    assert_eq!(by_code[0].0, platform);
+    assert_eq!(by_code[0].1, 1.0);
+
+    // LIKE wildcards in a query are only themselves, not a match for everything.
+    assert!(search_stops("%", 10, &pool).await?.is_empty());
+    assert!(search_stops("st%on", 10, &pool).await?.is_empty());
+    assert!(search_stops("R_ma", 10, &pool).await?.is_empty());
+
+    let children = get_child_stops(std::slice::from_ref(&station.stop_id), &pool).await?;
+    assert_eq!(children, vec![platform]);
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_search_routes(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    for (route_id, short_name) in [("660-3454", "660"), ("66-3454", "66")] {
+        let route = Route {
+            route_id: route_id.into(),
+            agency_id: None,
+            route_short_name: Some(short_name.into()),
+            route_long_name: Some("RBWH - UQ Lakes".into()),
+            route_desc: None,
+            route_type: 3,
+            route_url: None,
+            route_color: Some("E463A4".into()),
+            route_text_color: Some("000000".into()),
+        };
+        insert_route(&route, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+
+    let matches = search_routes("66", 10, &pool).await?;
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].0.route_id, "66-3454");
+    assert!(matches[0].1 > matches[1].1);
+    assert!(search_routes("%", 10, &pool).await?.is_empty());
+    assert!(search_routes("6_", 10, &pool).await?.is_empty());
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_get_shape_routes(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let route = Route {
+        route_id: "R600-3454".into(),
+        agency_id: None,
+        route_short_name: Some("19".into()),
+        route_long_name: Some("Salisbury - PA Hospital StationLink".into()),
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: Some("E463A4".into()),
+        route_text_color: Some("000000".into()),
+    };
+    insert_route(&route, &mut *transaction).await?;
+
+    for trip_id in ["32324843-ATS_KBL 25-38992", "32324844-ATS_KBL 25-38992"] {
+        let trip = Trip {
+            route_id: route.route_id.clone(),
+            service_id: "ATS_KBL 25-38992".into(),
+            trip_id: trip_id.into(),
+            trip_headsign: None,
+            direction_id: Some(false),
+            block_id: None,
+            shape_id: Some("R6000053".into()),
+        };
+        insert_trip(&trip, &mut *transaction).await?;
+    }
+
+    for sequence in [10002, 10001] {
+        let shape = Shape {
+            shape_id: "R6000053".into(),
+            shape_pt_lat: -27.55,
+            shape_pt_lon: 153.02,
+            shape_pt_sequence: sequence,
+        };
+        insert_shape(&shape, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+
+    let shape_routes = get_shape_routes(&pool).await?;
+    assert_eq!(shape_routes, vec![("R6000053".to_owned(), route)]);
+
+    let sequences: Vec<i32> = get_shapes(&pool)
+        .await?
+        .iter()
+        .map(|s| s.shape_pt_sequence)
+        .collect();
+    assert_eq!(sequences, vec![10001, 10002]);
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_get_agency_route_ids(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let agency = Agency {
+        agency_id: None,
+        agency_name: "Translink".into(),
+        agency_url: "https://translink.com.au/".into(),
+        agency_timezone: "Australia/Brisbane".into(),
+        agency_lang: Some("en".into()),
+        agency_phone: None,
+    };
+    insert_agency(&agency, &mut *transaction).await?;
+
+    let route = Route {
+        route_id: "66-3454".into(),
+        agency_id: None,
+        route_short_name: Some("66".into()),
+        route_long_name: Some("RBWH - UQ Lakes".into()),
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: None,
+        route_text_color: None,
+    };
+    insert_route(&route, &mut *transaction).await?;
+    transaction.commit().await?;
+
+    // With a single agency, routes without an agency_id belong to it.
+    assert_eq!(
+        get_agency_route_ids("Translink", &pool).await?,
+        vec![route.route_id]
+    );
+    assert!(
+        get_agency_route_ids("Queensland Rail", &pool)
+            .await?
+            .is_empty()
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_get_active_service_ids(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let weekdays = Calendar {
+        service_id: "weekdays".into(),
+        monday: true,
+        tuesday: true,
+        wednesday: true,
+        thursday: true,
+        friday: true,
+        saturday: false,
+        sunday: false,
+        start_date: NaiveDate::from_ymd_opt(2025, 7, 1).unwrap(),
+        end_date: NaiveDate::from_ymd_opt(2025, 7, 31).unwrap(),
+    };
+    insert_calendar(&weekdays, &mut *transaction).await?;
+    for (service_id, exception_type) in [("weekdays", 2), ("special", 1)] {
+        let cd = CalendarDate {
+            service_id: service_id.into(),
+            date: NaiveDate::from_ymd_opt(2025, 7, 4).unwrap(),
+            exception_type,
+        };
+        insert_calendar_date(&cd, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+
+    // A Thursday, a Friday with the weekday service swapped out, then a Saturday.
+    let active =
+        async |d| get_active_service_ids(NaiveDate::from_ymd_opt(2025, 7, d).unwrap(), &pool).await;
+    assert_eq!(active(3).await?, vec!["weekdays".to_owned()]);
+    assert_eq!(active(4).await?, vec!["special".to_owned()]);
+    assert!(active(5).await?.is_empty());
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_replace_generated_transfers(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    for stop_id in ["1", "2", "3"] {
+        let stop = Stop {
+            stop_id: stop_id.into(),
+            stop_code: None,
+            stop_name: Some(format!("Stop {stop_id}")),
+            stop_desc: None,
+            stop_lat: Some(-27.467834),
+            stop_lon: Some(153.019079),
+            zone_id: None,
+            stop_url: None,
+            location_type: Some(0),
+            parent_station: None,
+            platform_code: None,
+        };
+        insert_stop(&stop, &mut *transaction).await?;
+    }
+    let transfer = |from: &str, to: &str, walk_time| GeneratedTransfer {
+        from_stop_id: from.into(),
+        to_stop_id: to.into(),
+        distance_m: walk_time as f64 * 1.3,
+        walk_time,
+    };
+    replace_generated_transfers(&[transfer("1", "2", 100)], &mut transaction).await?;
+    replace_generated_transfers(
+        &[transfer("2", "3", 200), transfer("2", "1", 100)],
+        &mut transaction,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    // The first lot is gone, and each stop's transfers come shortest first.
+    assert_eq!(
+        get_generated_transfers(&pool).await?,
+        vec![transfer("2", "1", 100), transfer("2", "3", 200)]
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_summarise_performance(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let stop = Stop {
+        stop_id: "1".into(),
+        stop_code: None,
+        stop_name: Some("Herschel Street Stop 1 near North Quay".into()),
+        stop_desc: None,
+        stop_lat: Some(-27.467834),
+        stop_lon: Some(153.019079),
+        zone_id: None,
+        stop_url: None,
+        location_type: Some(0),
+        parent_station: None,
+        platform_code: None,
+    };
+    insert_stop(&stop, &mut *transaction).await?;
+    let route = Route {
+        route_id: "R600-3454".into(),
+        agency_id: None,
+        route_short_name: Some("19".into()),
+        route_long_name: None,
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: None,
+        route_text_color: None,
+    };
+    insert_route(&route, &mut *transaction).await?;
+    let trip = Trip {
+        route_id: route.route_id.clone(),
+        service_id: "weekdays".into(),
+        trip_id: "trip".into(),
+        trip_headsign: None,
+        direction_id: None,
+        block_id: None,
+        shape_id: None,
+    };
+    insert_trip(&trip, &mut *transaction).await?;
+    // Leaving the same stop at 8:00, 8:10 and 8:20.
+    for stop_sequence in 1..=3 {
+        let time = TimeDelta::try_minutes(8 * 60 + 10 * (stop_sequence as i64 - 1)).unwrap();
+        let stop_time = StopTime {
+            trip_id: trip.trip_id.clone(),
+            arrival_time: Some(time.try_into().unwrap()),
+            departure_time: time.try_into().unwrap(),
+            stop_id: stop.stop_id.clone(),
+            stop_sequence,
+            pickup_type: 0,
+            drop_off_type: 0,
+        };
+        insert_stop_time(&stop_time, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+
+    let date = NaiveDate::from_ymd_opt(2025, 7, 1).unwrap();
+    let at = |h, m| date.and_hms_opt(h, m, 0).unwrap();
+    let observation =
+        |stop_sequence, departure_time, departure_delay, observed_at| StopTimeObservation {
+            trip_id: trip.trip_id.clone(),
+            service_date: date,
+            stop_sequence,
+            arrival_time: None,
+            departure_time,
+            arrival_delay: None,
+            departure_delay,
+            observed_at,
+        };
+    // A minute late, two minutes early, then ten minutes late but not there yet.
+    upsert_stop_time_observations(
+        &[
+            observation(1, Some(at(8, 1)), None, at(8, 25)),
+            observation(2, None, Some(-120), at(8, 25)),
+            observation(3, None, Some(600), at(8, 25)),
+        ],
+        &pool,
+    )
+    .await?;
+    let summarise = async || {
+        let mut transaction = pool.begin().await?;
+        summarise_route_performance(date, 60, 300, &mut transaction).await?;
+        summarise_stop_performance(date, 60, 300, &mut transaction).await?;
+        summarise_hourly_performance(date, 60, 300, &mut transaction).await?;
+        transaction.commit().await
+    };
+    summarise().await?;
+    let routes = get_route_performance(date, &pool).await?;
+    assert_eq!((routes[0].observations, routes[0].early), (2, 1));
+
+    // Once it's been, the third stop counts too, and summarising again replaces the old rows.
+    upsert_stop_time_observations(&[observation(3, None, Some(600), at(8, 31))], &pool).await?;
+    summarise().await?;
+    let expected = |hour| HourlyPerformance {
+        service_date: date,
+        hour,
+        observations: 3,
+        on_time: 1,
+        early: 1,
+        late: 1,
+        mean_delay: 180.0,
+        median_delay: 60.0,
+        p90_delay: 492.0,
+    };
+    assert_eq!(
+        get_hourly_performance(date, &pool).await?,
+        vec![expected(8)]
+    );
+    let routes = get_route_performance(date, &pool).await?;
+    assert_eq!(routes.len(), 1);
+    assert_eq!(
+        (routes[0].observations, routes[0].late, routes[0].mean_delay),
+        (3, 1, 180.0)
+    );
+    let stops = get_stop_performance(date, &pool).await?;
+    assert_eq!((stops[0].stop_id.as_str(), stops[0].on_time), ("1", 1));
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_detect_headway_events(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let stop = Stop {
+        stop_id: "1".into(),
+        stop_code: None,
+        stop_name: Some("Herschel Street Stop 1 near North Quay".into()),
+        stop_desc: None,
+        stop_lat: Some(-27.467834),
+        stop_lon: Some(153.019079),
+        zone_id: None,
+        stop_url: None,
+        location_type: Some(0),
+        parent_station: None,
+        platform_code: None,
+    };
+    insert_stop(&stop, &mut *transaction).await?;
+    let route = Route {
+        route_id: "R600-3454".into(),
+        agency_id: None,
+        route_short_name: Some("19".into()),
+        route_long_name: None,
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: None,
+        route_text_color: None,
+    };
+    insert_route(&route, &mut *transaction).await?;
+    // Every ten minutes from 8:00, then d two minutes behind c.
+    for (trip_id, minutes) in [("a", 0), ("b", 10), ("c", 20), ("d", 22)] {
+        let trip = Trip {
+            route_id: route.route_id.clone(),
+            service_id: "weekdays".into(),
+            trip_id: trip_id.into(),
+            trip_headsign: None,
+            direction_id: Some(false),
+            block_id: None,
+            shape_id: None,
+        };
+        insert_trip(&trip, &mut *transaction).await?;
+        let time = TimeDelta::try_minutes(8 * 60 + minutes).unwrap();
+        let stop_time = StopTime {
+            trip_id: trip.trip_id.clone(),
+            arrival_time: Some(time.try_into().unwrap()),
+            departure_time: time.try_into().unwrap(),
+            stop_id: stop.stop_id.clone(),
+            stop_sequence: 1,
+            pickup_type: 0,
+            drop_off_type: 0,
+        };
+        insert_stop_time(&stop_time, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+
+    // b runs 11 minutes late, leaving a 21 minute gap, then c turns up 30s behind it.
+    // d is 90s behind c, under two minutes but only 30s closer than scheduled.
+    let date = NaiveDate::from_ymd_opt(2025, 7, 1).unwrap();
+    let at = |h, m, s| date.and_hms_opt(h, m, s).unwrap();
+    let observations: Vec<StopTimeObservation> = [("a", 0), ("b", 660), ("c", 90), ("d", 60)]
+        .into_iter()
+        .map(|(trip_id, delay)| StopTimeObservation {
+            trip_id: trip_id.into(),
+            service_date: date,
+            stop_sequence: 1,
+            arrival_time: None,
+            departure_time: None,
+            arrival_delay: None,
+            departure_delay: Some(delay),
+            observed_at: at(9, 0, 0),
+        })
+        .collect();
+    upsert_stop_time_observations(&observations, &pool).await?;
+
+    let mut transaction = pool.begin().await?;
+    detect_headway_events(date, 120, 2.0, 900, &mut transaction).await?;
+    transaction.commit().await?;
+
+    let event = |trip_id: &str, previous: &str, kind: &str, departed, headway| HeadwayEvent {
+        service_date: date,
+        route_id: route.route_id.clone(),
+        direction_id: Some(false),
+        stop_id: stop.stop_id.clone(),
+        stop_sequence: 1,
+        trip_id: trip_id.into(),
+        previous_trip_id: previous.into(),
+        kind: kind.into(),
+        departed,
+        headway,
+        scheduled_headway: 600,
+    };
+    assert_eq!(
+        get_headway_events(date, &pool).await?,
+        vec![
+            event("b", "a", "gap", at(8, 21, 0), 1260),
+            event("c", "b", "bunching", at(8, 21, 30), 30),
+        ]
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_detect_trip_statuses(pool: PgPool) -> sqlx::Result<()> {
+    let mut transaction = pool.begin().await?;
+    let stop = Stop {
+        stop_id: "1".into(),
+        stop_code: None,
+        stop_name: Some("Herschel Street Stop 1 near North Quay".into()),
+        stop_desc: None,
+        stop_lat: Some(-27.467834),
+        stop_lon: Some(153.019079),
+        zone_id: None,
+        stop_url: None,
+        location_type: Some(0),
+        parent_station: None,
+        platform_code: None,
+    };
+    insert_stop(&stop, &mut *transaction).await?;
+    let route = Route {
+        route_id: "R600-3454".into(),
+        agency_id: None,
+        route_short_name: Some("19".into()),
+        route_long_name: None,
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: None,
+        route_text_color: None,
+    };
+    insert_route(&route, &mut *transaction).await?;
+    // Hourly from 8:00, on weekdays. "sunday" isn't running.
+    for (i, (trip_id, service_id)) in [
+        ("ran", "weekdays"),
+        ("missed", "weekdays"),
+        ("cancelled", "weekdays"),
+        ("later", "weekdays"),
+        ("sunday", "sundays"),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let trip = Trip {
+            route_id: route.route_id.clone(),
+            service_id: service_id.into(),
+            trip_id: trip_id.into(),
+            trip_headsign: None,
+            direction_id: None,
+            block_id: None,
+            shape_id: None,
+        };
+        insert_trip(&trip, &mut *transaction).await?;
+        let time = TimeDelta::try_hours(8 + i as i64).unwrap();
+        let stop_time = StopTime {
+            trip_id: trip.trip_id.clone(),
+            arrival_time: Some(time.try_into().unwrap()),
+            departure_time: time.try_into().unwrap(),
+            stop_id: stop.stop_id.clone(),
+            stop_sequence: 1,
+            pickup_type: 0,
+            drop_off_type: 0,
+        };
+        insert_stop_time(&stop_time, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+
+    let date = NaiveDate::from_ymd_opt(2025, 7, 1).unwrap();
+    let at = |h| date.and_hms_opt(h, 0, 0).unwrap();
+    let observation = |trip_id: &str, schedule_relationship: &str| TripObservation {
+        trip_id: trip_id.into(),
+        service_date: date,
+        route_id: Some(route.route_id.clone()),
+        schedule_relationship: schedule_relationship.into(),
+        in_trip_updates: true,
+        in_vehicle_positions: false,
+        first_seen: at(7),
+        last_seen: at(7),
+    };
+    upsert_trip_observations(
+        &[
+            observation("ran", "SCHEDULED"),
+            observation("cancelled", "CANCELED"),
+            observation("extra", "ADDED"),
+        ],
+        &pool,
+    )
+    .await?;
+
+    // At 10:30, "later" hasn't been due yet.
+    let service_ids = vec!["weekdays".to_owned()];
+    let mut transaction = pool.begin().await?;
+    detect_trip_statuses(
+        date,
+        &service_ids,
+        date.and_hms_opt(10, 30, 0).unwrap(),
+        &mut transaction,
+    )
+    .await?;
+    summarise_daily_trip_report(date, &service_ids, &mut transaction).await?;
+    transaction.commit().await?;
+
+    let status = |trip_id: &str, status: &str, scheduled_start| TripStatus {
+        service_date: date,
+        trip_id: trip_id.into(),
+        route_id: Some(route.route_id.clone()),
+        status: status.into(),
+        scheduled_start,
+    };
+    assert_eq!(
+        get_trip_statuses(date, &pool).await?,
+        vec![
+            status("cancelled", "cancelled", Some(at(10))),
+            status("extra", "added", None),
+            status("missed", "missing", Some(at(9))),
+        ]
+    );
+    assert_eq!(
+        get_daily_trip_report(date, &pool).await?,
+        Some(DailyTripReport {
+            service_date: date,
+            scheduled: 4,
+            seen: 3,
+            missing: 1,
+            cancelled: 1,
+            added: 1,
+        })
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_write_gtfs(pool: PgPool) -> sqlx::Result<()> {
+    use std::io::{Cursor, Read};
+
+    let mut conn = pool.begin().await?;
+    insert_agency(
+        &Agency {
+            agency_id: None,
+            agency_name: "Translink".into(),
+            agency_url: "https://translink.com.au/".into(),
+            agency_timezone: "Australia/Brisbane".into(),
+            agency_lang: Some("en".into()),
+            agency_phone: Some("13 12 30".into()),
+        },
+        &mut *conn,
+    )
+    .await?;
+    insert_calendar(
+        &Calendar {
+            service_id: "ATS_HBL 23-34836".into(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: false,
+            sunday: false,
+            start_date: NaiveDate::from_ymd_opt(2023, 11, 14).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2023, 11, 17).unwrap(),
+        },
+        &mut *conn,
+    )
+    .await?;
+    insert_feed_info(
+        &FeedInfo {
+            feed_publisher_name: "Translink".into(),
+            feed_publisher_url: "https://www.translink.com.au/".into(),
+            feed_lang: Some("en".into()),
+            feed_start_date: None,
+            feed_end_date: None,
+        },
+        &mut *conn,
+    )
+    .await?;
+    conn.commit().await?;
+
+    let db = super::Db(pool);
+    let zip =
+        crate::export::gtfs::write_gtfs(&db, &Default::default(), Some("v1"), Cursor::new(vec![]))
+            .await
+            .unwrap();
+    let mut zip = zip::ZipArchive::new(zip).unwrap();
+    let mut read = |name: &str| {
+        let mut text = String::new();
+        zip.by_name(name)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    };
+
+    assert_eq!(
+        read("agency.txt"),
+        "agency_id,agency_name,agency_url,agency_timezone,agency_lang,agency_phone\n\
+         ,Translink,https://translink.com.au/,Australia/Brisbane,en,13 12 30\n"
+    );
+    assert_eq!(
+        read("calendar.txt"),
+        "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+         ATS_HBL 23-34836,1,1,1,1,1,0,0,20231114,20231117\n"
+    );
+    assert_eq!(
+        read("feed_info.txt"),
+        "feed_publisher_name,feed_publisher_url,feed_lang,feed_start_date,feed_end_date,feed_version\n\
+         Translink,https://www.translink.com.au/,en,,,v1\n"
+    );
+    // Required files are written even when empty, optional ones are left out.
+    assert_eq!(read("stops.txt").lines().count(), 1);
+    assert_eq!(
+        zip.file_names()
+            .filter(|f| *f == "calendar_dates.txt")
+            .count(),
+        0
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_write_gtfs_subset(pool: PgPool) -> sqlx::Result<()> {
+    use crate::export::{gtfs::write_gtfs, subset::FeedSubset};
+    use crate::tiles::geometry::BBox;
+    use std::io::{Cursor, Read};
+
+    let mut conn = pool.begin().await?;
+    insert_agency(
+        &Agency {
+            agency_id: None,
+            agency_name: "Translink".into(),
+            agency_url: "https://translink.com.au/".into(),
+            agency_timezone: "Australia/Brisbane".into(),
+            agency_lang: None,
+            agency_phone: None,
+        },
+        &mut *conn,
+    )
+    .await?;
+    let stops = [
+        ("place_a", Some(1), None, -27.470, 153.020),
+        ("a1", Some(0), Some("place_a"), -27.470, 153.020),
+        ("b", Some(0), None, -27.480, 153.030),
+        ("c", Some(0), None, -27.600, 153.200),
+        ("unused", Some(0), None, -27.490, 153.040),
+    ];
+    for (stop_id, location_type, parent_station, lat, lon) in stops {
+        let stop = Stop {
+            stop_id: stop_id.into(),
+            stop_code: None,
+            stop_name: Some(stop_id.into()),
+            stop_desc: None,
+            stop_lat: Some(lat),
+            stop_lon: Some(lon),
+            zone_id: None,
+            stop_url: None,
+            location_type,
+            parent_station: parent_station.map(String::from),
+            platform_code: None,
+        };
+        insert_stop(&stop, &mut *conn).await?;
+    }
+    for route_id in ["r1", "r2"] {
+        let route = Route {
+            route_id: route_id.into(),
+            agency_id: None,
+            route_short_name: Some(route_id.into()),
+            route_long_name: None,
+            route_desc: None,
+            route_type: 3,
+            route_url: None,
+            route_color: None,
+            route_text_color: None,
+        };
+        insert_route(&route, &mut *conn).await?;
+    }
+    insert_calendar(
+        &Calendar {
+            service_id: "weekdays".into(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: false,
+            sunday: false,
+            start_date: NaiveDate::from_ymd_opt(2025, 7, 1).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2025, 12, 31).unwrap(),
+        },
+        &mut *conn,
+    )
+    .await?;
+    insert_calendar_date(
+        &CalendarDate {
+            service_id: "weekdays".into(),
+            date: NaiveDate::from_ymd_opt(2025, 12, 25).unwrap(),
+            exception_type: 2,
+        },
+        &mut *conn,
+    )
+    .await?;
+    for shape_pt_sequence in 1..=2 {
+        let shape = Shape {
+            shape_id: "s1".into(),
+            shape_pt_lat: -27.47,
+            shape_pt_lon: 153.02,
+            shape_pt_sequence,
+        };
+        insert_shape(&shape, &mut *conn).await?;
+    }
+    let trips = [
+        ("t1", "r1", Some("s1"), &["a1", "b", "c"][..]),
+        ("t2", "r2", None, &["b", "c"]),
+    ];
+    for (trip_id, route_id, shape_id, stop_ids) in trips {
+        let trip = Trip {
+            route_id: route_id.into(),
+            service_id: "weekdays".into(),
+            trip_id: trip_id.into(),
+            trip_headsign: None,
+            direction_id: None,
+            block_id: None,
+            shape_id: shape_id.map(String::from),
+        };
+        insert_trip(&trip, &mut *conn).await?;
+        for (i, stop_id) in stop_ids.iter().enumerate() {
+            let time = TimeDelta::try_minutes(8 * 60 + i as i64 * 5)
+                .unwrap()
+                .try_into()
+                .unwrap();
+            let stop_time = StopTime {
+                trip_id: trip_id.into(),
+                arrival_time: Some(time),
+                departure_time: time,
+                stop_id: stop_id.to_string(),
+                stop_sequence: i as i32 + 1,
+                pickup_type: 0,
+                drop_off_type: 0,
+            };
+            insert_stop_time(&stop_time, &mut *conn).await?;
+        }
+    }
+    conn.commit().await?;
+
+    let db = super::Db(pool);
+    // First column of every row of a file, or None if it was left out.
+    let export = async |subset: FeedSubset| {
+        let zip = write_gtfs(&db, &subset, None, Cursor::new(vec![]))
+            .await
+            .unwrap();
+        let mut zip = zip::ZipArchive::new(zip).unwrap();
+        move |name: &str| -> Option<Vec<String>> {
+            let mut text = String::new();
+            zip.by_name(name).ok()?.read_to_string(&mut text).unwrap();
+            Some(
+                text.lines()
+                    .skip(1)
+                    .map(|l| l.split(',').next().unwrap().to_owned())
+                    .collect(),
+            )
+        }
+    };
+
+    let mut read = export(FeedSubset {
+        route_ids: Some(vec!["r1".into()]),
+        dates: Some((
+            NaiveDate::from_ymd_opt(2025, 7, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2025, 7, 31).unwrap(),
+        )),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(read("trips.txt").unwrap(), vec!["r1"]);
+    assert_eq!(read("stop_times.txt").unwrap(), vec!["t1", "t1", "t1"]);
+    assert_eq!(read("stops.txt").unwrap(), vec!["a1", "b", "c", "place_a"]);
+    assert_eq!(read("shapes.txt").unwrap(), vec!["s1", "s1"]);
+    assert_eq!(read("agency.txt").unwrap(), vec![""]);
+    // The calendar is clipped to the range, so Christmas falls outside it.
+    assert_eq!(read("calendar.txt").unwrap(), vec!["weekdays"]);
+    assert_eq!(read("calendar_dates.txt"), None);
+
+    // Only a1 and b are in the box, so t2 is left with one stop and dropped.
+    let mut read = export(FeedSubset {
+        bbox: Some(BBox {
+            min_lon: 153.0,
+            min_lat: -27.5,
+            max_lon: 153.035,
+            max_lat: -27.4,
+        }),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(read("stop_times.txt").unwrap(), vec!["t1", "t1"]);
+    assert_eq!(read("routes.txt").unwrap(), vec!["r1"]);
+    assert_eq!(read("stops.txt").unwrap(), vec!["a1", "b", "place_a"]);
+
+    // No service on a weekend.
+    let saturday = NaiveDate::from_ymd_opt(2025, 7, 5).unwrap();
+    let mut read = export(FeedSubset {
+        dates: Some((saturday, saturday)),
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(read("trips.txt").unwrap(), Vec::<String>::new());
+    assert_eq!(read("calendar.txt"), None);
+    Ok(())
+}
+
+/// A small static feed zip, leaving out the named file.
+fn static_gtfs_zip(without: &str) -> std::sync::Arc<Vec<u8>> {
+    use std::io::{Cursor, Write};
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in [
+        (
+            "agency.txt",
+            "agency_name,agency_url,agency_timezone\nTranslink,https://translink.com.au/,Australia/Brisbane\n",
+        ),
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon\n1,A,-27.46,153.02\n2,B,-27.47,153.03\n3,C,bad,153.03\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,route_short_name,route_type\n27-3251,27,3\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id\n27-3251,ATS_HBL 23-34836,t1\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nt1,,08:00:00,1,1\nt1,08:05:00,08:05:00,2,2\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\nATS_HBL 23-34836,1,1,1,1,1,0,0,20231114,20231117\n",
+        ),
+    ] {
+        if name == without {
+            continue;
+        }
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    std::sync::Arc::new(zip.finish().unwrap().into_inner())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_insert_static_gtfs(pool: PgPool) -> sqlx::Result<()> {
+    let zip = static_gtfs_zip("");
+
+    let db = super::Db(pool);
+    let metrics = crate::gtfs::StaticGtfs::new(zip, LastUpdate::new("SEQ".into()))
+        .insert_db(db.clone(), ImportMode::Full)
+        .await
+        .unwrap();
+
+    let rows: Vec<(&str, u64)> = metrics.tables.iter().map(|t| (t.table, t.rows)).collect();
+    assert_eq!(
+        rows,
+        [
+            ("agency", 1),
+            ("stops", 2),
+            ("routes", 1),
+            ("trips", 1),
+            ("stop_times", 2),
+            ("calendar", 1),
+            ("calendar_dates", 0),
+            ("shapes", 0),
+            ("feed_info", 0),
+        ]
+    );
+    assert_eq!(metrics.rows(), 8);
+    let stops = metrics
+        .files
+        .iter()
+        .find(|f| f.file == "stops.txt")
+        .unwrap();
+    assert_eq!((stops.parsed, stops.skipped), (2, 1));
+
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM stop_times")
+        .fetch_one(&db.0)
+        .await?;
+    assert_eq!(count, Some(2));
+    assert!(get_feed_last_update("SEQ".into(), &db.0).await?.is_some());
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_insert_static_gtfs_missing_file(pool: PgPool) -> sqlx::Result<()> {
+    let db = super::Db(pool);
+    let gtfs = crate::gtfs::StaticGtfs::new(
+        static_gtfs_zip("stop_times.txt"),
+        LastUpdate::new("SEQ".into()),
+    );
+    assert!(gtfs.insert_db(db.clone(), ImportMode::Full).await.is_err());
+
+    // Nothing is merged, not even the tables that were staged fine.
+    assert!(get_feed_last_update("SEQ".into(), &db.0).await?.is_none());
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM stops")
+        .fetch_one(&db.0)
+        .await?;
+    assert_eq!(count, Some(0));
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_failed_import_leaves_feed(pool: PgPool) -> sqlx::Result<()> {
+    let db = super::Db(pool);
+    let first = LastUpdate::new("SEQ".into());
+    crate::gtfs::static_gtfs::parse(static_gtfs_zip(""))
+        .insert_db(&db, &first, ImportMode::Full)
+        .await
+        .unwrap();
+
+    // The new feed changes stops, but never finishes loading.
+    let mut feed = crate::gtfs::static_gtfs::parse(static_gtfs_zip(""))
+        .collect()
+        .await
+        .unwrap();
+    feed.stops[0].stop_name = Some("Renamed".into());
+    let mut feed = crate::bridge::static_bridge::GtfsDbModel::from(feed);
+    feed.stop_times = crate::gtfs::static_gtfs::parse(static_gtfs_zip("stop_times.txt")).stop_times;
+    let second = LastUpdate {
+        feed_region: "SEQ".into(),
+        feed_last_update: first.feed_last_update + TimeDelta::days(1),
+    };
+    assert!(
+        feed.insert_db(&db, &second, ImportMode::Incremental)
+            .await
+            .is_err()
+    );
+
+    // Everyone still sees the whole of the first feed.
+    let names = sqlx::query_scalar!("SELECT stop_name FROM stops ORDER BY stop_id")
+        .fetch_all(&db.0)
+        .await?;
+    assert!(!names.contains(&Some("Renamed".into())));
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM stop_times")
+        .fetch_one(&db.0)
+        .await?;
+    assert_eq!(count, Some(2));
+    assert_eq!(
+        get_feed_last_update("SEQ".into(), &db.0).await?,
+        Some(first.feed_last_update)
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_insert_static_gtfs_incremental(pool: PgPool) -> sqlx::Result<()> {
+    let db = super::Db(pool);
+    let last_update = LastUpdate::new("SEQ".into());
+    crate::gtfs::static_gtfs::parse(static_gtfs_zip(""))
+        .insert_db(&db, &last_update, ImportMode::Full)
+        .await
+        .unwrap();
+
+    // Stop 1 goes, taking its stop time with it, and stop 2 is renamed.
+    let mut feed = crate::gtfs::static_gtfs::parse(static_gtfs_zip(""))
+        .collect()
+        .await
+        .unwrap();
+    feed.stops.retain(|s| s.stop_id != "1");
+    feed.stops[0].stop_name = Some("B2".into());
+    feed.stop_times.retain(|st| st.stop_id != "1");
+    let metrics = feed
+        .insert_db(&db, &last_update, ImportMode::Incremental)
+        .await
+        .unwrap();
+
+    let table = |name: &str| {
+        let t = metrics.tables.iter().find(|t| t.table == name).unwrap();
+        (t.rows, t.changed, t.deleted)
+    };
+    assert_eq!(table("stops"), (1, 1, 1));
+    // Deleted before its stop, rather than by the cascade.
+    assert_eq!(table("stop_times"), (1, 0, 1));
+    assert_eq!(table("agency"), (1, 0, 0));
+    assert_eq!(table("trips"), (1, 0, 0));
+
+    let stops = sqlx::query_as!(Stop, "SELECT * FROM stops ORDER BY stop_id")
+        .fetch_all(&db.0)
+        .await?;
+    assert_eq!(stops.len(), 1);
+    assert_eq!(stops[0].stop_name.as_deref(), Some("B2"));
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM stop_times")
+        .fetch_one(&db.0)
+        .await?;
+    assert_eq!(count, Some(1));
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_incremental_deletes_across_tables(pool: PgPool) -> sqlx::Result<()> {
+    let db = super::Db(pool);
+    let last_update = LastUpdate::new("SEQ".into());
+    crate::gtfs::static_gtfs::parse(static_gtfs_zip(""))
+        .insert_db(&db, &last_update, ImportMode::Full)
+        .await
+        .unwrap();
+
+    // Both chains into stop_times lose rows at once: the route with its trip, and a stop.
+    let mut feed = crate::gtfs::static_gtfs::parse(static_gtfs_zip(""))
+        .collect()
+        .await
+        .unwrap();
+    feed.routes.clear();
+    feed.trips.clear();
+    feed.stop_times.clear();
+    feed.stops.retain(|s| s.stop_id != "1");
+    let metrics = feed
+        .insert_db(&db, &last_update, ImportMode::Incremental)
+        .await
+        .unwrap();
+
+    let deleted = |name: &str| {
+        let t = metrics.tables.iter().find(|t| t.table == name).unwrap();
+        t.deleted
+    };
+    assert_eq!(
+        ["stop_times", "trips", "routes", "stops"].map(deleted),
+        [2, 1, 1, 1]
+    );
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM stops) AS "stops!",
+            (SELECT COUNT(*) FROM routes) AS "routes!",
+            (SELECT COUNT(*) FROM trips) AS "trips!",
+            (SELECT COUNT(*) FROM stop_times) AS "stop_times!"
+        "#
+    )
+    .fetch_one(&db.0)
+    .await?;
+    assert_eq!(
+        (counts.stops, counts.routes, counts.trips, counts.stop_times),
+        (1, 0, 0, 0)
+    );
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_resume_static_import(pool: PgPool) -> sqlx::Result<()> {
+    use crate::db::queries::get_import_progress;
+    use crate::gtfs::StaticGtfs;
+
+    let db = super::Db(pool);
+    let interrupted = StaticGtfs::new(
+        static_gtfs_zip("stop_times.txt"),
+        LastUpdate::new("SEQ".into()),
+    );
+    assert!(
+        interrupted
+            .insert_db(db.clone(), ImportMode::Full)
+            .await
+            .is_err()
+    );
+
+    let progress = get_import_progress("SEQ", &db.0).await?;
+    let stops = progress.iter().find(|p| p.table_name == "stops").unwrap();
+    assert!(stops.completed_at.is_some());
+    assert_eq!((stops.rows_done, stops.rows_total), (2, Some(3)));
+    let stop_times = progress.iter().find(|p| p.table_name == "stop_times");
+    assert!(stop_times.is_none_or(|p| p.completed_at.is_none()));
+
+    let metrics = StaticGtfs::new(static_gtfs_zip(""), LastUpdate::new("SEQ".into()))
+        .insert_db(db.clone(), ImportMode::Full)
+        .await
+        .unwrap();
+    let skipped: Vec<&str> = metrics
+        .tables
+        .iter()
+        .filter(|t| t.skipped)
+        .map(|t| t.table)
+        .collect();
+    // Every table but the missing one was staged, so only it has to be loaded.
+    // Optional files that aren't in the feed have no checksum, so they're never skipped.
+    assert_eq!(skipped, ["agency", "stops", "routes", "trips", "calendar"]);
+    let stop_times = metrics.tables.iter().find(|t| t.table == "stop_times");
+    assert_eq!(stop_times.map(|t| t.rows), Some(2));
+    let stops = metrics.tables.iter().find(|t| t.table == "stops");
+    assert_eq!(stops.map(|t| t.changed), Some(2));
+
+    // A finished import leaves nothing to resume.
+    assert!(get_import_progress("SEQ", &db.0).await?.is_empty());
+    assert!(get_feed_last_update("SEQ".into(), &db.0).await?.is_some());
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_tables_stage_concurrently(pool: PgPool) -> sqlx::Result<()> {
+    use crate::db::queries::get_import_progress;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    let db = super::Db(pool);
+    let mut feed = crate::gtfs::static_gtfs::parse(static_gtfs_zip(""));
+    // Agencies hold back until stops have been staged, which would never happen
+    // if tables were staged one after another in foreign key order.
+    let pool = db.0.clone();
+    let stops_staged = async move {
+        while !get_import_progress("SEQ", &pool)
+            .await
+            .unwrap()
+            .iter()
+            .any(|p| p.table_name == "stops" && p.completed_at.is_some())
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    let agencies = std::mem::replace(&mut feed.agencies.rows, futures::stream::empty().boxed());
+    feed.agencies.rows = futures::stream::once(stops_staged)
+        .filter_map(|()| async { None })
+        .chain(agencies)
+        .boxed();
+
+    let last_update = LastUpdate::new("SEQ".into());
+    let import = feed.insert_db(&db, &last_update, ImportMode::Full);
+    let metrics = tokio::time::timeout(Duration::from_secs(30), import)
+        .await
+        .expect("Tables should be staged at the same time")
+        .unwrap();
+    assert_eq!(metrics.rows(), 8);
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_readiness(pool: PgPool) -> sqlx::Result<()> {
+    let db = super::Db(pool.clone());
+    let last_poll = crate::health::LastPoll::default();
+
+    assert!(crate::health::live(&db).await.ok);
+    let report = crate::health::ready(&db, &last_poll).await;
+    let failed: Vec<&str> = report
+        .checks
+        .iter()
+        .filter(|c| !c.ok)
+        .map(|c| c.name)
+        .collect();
+    assert_eq!(failed, ["static", "realtime"]);
+
+    let mut conn = pool.acquire().await?;
+    insert_last_update(
+        &LastUpdate {
+            feed_region: "SEQ".to_owned(),
+            feed_last_update: Utc::now().naive_utc(),
+        },
+        &mut conn,
+    )
+    .await?;
+    let feed = FeedInfo {
+        feed_publisher_name: "Translink".into(),
+        feed_publisher_url: "https://translink.com.au/".into(),
+        feed_lang: Some("en".into()),
+        feed_start_date: None,
+        feed_end_date: Some(Utc::now().date_naive() + TimeDelta::days(30)),
+    };
+    insert_feed_info(&feed, &mut conn).await?;
+    last_poll.succeeded(Utc::now()).await;
+
+    assert!(crate::health::ready(&db, &last_poll).await.ok);
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_timezone(pool: PgPool) -> sqlx::Result<()> {
+    let db = super::Db(pool.clone());
+    assert_eq!(db.timezone().await.unwrap(), crate::vars::DEFAULT_TIMEZONE);
+
+    let agency = Agency {
+        agency_id: None,
+        agency_name: "Surfside".into(),
+        agency_url: "https://surfside.com.au/".into(),
+        agency_timezone: "Australia/Sydney".into(),
+        agency_lang: None,
+        agency_phone: None,
+    };
+    insert_agency(&agency, &mut *pool.acquire().await?).await?;
+    assert_eq!(db.timezone().await.unwrap(), chrono_tz::Australia::Sydney);
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "super::MIGRATOR")]
+async fn test_diff_loaded_feed(pool: PgPool) -> sqlx::Result<()> {
+    use crate::diff::{Feed, diff};
+
+    let zip = static_gtfs_zip("");
+    let db = super::Db(pool);
+    crate::gtfs::StaticGtfs::new(zip.clone(), LastUpdate::new("SEQ".into()))
+        .insert_db(db.clone(), ImportMode::Full)
+        .await
+        .unwrap();
+    let parse = || crate::gtfs::static_gtfs::parse(zip.clone()).collect();
+
+    // Streaming stop times out of the db gives the same timetables as the zip.
+    let loaded = Feed::load(&db).await.unwrap();
+    let parsed = Feed::from_rows(parse().await.unwrap());
+    assert!(diff(&loaded, &parsed).is_empty());
+
+    let mut changed = parse().await.unwrap();
+    changed.stop_times[1].stop_id = "1".into();
+    let changed = diff(&loaded, &Feed::from_rows(changed));
+    assert_eq!(changed.timetables[0].trips_restopped, 1);
+    Ok(())
+}
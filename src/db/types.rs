@@ -4,20 +4,48 @@
 //! Should directly map to the schema tables.
 
 use chrono::{NaiveDate, NaiveDateTime, Timelike, Utc};
+use serde::Serialize;
 use sqlx::{FromRow, PgConnection, postgres::types::PgInterval};
 
 use crate::db::{self, Db, queries::*};
 
+/// Inserts a row, or updates the one with the same key if anything's changed.
+/// Gives the number of rows written, so 0 when it was already up to date.
 pub trait InsertDB: Sized + Send + Sync {
     fn insert(
         &self,
         pool: &mut PgConnection,
+    ) -> impl std::future::Future<Output = Result<u64, sqlx::Error>> + std::marker::Send;
+}
+
+/// Static tables that are loaded through a staging table, so a whole feed can be
+/// swapped in at once. Rows with the same key as one already staged replace it.
+pub trait StageDB: Sized + Send + Sync {
+    fn stage(
+        &self,
+        pool: &mut PgConnection,
+    ) -> impl std::future::Future<Output = Result<u64, sqlx::Error>> + std::marker::Send;
+
+    /// Empties the staging table.
+    fn clear_staged(
+        pool: &mut PgConnection,
     ) -> impl std::future::Future<Output = Result<(), sqlx::Error>> + std::marker::Send;
+
+    /// Upserts every staged row into the live table, giving how many changed.
+    fn merge_staged(
+        pool: &mut PgConnection,
+    ) -> impl std::future::Future<Output = Result<u64, sqlx::Error>> + std::marker::Send;
+
+    /// Deletes every live row that isn't staged, cascading to anything that references it.
+    fn delete_unstaged(
+        pool: &mut PgConnection,
+    ) -> impl std::future::Future<Output = Result<u64, sqlx::Error>> + std::marker::Send;
 }
 
 /// Representation of agency table rows
 #[derive(Debug, FromRow, PartialEq, Eq)]
 pub struct Agency {
+    pub agency_id: Option<String>,
     pub agency_name: String,
     pub agency_url: String,
     pub agency_timezone: String,
@@ -26,7 +54,7 @@ pub struct Agency {
 }
 
 /// Representation of stops table rows
-#[derive(Debug, FromRow, PartialEq)]
+#[derive(Debug, FromRow, PartialEq, Serialize)]
 pub struct Stop {
     pub stop_id: String,
     pub stop_code: Option<String>,
@@ -42,9 +70,10 @@ pub struct Stop {
 }
 
 /// Representation of routes table rows
-#[derive(Debug, FromRow, PartialEq, Eq)]
+#[derive(Debug, FromRow, PartialEq, Eq, Serialize)]
 pub struct Route {
     pub route_id: String,
+    pub agency_id: Option<String>,
     pub route_short_name: Option<String>,
     pub route_long_name: Option<String>,
     pub route_desc: Option<String>,
@@ -139,58 +168,355 @@ impl LastUpdate {
     }
 }
 
+/// Representation of generated_transfers table rows
+#[derive(Debug, FromRow, PartialEq)]
+pub struct GeneratedTransfer {
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    /// Walking distance, not straight-line.
+    pub distance_m: f64,
+    /// Seconds.
+    pub walk_time: i32,
+}
+
+/// Representation of stop_time_observations table rows
+#[derive(Debug, Clone, FromRow, PartialEq, Eq)]
+pub struct StopTimeObservation {
+    pub trip_id: String,
+    pub service_date: NaiveDate,
+    pub stop_sequence: i32,
+    pub arrival_time: Option<NaiveDateTime>,
+    pub departure_time: Option<NaiveDateTime>,
+    pub arrival_delay: Option<i32>,
+    pub departure_delay: Option<i32>,
+    pub observed_at: NaiveDateTime,
+}
+
+/// Representation of route_performance table rows
+#[derive(Debug, FromRow, PartialEq, Serialize)]
+pub struct RoutePerformance {
+    pub service_date: NaiveDate,
+    pub route_id: String,
+    pub observations: i32,
+    pub on_time: i32,
+    pub early: i32,
+    pub late: i32,
+    /// Seconds, negative for early.
+    pub mean_delay: f64,
+    pub median_delay: f64,
+    pub p90_delay: f64,
+}
+
+/// Representation of stop_performance table rows
+#[derive(Debug, FromRow, PartialEq, Serialize)]
+pub struct StopPerformance {
+    pub service_date: NaiveDate,
+    pub stop_id: String,
+    pub observations: i32,
+    pub on_time: i32,
+    pub early: i32,
+    pub late: i32,
+    /// Seconds, negative for early.
+    pub mean_delay: f64,
+    pub median_delay: f64,
+    pub p90_delay: f64,
+}
+
+/// Representation of hourly_performance table rows
+#[derive(Debug, FromRow, PartialEq, Serialize)]
+pub struct HourlyPerformance {
+    pub service_date: NaiveDate,
+    /// Of the scheduled departure, past 23 for trips after midnight.
+    pub hour: i32,
+    pub observations: i32,
+    pub on_time: i32,
+    pub early: i32,
+    pub late: i32,
+    /// Seconds, negative for early.
+    pub mean_delay: f64,
+    pub median_delay: f64,
+    pub p90_delay: f64,
+}
+
+/// Representation of headway_events table rows
+#[derive(Debug, FromRow, PartialEq, Eq, Serialize)]
+pub struct HeadwayEvent {
+    pub service_date: NaiveDate,
+    pub route_id: String,
+    pub direction_id: Option<bool>,
+    pub stop_id: String,
+    pub stop_sequence: i32,
+    pub trip_id: String,
+    pub previous_trip_id: String,
+    /// "bunching" or "gap".
+    pub kind: String,
+    pub departed: NaiveDateTime,
+    pub headway: i32,
+    pub scheduled_headway: i32,
+}
+
+/// Representation of import_progress table rows
+#[derive(Debug, Clone, FromRow, PartialEq, Eq)]
+pub struct ImportProgress {
+    pub feed_region: String,
+    pub table_name: String,
+    pub checksum: Option<i64>,
+    pub rows_done: i64,
+    pub rows_total: Option<i64>,
+    pub started_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+/// Representation of trip_observations table rows
+#[derive(Debug, Clone, FromRow, PartialEq, Eq)]
+pub struct TripObservation {
+    pub trip_id: String,
+    pub service_date: NaiveDate,
+    pub route_id: Option<String>,
+    pub schedule_relationship: String,
+    pub in_trip_updates: bool,
+    pub in_vehicle_positions: bool,
+    pub first_seen: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+}
+
+/// Representation of trip_statuses table rows
+#[derive(Debug, FromRow, PartialEq, Eq, Serialize)]
+pub struct TripStatus {
+    pub service_date: NaiveDate,
+    pub trip_id: String,
+    pub route_id: Option<String>,
+    /// "missing", "cancelled" or "added".
+    pub status: String,
+    pub scheduled_start: Option<NaiveDateTime>,
+}
+
+/// Representation of daily_trip_reports table rows
+#[derive(Debug, FromRow, PartialEq, Eq, Serialize)]
+pub struct DailyTripReport {
+    pub service_date: NaiveDate,
+    pub scheduled: i32,
+    pub seen: i32,
+    pub missing: i32,
+    pub cancelled: i32,
+    pub added: i32,
+}
+
 impl InsertDB for Agency {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_agency(self, db).await
     }
 }
 impl InsertDB for Stop {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_stop(self, db).await
     }
 }
 impl InsertDB for Route {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_route(self, db).await
     }
 }
 impl InsertDB for Trip {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_trip(self, db).await
     }
 }
 impl InsertDB for StopTime {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_stop_time(self, db).await
     }
 }
 
 impl InsertDB for Calendar {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_calendar(self, db).await
     }
 }
 
 impl InsertDB for CalendarDate {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_calendar_date(self, db).await
     }
 }
 
 impl InsertDB for Shape {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_shape(self, db).await
     }
 }
 
 impl InsertDB for FeedInfo {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_feed_info(self, db).await
     }
 }
 
 impl InsertDB for LastUpdate {
-    async fn insert(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+    async fn insert(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
         insert_last_update(self, db).await
     }
 }
+
+impl StageDB for Agency {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_agency(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_agencies(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_agencies(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_agencies(db).await
+    }
+}
+
+impl StageDB for Stop {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_stop(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_stops(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_stops(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_stops(db).await
+    }
+}
+
+impl StageDB for Route {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_route(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_routes(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_routes(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_routes(db).await
+    }
+}
+
+impl StageDB for Trip {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_trip(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_trips(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_trips(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_trips(db).await
+    }
+}
+
+impl StageDB for StopTime {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_stop_time(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_stop_times(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_stop_times(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_stop_times(db).await
+    }
+}
+
+impl StageDB for Calendar {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_calendar(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_calendars(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_calendars(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_calendars(db).await
+    }
+}
+
+impl StageDB for CalendarDate {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_calendar_date(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_calendar_dates(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_calendar_dates(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_calendar_dates(db).await
+    }
+}
+
+impl StageDB for Shape {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_shape(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_shapes(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_shapes(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_shapes(db).await
+    }
+}
+
+impl StageDB for FeedInfo {
+    async fn stage(&self, db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        stage_feed_info(self, db).await
+    }
+
+    async fn clear_staged(db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        clear_staged_feed_info(db).await
+    }
+
+    async fn merge_staged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        merge_staged_feed_info(db).await
+    }
+
+    async fn delete_unstaged(db: &mut PgConnection) -> Result<u64, sqlx::Error> {
+        delete_unstaged_feed_info(db).await
+    }
+}
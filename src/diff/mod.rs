@@ -0,0 +1,382 @@
+//! DIFF
+//!
+//! What changed between two versions of the static feed: routes, stops and
+//! trips added, removed or modified, stops that moved, and how each route's
+//! timetable changed. Either side can be the feed in the db or a zip.
+//! Trips are matched by trip_id, so a trip given a new id shows as one
+//! removed and one added.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use futures::TryStreamExt;
+use serde::Serialize;
+use sqlx::postgres::types::PgInterval;
+use tracing::{info, instrument};
+
+use crate::{
+    bridge::static_bridge::FeedRows,
+    db::{
+        Db, queries,
+        types::{Route, Stop, StopTime, Trip},
+    },
+    export::gtfs::ToCsv,
+    geo::distance_m,
+    gtfs::{read_zip, static_gtfs},
+};
+
+/// The parts of a feed that get compared.
+#[derive(Debug, Default)]
+pub struct Feed {
+    routes: BTreeMap<String, Route>,
+    stops: BTreeMap<String, Stop>,
+    trips: BTreeMap<String, Trip>,
+    timetables: HashMap<String, Timetable>,
+}
+
+/// A trip's stop times, boiled down to enough to tell how they changed.
+#[derive(Debug, PartialEq)]
+struct Timetable {
+    stops: u64,
+    times: u64,
+}
+
+/// Hashes stop times into timetables a trip at a time, so they needn't all be
+/// held at once. Stop times have to come ordered by trip and stop_sequence.
+#[derive(Default)]
+struct Timetables {
+    done: HashMap<String, Timetable>,
+    trip: Option<(String, DefaultHasher, DefaultHasher)>,
+}
+
+impl Timetables {
+    fn push(mut self, st: StopTime) -> Self {
+        if self.trip.as_ref().is_none_or(|(id, ..)| *id != st.trip_id) {
+            self.finish_trip();
+            self.trip = Some((
+                st.trip_id.clone(),
+                DefaultHasher::new(),
+                DefaultHasher::new(),
+            ));
+        }
+        if let Some((_, stops, times)) = &mut self.trip {
+            st.stop_id.hash(stops);
+            st.arrival_time.as_ref().map(secs).hash(times);
+            secs(&st.departure_time).hash(times);
+        }
+        self
+    }
+
+    fn finish_trip(&mut self) {
+        if let Some((trip_id, stops, times)) = self.trip.take() {
+            let timetable = Timetable {
+                stops: stops.finish(),
+                times: times.finish(),
+            };
+            self.done.insert(trip_id, timetable);
+        }
+    }
+
+    fn finish(mut self) -> HashMap<String, Timetable> {
+        self.finish_trip();
+        self.done
+    }
+}
+
+impl Feed {
+    pub fn new(
+        routes: Vec<Route>,
+        stops: Vec<Stop>,
+        trips: Vec<Trip>,
+        mut stop_times: Vec<StopTime>,
+    ) -> Feed {
+        stop_times
+            .sort_by(|a, b| (&a.trip_id, a.stop_sequence).cmp(&(&b.trip_id, b.stop_sequence)));
+        let timetables = stop_times
+            .into_iter()
+            .fold(Timetables::default(), Timetables::push)
+            .finish();
+        Feed::with_timetables(routes, stops, trips, timetables)
+    }
+
+    fn with_timetables(
+        routes: Vec<Route>,
+        stops: Vec<Stop>,
+        trips: Vec<Trip>,
+        timetables: HashMap<String, Timetable>,
+    ) -> Feed {
+        Feed {
+            routes: routes
+                .into_iter()
+                .map(|r| (r.route_id.clone(), r))
+                .collect(),
+            stops: stops.into_iter().map(|s| (s.stop_id.clone(), s)).collect(),
+            trips: trips.into_iter().map(|t| (t.trip_id.clone(), t)).collect(),
+            timetables,
+        }
+    }
+
+    /// The feed currently in the db. Stop times are streamed in order and only
+    /// kept as timetables, as there are far more of them than anything else.
+    #[instrument(skip(db))]
+    pub async fn load(db: &Db) -> Result<Feed> {
+        let pool = &db.0;
+        let timetables = queries::stream_stop_times(pool)
+            .try_fold(Timetables::default(), |t, st| async { Ok(t.push(st)) })
+            .await?
+            .finish();
+        Ok(Feed::with_timetables(
+            queries::stream_routes(pool).try_collect().await?,
+            queries::stream_stops(pool).try_collect().await?,
+            queries::stream_trips(pool).try_collect().await?,
+            timetables,
+        ))
+    }
+
+    /// A parsed feed, converted the same way as on import.
+    pub fn from_rows(rows: FeedRows) -> Feed {
+        Feed::new(rows.routes, rows.stops, rows.trips, rows.stop_times)
+    }
+
+    /// A feed zip, or a url to one.
+    pub async fn from_path(path: &Path) -> Result<Feed> {
+        let zip = read_zip(&path.to_string_lossy()).await?;
+        Ok(Feed::from_rows(static_gtfs::parse(zip).collect().await?))
+    }
+}
+
+fn secs(interval: &PgInterval) -> i64 {
+    interval.days as i64 * 86400 + interval.microseconds / 1_000_000
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct FeedDiff {
+    pub routes: TableDiff,
+    pub stops: TableDiff,
+    pub moved_stops: Vec<MovedStop>,
+    pub trips: TableDiff,
+    /// Only routes with trips that changed.
+    pub timetables: Vec<RouteTimetableDiff>,
+}
+
+/// Rows added, removed or modified, by id.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct TableDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<Modified>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Modified {
+    pub id: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// A field that changed, with both values as they'd be written in GTFS.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MovedStop {
+    pub stop_id: String,
+    pub distance_m: f64,
+}
+
+/// How many of a route's trips changed. Restopped trips call at different
+/// stops, retimed ones call at the same stops at different times.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RouteTimetableDiff {
+    pub route_id: String,
+    pub trips_added: usize,
+    pub trips_removed: usize,
+    pub trips_restopped: usize,
+    pub trips_retimed: usize,
+}
+
+impl FeedDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == FeedDiff::default()
+    }
+}
+
+/// Everything that changed going from old to new.
+pub fn diff(old: &Feed, new: &Feed) -> FeedDiff {
+    let moved_stops = old
+        .stops
+        .values()
+        .filter_map(|old| {
+            let new = new.stops.get(&old.stop_id)?;
+            let (Some(old_lat), Some(old_lon), Some(new_lat), Some(new_lon)) =
+                (old.stop_lat, old.stop_lon, new.stop_lat, new.stop_lon)
+            else {
+                return None;
+            };
+            let distance_m = distance_m(old_lat, old_lon, new_lat, new_lon);
+            (distance_m > 0.0).then(|| MovedStop {
+                stop_id: old.stop_id.clone(),
+                distance_m,
+            })
+        })
+        .collect();
+
+    let mut timetables: BTreeMap<String, RouteTimetableDiff> = BTreeMap::new();
+    for (trip_id, trip) in &new.trips {
+        if !old.trips.contains_key(trip_id) {
+            route(&mut timetables, trip).trips_added += 1;
+            continue;
+        }
+        match (old.timetables.get(trip_id), new.timetables.get(trip_id)) {
+            (Some(old), Some(new)) if old.stops != new.stops => {
+                route(&mut timetables, trip).trips_restopped += 1
+            }
+            (Some(old), Some(new)) if old.times != new.times => {
+                route(&mut timetables, trip).trips_retimed += 1
+            }
+            (Some(_), Some(_)) | (None, None) => {}
+            _ => route(&mut timetables, trip).trips_restopped += 1,
+        }
+    }
+    for (trip_id, trip) in &old.trips {
+        if !new.trips.contains_key(trip_id) {
+            route(&mut timetables, trip).trips_removed += 1;
+        }
+    }
+
+    FeedDiff {
+        routes: diff_table(&old.routes, &new.routes),
+        stops: diff_table(&old.stops, &new.stops),
+        moved_stops,
+        trips: diff_table(&old.trips, &new.trips),
+        timetables: timetables.into_values().collect(),
+    }
+}
+
+/// The timetable diff for a trip's route, started if it's the first change on it.
+fn route<'a>(
+    timetables: &'a mut BTreeMap<String, RouteTimetableDiff>,
+    trip: &Trip,
+) -> &'a mut RouteTimetableDiff {
+    timetables
+        .entry(trip.route_id.clone())
+        .or_insert_with(|| RouteTimetableDiff {
+            route_id: trip.route_id.clone(),
+            ..Default::default()
+        })
+}
+
+fn diff_table<T: ToCsv>(old: &BTreeMap<String, T>, new: &BTreeMap<String, T>) -> TableDiff {
+    let mut diff = TableDiff {
+        added: new
+            .keys()
+            .filter(|id| !old.contains_key(*id))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    for (id, old_row) in old {
+        let Some(new_row) = new.get(id) else {
+            diff.removed.push(id.clone());
+            continue;
+        };
+        let changes: Vec<FieldChange> = T::header()
+            .into_iter()
+            .zip(old_row.record().into_iter().zip(new_row.record()))
+            .filter(|(_, (old, new))| old != new)
+            .map(|(field, (old, new))| FieldChange { field, old, new })
+            .collect();
+        if !changes.is_empty() {
+            diff.modified.push(Modified {
+                id: id.clone(),
+                changes,
+            });
+        }
+    }
+    diff
+}
+
+/// Routes and stops are listed one by one, trips are only counted as there are so many.
+impl fmt::Display for FeedDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for (name, table, list) in [
+            ("Routes", &self.routes, true),
+            ("Stops", &self.stops, true),
+            ("Trips", &self.trips, false),
+        ] {
+            writeln!(
+                f,
+                "{name}: {} added, {} removed, {} modified",
+                table.added.len(),
+                table.removed.len(),
+                table.modified.len()
+            )?;
+            if !list {
+                continue;
+            }
+            for id in &table.added {
+                writeln!(f, "  + {id}")?;
+            }
+            for id in &table.removed {
+                writeln!(f, "  - {id}")?;
+            }
+            for modified in &table.modified {
+                let changes: Vec<String> = modified
+                    .changes
+                    .iter()
+                    .map(|c| format!("{} {:?} -> {:?}", c.field, c.old, c.new))
+                    .collect();
+                writeln!(f, "  ~ {}: {}", modified.id, changes.join(", "))?;
+            }
+        }
+        if !self.moved_stops.is_empty() {
+            writeln!(f, "Moved stops: {}", self.moved_stops.len())?;
+            for moved in &self.moved_stops {
+                writeln!(f, "  {}: {:.0} m", moved.stop_id, moved.distance_m)?;
+            }
+        }
+        if !self.timetables.is_empty() {
+            writeln!(f, "Timetables:")?;
+            for route in &self.timetables {
+                writeln!(
+                    f,
+                    "  {}: {} added, {} removed, {} restopped, {} retimed",
+                    route.route_id,
+                    route.trips_added,
+                    route.trips_removed,
+                    route.trips_restopped,
+                    route.trips_retimed
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a diff as both a summary and JSON, named for when it was made.
+pub fn write_diff(dir: &Path, diff: &FeedDiff, at: DateTime<Local>) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("feed-diff-{}", at.format("%Y%m%dT%H%M%S")));
+    fs::write(path.with_extension("txt"), diff.to_string())?;
+    fs::write(
+        path.with_extension("json"),
+        serde_json::to_string_pretty(diff)?,
+    )?;
+    info!(path = %path.display(), "Wrote feed diff");
+    Ok(path)
+}
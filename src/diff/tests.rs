@@ -0,0 +1,184 @@
+//! Diff tests
+//!
+//! Tests diffing two small feeds built in memory.
+
+use chrono::TimeDelta;
+
+use super::*;
+
+fn route(route_id: &str, route_color: &str) -> Route {
+    Route {
+        route_id: route_id.into(),
+        agency_id: None,
+        route_short_name: Some(route_id.into()),
+        route_long_name: None,
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: Some(route_color.into()),
+        route_text_color: None,
+    }
+}
+
+fn stop(stop_id: &str, lat: f64, lon: f64) -> Stop {
+    Stop {
+        stop_id: stop_id.into(),
+        stop_code: None,
+        stop_name: Some(stop_id.into()),
+        stop_desc: None,
+        stop_lat: Some(lat),
+        stop_lon: Some(lon),
+        zone_id: None,
+        stop_url: None,
+        location_type: Some(0),
+        parent_station: None,
+        platform_code: None,
+    }
+}
+
+fn trip(trip_id: &str, route_id: &str) -> Trip {
+    Trip {
+        route_id: route_id.into(),
+        service_id: "weekdays".into(),
+        trip_id: trip_id.into(),
+        trip_headsign: None,
+        direction_id: None,
+        block_id: None,
+        shape_id: None,
+    }
+}
+
+/// Stop times calling at each stop five minutes apart, from start minutes after midnight.
+fn stop_times(trip_id: &str, stop_ids: &[&str], start: i64) -> Vec<StopTime> {
+    stop_ids
+        .iter()
+        .enumerate()
+        .map(|(i, stop_id)| {
+            let time: PgInterval = TimeDelta::try_minutes(start + i as i64 * 5)
+                .unwrap()
+                .try_into()
+                .unwrap();
+            StopTime {
+                trip_id: trip_id.into(),
+                arrival_time: Some(time),
+                departure_time: time,
+                stop_id: stop_id.to_string(),
+                stop_sequence: i as i32 + 1,
+                pickup_type: 0,
+                drop_off_type: 0,
+            }
+        })
+        .collect()
+}
+
+fn old_feed() -> Feed {
+    Feed::new(
+        vec![route("r1", "8DC63F"), route("r2", "E463A4")],
+        vec![
+            stop("a", -27.47, 153.02),
+            stop("b", -27.48, 153.03),
+            stop("c", -27.49, 153.04),
+        ],
+        vec![trip("t1", "r1"), trip("t2", "r1"), trip("t3", "r2")],
+        [
+            stop_times("t1", &["a", "b"], 480),
+            stop_times("t2", &["a", "b"], 500),
+            stop_times("t3", &["b", "c"], 480),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+    )
+}
+
+#[test]
+fn test_diff_same_feed() {
+    let diff = diff(&old_feed(), &old_feed());
+    assert!(diff.is_empty());
+    assert_eq!(diff.to_string(), "No changes\n");
+}
+
+#[test]
+fn test_diff() {
+    let mut b = stop("b", -27.48, 153.03);
+    b.stop_name = Some("b renamed".into());
+    let new = Feed::new(
+        vec![route("r1", "000000"), route("r3", "E463A4")],
+        vec![stop("a", -27.47, 153.021), b, stop("d", -27.5, 153.05)],
+        vec![trip("t1", "r1"), trip("t2", "r1"), trip("t4", "r3")],
+        [
+            stop_times("t1", &["a", "b"], 490),
+            stop_times("t2", &["b", "a"], 500),
+            stop_times("t4", &["a", "d"], 480),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+    );
+    let diff = diff(&old_feed(), &new);
+
+    assert_eq!(diff.routes.added, vec!["r3"]);
+    assert_eq!(diff.routes.removed, vec!["r2"]);
+    assert_eq!(
+        diff.routes.modified,
+        vec![Modified {
+            id: "r1".into(),
+            changes: vec![FieldChange {
+                field: "route_color",
+                old: "8DC63F".into(),
+                new: "000000".into(),
+            }],
+        }]
+    );
+
+    assert_eq!(diff.stops.added, vec!["d"]);
+    assert_eq!(diff.stops.removed, vec!["c"]);
+    let modified: Vec<(&str, Vec<&str>)> = diff
+        .stops
+        .modified
+        .iter()
+        .map(|m| (m.id.as_str(), m.changes.iter().map(|c| c.field).collect()))
+        .collect();
+    assert_eq!(
+        modified,
+        vec![("a", vec!["stop_lon"]), ("b", vec!["stop_name"])]
+    );
+    assert_eq!(diff.moved_stops.len(), 1);
+    assert_eq!(diff.moved_stops[0].stop_id, "a");
+    assert!((diff.moved_stops[0].distance_m - 98.6).abs() < 1.0);
+
+    assert_eq!(diff.trips.added, vec!["t4"]);
+    assert_eq!(diff.trips.removed, vec!["t3"]);
+    assert!(diff.trips.modified.is_empty());
+    assert_eq!(
+        diff.timetables,
+        vec![
+            RouteTimetableDiff {
+                route_id: "r1".into(),
+                trips_restopped: 1,
+                trips_retimed: 1,
+                ..Default::default()
+            },
+            RouteTimetableDiff {
+                route_id: "r2".into(),
+                trips_removed: 1,
+                ..Default::default()
+            },
+            RouteTimetableDiff {
+                route_id: "r3".into(),
+                trips_added: 1,
+                ..Default::default()
+            },
+        ]
+    );
+
+    let summary = diff.to_string();
+    assert!(summary.contains("Routes: 1 added, 1 removed, 1 modified\n"));
+    assert!(summary.contains("  ~ r1: route_color \"8DC63F\" -> \"000000\"\n"));
+    assert!(summary.contains("Trips: 1 added, 1 removed, 0 modified\nMoved stops: 1\n"));
+    assert!(summary.contains("  r1: 0 added, 0 removed, 1 restopped, 1 retimed\n"));
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(json["routes"]["added"], serde_json::json!(["r3"]));
+    assert_eq!(json["timetables"][2]["trips_added"], 1);
+}
@@ -0,0 +1,130 @@
+//! GeoJSON export of stops and route shapes.
+//!
+//! Stops become Points, shapes become LineStrings carrying their route's
+//! properties. Lines get a simplestyle `stroke` so most viewers colour them
+//! by route out of the box. Isochrones become a MultiPolygon per band.
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::{info, instrument};
+
+use super::{css_color, route_lines};
+use crate::{
+    db::{Db, queries},
+    routing::isochrone::{Isochrone, MultiPolygon},
+};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point([f64; 2]),
+    LineString(Vec<[f64; 2]>),
+    MultiPolygon(MultiPolygon),
+}
+
+/// Every stop with coordinates, as Points.
+#[instrument(skip(db))]
+pub async fn stops(db: &Db) -> Result<FeatureCollection> {
+    let features = queries::get_stops(&db.0)
+        .await?
+        .into_iter()
+        .filter_map(|stop| {
+            Some(Feature {
+                geometry: Geometry::Point([stop.stop_lon?, stop.stop_lat?]),
+                properties: json!({
+                    "stop_id": stop.stop_id,
+                    "stop_code": stop.stop_code,
+                    "stop_name": stop.stop_name,
+                    "zone_id": stop.zone_id,
+                    "stop_url": stop.stop_url,
+                    "location_type": stop.location_type,
+                    "parent_station": stop.parent_station,
+                    "platform_code": stop.platform_code,
+                }),
+            })
+        })
+        .collect();
+    Ok(FeatureCollection { features })
+}
+
+/// Every shape as a LineString, styled with its route's colour.
+#[instrument(skip(db))]
+pub async fn routes(db: &Db) -> Result<FeatureCollection> {
+    let features = route_lines(db)
+        .await?
+        .into_iter()
+        .map(|line| Feature {
+            geometry: Geometry::LineString(
+                line.points.iter().map(|&(lon, lat)| [lon, lat]).collect(),
+            ),
+            properties: json!({
+                "shape_id": line.shape_id,
+                "route_id": line.route.route_id,
+                "route_short_name": line.route.route_short_name,
+                "route_long_name": line.route.route_long_name,
+                "route_type": line.route.route_type,
+                "route_color": css_color(&line.route.route_color),
+                "route_text_color": css_color(&line.route.route_text_color),
+                "stroke": css_color(&line.route.route_color),
+            }),
+        })
+        .collect();
+    Ok(FeatureCollection { features })
+}
+
+/// Each band as a MultiPolygon, largest first so smaller bands draw on top,
+/// then every reached stop as a Point with its travel time.
+pub fn isochrone(isochrone: &Isochrone) -> FeatureCollection {
+    let bands = isochrone.bands.iter().rev().map(|band| Feature {
+        geometry: Geometry::MultiPolygon(band.area.clone()),
+        properties: json!({
+            "travel_time": band.travel_time,
+            "minutes": band.travel_time / 60,
+        }),
+    });
+    let stops = isochrone.stops.iter().map(|stop| Feature {
+        geometry: Geometry::Point([stop.lon, stop.lat]),
+        properties: json!({
+            "stop_id": stop.stop_id,
+            "stop_name": stop.name,
+            "travel_time": stop.travel_time,
+        }),
+    });
+    FeatureCollection {
+        features: bands.chain(stops).collect(),
+    }
+}
+
+/// Writes stops.geojson and routes.geojson into the given directory.
+#[instrument(skip(db))]
+pub async fn write_geojson(db: &Db, dir: &Path) -> Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(
+        dir.join("stops.geojson"),
+        serde_json::to_vec(&stops(db).await?)?,
+    )
+    .await?;
+    tokio::fs::write(
+        dir.join("routes.geojson"),
+        serde_json::to_vec(&routes(db).await?)?,
+    )
+    .await?;
+    info!("Wrote GeoJSON export");
+    Ok(())
+}
@@ -0,0 +1,308 @@
+//! Writes the db back out as a GTFS feed zip.
+//!
+//! Every stored GTFS table goes back to its spec file, with the spec's
+//! formatting: dates as YYYYMMDD, times as HH:MM:SS (past 24 hours for
+//! trips after midnight), booleans as 0/1 and colours as bare hex.
+
+use std::{
+    fs::{self, File},
+    io::{Seek, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+use sqlx::postgres::types::PgInterval;
+use tempfile::NamedTempFile;
+use tracing::{info, instrument};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use super::subset::{self, FeedSubset};
+use crate::db::{Db, queries, types::*};
+
+/// A db type that is a row of a GTFS file.
+pub trait ToCsv {
+    fn header() -> Vec<&'static str>;
+    fn record(&self) -> Vec<String>;
+}
+
+/// How a field is written in GTFS.
+trait CsvField {
+    fn csv(&self) -> String;
+}
+
+impl CsvField for String {
+    fn csv(&self) -> String {
+        self.clone()
+    }
+}
+
+impl CsvField for i32 {
+    fn csv(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl CsvField for f64 {
+    fn csv(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl CsvField for bool {
+    fn csv(&self) -> String {
+        (*self as i32).to_string()
+    }
+}
+
+impl CsvField for NaiveDate {
+    fn csv(&self) -> String {
+        self.format("%Y%m%d").to_string()
+    }
+}
+
+impl CsvField for PgInterval {
+    fn csv(&self) -> String {
+        let secs = self.days as i64 * 86400 + self.microseconds / 1_000_000;
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+impl<T: CsvField> CsvField for Option<T> {
+    fn csv(&self) -> String {
+        self.as_ref().map(T::csv).unwrap_or_default()
+    }
+}
+
+/// Colours are stored as given, which may have a # or be lower case.
+fn color(color: &Option<String>) -> String {
+    color
+        .as_deref()
+        .map(|c| c.trim_start_matches('#').to_uppercase())
+        .unwrap_or_default()
+}
+
+macro_rules! to_csv {
+    ($($ty:ident { $($field:ident $(as $format:ident)?),* $(,)? })*) => {$(
+        impl ToCsv for $ty {
+            fn header() -> Vec<&'static str> {
+                vec![$(stringify!($field)),*]
+            }
+
+            fn record(&self) -> Vec<String> {
+                vec![$(to_csv!(@field self.$field $(, $format)?)),*]
+            }
+        }
+    )*};
+    (@field $value:expr) => {
+        CsvField::csv(&$value)
+    };
+    (@field $value:expr, $format:ident) => {
+        $format(&$value)
+    };
+}
+
+to_csv! {
+    Agency { agency_id, agency_name, agency_url, agency_timezone, agency_lang, agency_phone }
+    Stop {
+        stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon, zone_id, stop_url,
+        location_type, parent_station, platform_code,
+    }
+    Route {
+        route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
+        route_url, route_color as color, route_text_color as color,
+    }
+    Trip { route_id, service_id, trip_id, trip_headsign, direction_id, block_id, shape_id }
+    StopTime {
+        trip_id, arrival_time, departure_time, stop_id, stop_sequence, pickup_type, drop_off_type,
+    }
+    Calendar {
+        service_id, monday, tuesday, wednesday, thursday, friday, saturday, sunday, start_date,
+        end_date,
+    }
+    CalendarDate { service_id, date, exception_type }
+    Shape { shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence }
+    FeedInfo { feed_publisher_name, feed_publisher_url, feed_lang, feed_start_date, feed_end_date }
+}
+
+/// feed_info with a feed_version stamped on it.
+struct VersionedFeedInfo {
+    info: FeedInfo,
+    feed_version: String,
+}
+
+impl ToCsv for VersionedFeedInfo {
+    fn header() -> Vec<&'static str> {
+        let mut header = FeedInfo::header();
+        header.push("feed_version");
+        header
+    }
+
+    fn record(&self) -> Vec<String> {
+        let mut record = self.info.record();
+        record.push(self.feed_version.clone());
+        record
+    }
+}
+
+/// Writes GTFS files into a zip, one at a time.
+pub struct GtfsWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> GtfsWriter<W> {
+    pub fn new(writer: W) -> GtfsWriter<W> {
+        GtfsWriter {
+            zip: ZipWriter::new(writer),
+        }
+    }
+
+    /// Writes a file from a stream of rows, giving how many there were.
+    /// Optional files are left out of the zip when there are no rows.
+    pub async fn write<T: ToCsv>(
+        &mut self,
+        file_name: &str,
+        mut rows: BoxStream<'_, Result<T, sqlx::Error>>,
+        optional: bool,
+    ) -> Result<usize> {
+        let first = rows.try_next().await?;
+        if optional && first.is_none() {
+            return Ok(0);
+        }
+
+        self.zip
+            .start_file(file_name, SimpleFileOptions::default())?;
+        let mut csv = csv::Writer::from_writer(&mut self.zip);
+        csv.write_record(T::header())?;
+        let mut written = 0;
+        let mut rows = futures::stream::iter(first.map(Ok)).chain(rows);
+        while let Some(row) = rows.try_next().await? {
+            csv.write_record(row.record())?;
+            written += 1;
+        }
+        csv.flush()?;
+        Ok(written)
+    }
+
+    pub fn finish(self) -> Result<W> {
+        Ok(self.zip.finish()?)
+    }
+}
+
+/// Writes the stored feed, or the subset of it asked for, into a zip.
+/// The db only ever holds one feed, so feed_version is only stamped into
+/// feed_info.txt, if given.
+#[instrument(skip(db, writer))]
+pub async fn write_gtfs<W: Write + Seek>(
+    db: &Db,
+    subset: &FeedSubset,
+    feed_version: Option<&str>,
+    writer: W,
+) -> Result<W> {
+    let pool = &db.0;
+    let mut gtfs = GtfsWriter::new(writer);
+    if subset.is_everything() {
+        gtfs.write("agency.txt", queries::stream_agencies(pool), false)
+            .await?;
+        gtfs.write("stops.txt", queries::stream_stops(pool), false)
+            .await?;
+        gtfs.write("routes.txt", queries::stream_routes(pool), false)
+            .await?;
+        gtfs.write("trips.txt", queries::stream_trips(pool), false)
+            .await?;
+        gtfs.write("stop_times.txt", queries::stream_stop_times(pool), false)
+            .await?;
+        gtfs.write("calendar.txt", queries::stream_calendars(pool), true)
+            .await?;
+        gtfs.write(
+            "calendar_dates.txt",
+            queries::stream_calendar_dates(pool),
+            true,
+        )
+        .await?;
+        gtfs.write("shapes.txt", queries::stream_shapes(pool), true)
+            .await?;
+    } else {
+        subset::write(db, subset, &mut gtfs).await?;
+    }
+
+    let feed_info = queries::stream_feed_info(pool).map_ok(|mut info| {
+        info.feed_start_date = info.feed_start_date.map(|d| clip_date(subset, d));
+        info.feed_end_date = info.feed_end_date.map(|d| clip_date(subset, d));
+        info
+    });
+    match feed_version {
+        Some(feed_version) => {
+            let rows = feed_info.map_ok(|info| VersionedFeedInfo {
+                info,
+                feed_version: feed_version.to_owned(),
+            });
+            gtfs.write("feed_info.txt", rows.boxed(), true).await?
+        }
+        None => gtfs.write("feed_info.txt", feed_info.boxed(), true).await?,
+    };
+    info!("Wrote GTFS feed");
+    gtfs.finish()
+}
+
+/// Moves a date into the subset's date range.
+fn clip_date(subset: &FeedSubset, date: NaiveDate) -> NaiveDate {
+    match subset.dates {
+        Some((from, to)) => date.clamp(from, to),
+        None => date,
+    }
+}
+
+/// Writes the stored feed, or a subset of it, to a zip file, replacing it once finished.
+pub async fn export_gtfs(
+    db: &Db,
+    subset: &FeedSubset,
+    feed_version: Option<&str>,
+    path: &Path,
+) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = path.with_extension("zip.partial");
+    write_gtfs(db, subset, feed_version, File::create(&partial)?).await?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// The stored feed, or a subset of it, written to an anonymous temp file and
+/// rewound to be read back.
+pub async fn temp_gtfs(db: &Db, subset: &FeedSubset) -> Result<File> {
+    let mut file = write_gtfs(db, subset, None, tempfile::tempfile()?).await?;
+    file.rewind()?;
+    Ok(file)
+}
+
+/// The whole stored feed as a zip in dir, written once per import and reused
+/// until the next one. Zips of older imports are removed once it's written.
+pub async fn cached_gtfs(db: &Db, dir: &Path) -> Result<File> {
+    let Some(last_update) = queries::get_feed_last_update("SEQ".into(), &db.0).await? else {
+        return temp_gtfs(db, &FeedSubset::default()).await;
+    };
+    let path = dir.join(format!("gtfs-{}.zip", last_update.format("%Y%m%dT%H%M%S")));
+    if let Ok(file) = File::open(&path) {
+        return Ok(file);
+    }
+
+    fs::create_dir_all(dir)?;
+    // Written under a temp name and renamed, so concurrent requests never see half a zip.
+    let partial = NamedTempFile::new_in(dir)?;
+    write_gtfs(db, &FeedSubset::default(), None, partial.as_file()).await?;
+    partial.persist(&path)?;
+    let file = File::open(&path)?;
+
+    for entry in fs::read_dir(dir)? {
+        let stale = entry?.path();
+        let name = stale.file_name().unwrap_or_default().to_string_lossy();
+        if stale != path && name.starts_with("gtfs-") && name.ends_with(".zip") {
+            // Another request may have got to it first.
+            let _ = fs::remove_file(&stale);
+        }
+    }
+    Ok(file)
+}
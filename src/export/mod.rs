@@ -0,0 +1,61 @@
+//! EXPORT
+//!
+//! Getting data back out of the db in formats other tools understand.
+//! Also holds the geometry helpers the exporters share.
+
+pub mod geojson;
+pub mod gtfs;
+pub mod parquet;
+pub mod subset;
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::db::{Db, queries, types::Route};
+
+/// A shape assembled into a line, along with the route that runs it.
+#[derive(Debug)]
+pub struct RouteLine {
+    pub shape_id: String,
+    pub route: Route,
+    /// (lon, lat) pairs in shape_pt_sequence order.
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Assembles every shape's points into lines, one per shape/route pair.
+#[instrument(skip(db))]
+pub async fn route_lines(db: &Db) -> Result<Vec<RouteLine>> {
+    // Points come back ordered by shape then sequence, so pushing in order is enough.
+    let mut points: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
+    for shape in queries::get_shapes(&db.0).await? {
+        points
+            .entry(shape.shape_id)
+            .or_default()
+            .push((shape.shape_pt_lon, shape.shape_pt_lat));
+    }
+
+    Ok(queries::get_shape_routes(&db.0)
+        .await?
+        .into_iter()
+        .filter_map(|(shape_id, route)| {
+            let points = points.get(&shape_id)?.clone();
+            Some(RouteLine {
+                shape_id,
+                route,
+                points,
+            })
+        })
+        .collect())
+}
+
+/// Formats a stored hex colour ("E463A4") for css/geojson styling ("#E463A4").
+pub fn css_color(color: &Option<String>) -> Option<String> {
+    color
+        .as_deref()
+        .filter(|c| !c.is_empty())
+        .map(|c| format!("#{c}"))
+}
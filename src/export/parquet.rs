@@ -0,0 +1,237 @@
+//! Parquet export, for analysis in tools that want columnar files.
+//!
+//! Every table is its own Hive style dataset, so tools can read a table
+//! whole or just the partitions they need. Static tables are partitioned by
+//! the feed version they were imported as, realtime observations by service date:
+//!
+//! stops/feed_version=20250701T030000/data.parquet
+//! stop_time_observations/service_date=2025-07-01/data.parquet
+
+use std::{fs, fs::File, path::Path, sync::Arc};
+
+use anyhow::Result;
+use arrow_array::{
+    ArrayRef, BooleanArray, Date32Array, Float64Array, Int32Array, RecordBatch, StringArray,
+    TimestampMicrosecondArray, types::Date32Type,
+};
+use chrono::{Days, NaiveDate, NaiveDateTime, Utc};
+use futures::{TryStreamExt, stream::BoxStream};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use sqlx::postgres::types::PgInterval;
+use tracing::{info, instrument};
+
+use crate::db::{Db, queries, types::*};
+
+/// Rows are written in batches of this many, each its own row group.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// A db type that can be written as a table.
+pub trait ToArrow: Sized {
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch>;
+}
+
+/// A field type that can be turned into an arrow column.
+/// Only Option fields make nullable columns, so the schema is the same however many
+/// nulls a batch happens to have, even none at all.
+trait Column {
+    const NULLABLE: bool;
+
+    fn array<'a>(values: impl Iterator<Item = &'a Self>) -> ArrayRef
+    where
+        Self: 'a;
+}
+
+macro_rules! column {
+    ($ty:ty, $array:ty, $convert:expr) => {
+        impl Column for $ty {
+            const NULLABLE: bool = false;
+
+            fn array<'a>(values: impl Iterator<Item = &'a Self>) -> ArrayRef {
+                Arc::new(values.map(|v| Some($convert(v))).collect::<$array>())
+            }
+        }
+
+        impl Column for Option<$ty> {
+            const NULLABLE: bool = true;
+
+            fn array<'a>(values: impl Iterator<Item = &'a Self>) -> ArrayRef {
+                Arc::new(values.map(|v| v.as_ref().map($convert)).collect::<$array>())
+            }
+        }
+    };
+}
+
+column!(String, StringArray, |v: &String| v.clone());
+column!(i32, Int32Array, |v: &i32| *v);
+column!(f64, Float64Array, |v: &f64| *v);
+column!(bool, BooleanArray, |v: &bool| *v);
+column!(NaiveDate, Date32Array, |v: &NaiveDate| {
+    Date32Type::from_naive_date(*v)
+});
+column!(
+    NaiveDateTime,
+    TimestampMicrosecondArray,
+    |v: &NaiveDateTime| { v.and_utc().timestamp_micros() }
+);
+// GTFS times, as seconds since the start of the service day. Parquet has no durations.
+column!(PgInterval, Int32Array, |v: &PgInterval| {
+    v.days * 86400 + (v.microseconds / 1_000_000) as i32
+});
+
+/// Whether a field's column is nullable, going by its type alone.
+fn nullable<T: Column>(_: Option<&T>) -> bool {
+    T::NULLABLE
+}
+
+macro_rules! to_arrow {
+    ($($ty:ident { $($field:ident),* $(,)? })*) => {$(
+        impl ToArrow for $ty {
+            fn to_batch(rows: &[Self]) -> Result<RecordBatch> {
+                Ok(RecordBatch::try_from_iter_with_nullable([
+                    $((
+                        stringify!($field),
+                        Column::array(rows.iter().map(|r| &r.$field)),
+                        nullable(rows.first().map(|r| &r.$field)),
+                    )),*
+                ])?)
+            }
+        }
+    )*};
+}
+
+to_arrow! {
+    Agency { agency_id, agency_name, agency_url, agency_timezone, agency_lang, agency_phone }
+    Stop {
+        stop_id, stop_code, stop_name, stop_desc, stop_lat, stop_lon, zone_id, stop_url,
+        location_type, parent_station, platform_code,
+    }
+    Route {
+        route_id, agency_id, route_short_name, route_long_name, route_desc, route_type,
+        route_url, route_color, route_text_color,
+    }
+    Trip { route_id, service_id, trip_id, trip_headsign, direction_id, block_id, shape_id }
+    StopTime {
+        trip_id, arrival_time, departure_time, stop_id, stop_sequence, pickup_type, drop_off_type,
+    }
+    Calendar {
+        service_id, monday, tuesday, wednesday, thursday, friday, saturday, sunday, start_date,
+        end_date,
+    }
+    CalendarDate { service_id, date, exception_type }
+    Shape { shape_id, shape_pt_lat, shape_pt_lon, shape_pt_sequence }
+    FeedInfo { feed_publisher_name, feed_publisher_url, feed_lang, feed_start_date, feed_end_date }
+    GeneratedTransfer { from_stop_id, to_stop_id, distance_m, walk_time }
+    StopTimeObservation {
+        trip_id, service_date, stop_sequence, arrival_time, departure_time, arrival_delay,
+        departure_delay, observed_at,
+    }
+    TripObservation {
+        trip_id, service_date, route_id, schedule_relationship, in_trip_updates,
+        in_vehicle_positions, first_seen, last_seen,
+    }
+}
+
+/// Writes a stream of rows to a Parquet file, replacing it once finished.
+/// Gives the number of rows written.
+pub async fn write_table<T: ToArrow>(
+    mut rows: BoxStream<'_, Result<T, sqlx::Error>>,
+    path: &Path,
+) -> Result<usize> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Readers never see a half written file.
+    let partial = path.with_extension("parquet.partial");
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(
+        File::create(&partial)?,
+        T::to_batch(&[])?.schema(),
+        Some(properties),
+    )?;
+
+    let mut written = 0;
+    let mut batch = Vec::with_capacity(BATCH_ROWS);
+    while let Some(row) = rows.try_next().await? {
+        batch.push(row);
+        if batch.len() == BATCH_ROWS {
+            writer.write(&T::to_batch(&batch)?)?;
+            written += batch.len();
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        writer.write(&T::to_batch(&batch)?)?;
+        written += batch.len();
+    }
+    writer.close()?;
+    fs::rename(partial, path)?;
+    Ok(written)
+}
+
+/// Writes every static table, under the version of the feed currently in the db.
+#[instrument(skip(db))]
+pub async fn write_static(db: &Db, dir: &Path) -> Result<()> {
+    let version = queries::get_feed_last_update("SEQ".into(), &db.0)
+        .await?
+        .map(|t| t.format("%Y%m%dT%H%M%S").to_string())
+        .unwrap_or_else(|| "unknown".to_owned());
+    let path = |table: &str| {
+        dir.join(table)
+            .join(format!("feed_version={version}"))
+            .join("data.parquet")
+    };
+
+    let pool = &db.0;
+    write_table(queries::stream_agencies(pool), &path("agency")).await?;
+    write_table(queries::stream_stops(pool), &path("stops")).await?;
+    write_table(queries::stream_routes(pool), &path("routes")).await?;
+    write_table(queries::stream_trips(pool), &path("trips")).await?;
+    write_table(queries::stream_stop_times(pool), &path("stop_times")).await?;
+    write_table(queries::stream_calendars(pool), &path("calendar")).await?;
+    write_table(
+        queries::stream_calendar_dates(pool),
+        &path("calendar_dates"),
+    )
+    .await?;
+    write_table(queries::stream_shapes(pool), &path("shapes")).await?;
+    write_table(queries::stream_feed_info(pool), &path("feed_info")).await?;
+    write_table(
+        queries::stream_generated_transfers(pool),
+        &path("generated_transfers"),
+    )
+    .await?;
+    info!(version, "Wrote static Parquet export");
+    Ok(())
+}
+
+/// Writes the archived realtime observations for some service dates.
+#[instrument(skip(db))]
+pub async fn write_realtime(db: &Db, dir: &Path, service_dates: &[NaiveDate]) -> Result<()> {
+    let path = |table: &str, date: NaiveDate| {
+        dir.join(table)
+            .join(format!("service_date={date}"))
+            .join("data.parquet")
+    };
+    for &date in service_dates {
+        write_table(
+            queries::stream_stop_time_observations(date, &db.0),
+            &path("stop_time_observations", date),
+        )
+        .await?;
+        write_table(
+            queries::stream_trip_observations(date, &db.0),
+            &path("trip_observations", date),
+        )
+        .await?;
+    }
+    info!("Wrote realtime Parquet export");
+    Ok(())
+}
+
+/// Rewrites yesterday's and today's realtime observations, which may still be changing.
+pub async fn write_recent(db: &Db, dir: &Path) -> Result<()> {
+    let today = Utc::now().with_timezone(&db.timezone().await?).date_naive();
+    write_realtime(db, dir, &[today - Days::new(1), today]).await
+}
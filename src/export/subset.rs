@@ -0,0 +1,154 @@
+//! Cuts a smaller, self-contained feed out of the db.
+//!
+//! The subset starts from stop times of the matching trips, then keeps
+//! everything they reference: trips, routes, agencies, stops and their
+//! parent stations, shapes and calendars. Shapes are kept whole, even
+//! when the stop times are clipped to a bbox.
+
+use std::collections::{HashMap, HashSet};
+use std::future::ready;
+use std::io::{Seek, Write};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use futures::{StreamExt, TryStreamExt};
+
+use super::gtfs::GtfsWriter;
+use crate::{
+    db::{Db, queries},
+    tiles::geometry::BBox,
+};
+
+/// Which part of the feed to keep. Every part given has to match.
+#[derive(Debug, Clone, Default)]
+pub struct FeedSubset {
+    pub route_ids: Option<Vec<String>>,
+    /// agency_id or agency_name.
+    pub agency: Option<String>,
+    /// Stops outside are dropped, along with trips left with less than two stops.
+    pub bbox: Option<BBox>,
+    /// Trips running on at least one day in this inclusive range.
+    /// Calendars are clipped to it.
+    pub dates: Option<(NaiveDate, NaiveDate)>,
+}
+
+impl FeedSubset {
+    pub fn is_everything(&self) -> bool {
+        self.route_ids.is_none()
+            && self.agency.is_none()
+            && self.bbox.is_none()
+            && self.dates.is_none()
+    }
+
+    /// Clips a date range to the subset's, None if they don't overlap.
+    pub fn clip_dates(&self, start: NaiveDate, end: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        let (start, end) = match self.dates {
+            Some((from, to)) => (start.max(from), end.min(to)),
+            None => (start, end),
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    fn contains_date(&self, date: NaiveDate) -> bool {
+        self.dates
+            .is_none_or(|(from, to)| (from..=to).contains(&date))
+    }
+}
+
+/// Writes every table but feed_info, keeping only what the subset needs.
+pub async fn write<W: Write + Seek>(
+    db: &Db,
+    subset: &FeedSubset,
+    gtfs: &mut GtfsWriter<W>,
+) -> Result<()> {
+    let pool = &db.0;
+    let route_ids = match &subset.agency {
+        Some(agency) => {
+            let agency_routes = queries::get_agency_route_ids(agency, pool).await?;
+            Some(match &subset.route_ids {
+                Some(route_ids) => agency_routes
+                    .into_iter()
+                    .filter(|r| route_ids.contains(r))
+                    .collect(),
+                None => agency_routes,
+            })
+        }
+        None => subset.route_ids.clone(),
+    };
+
+    let mut trip_ids = HashSet::new();
+    let mut stop_ids = HashSet::new();
+    let stop_times =
+        queries::stream_subset_stop_times(route_ids.as_deref(), subset.bbox, subset.dates, pool)
+            .inspect_ok(|stop_time| {
+                trip_ids.insert(stop_time.trip_id.clone());
+                stop_ids.insert(stop_time.stop_id.clone());
+            });
+    gtfs.write("stop_times.txt", stop_times.boxed(), false)
+        .await?;
+
+    let (mut route_ids, mut shape_ids, mut service_ids) =
+        (HashSet::new(), HashSet::new(), HashSet::new());
+    let trips = queries::stream_trips(pool)
+        .try_filter(|trip| ready(trip_ids.contains(&trip.trip_id)))
+        .inspect_ok(|trip| {
+            route_ids.insert(trip.route_id.clone());
+            shape_ids.extend(trip.shape_id.clone());
+            service_ids.insert(trip.service_id.clone());
+        });
+    gtfs.write("trips.txt", trips.boxed(), false).await?;
+
+    let mut agency_ids = HashSet::new();
+    let routes = queries::stream_routes(pool)
+        .try_filter(|route| ready(route_ids.contains(&route.route_id)))
+        .inspect_ok(|route| {
+            agency_ids.insert(route.agency_id.clone());
+        });
+    gtfs.write("routes.txt", routes.boxed(), false).await?;
+
+    // Routes without an agency_id belong to the feed's only agency.
+    let agencies = queries::stream_agencies(pool).try_filter(|agency| {
+        ready(agency_ids.contains(&None) || agency_ids.contains(&agency.agency_id))
+    });
+    gtfs.write("agency.txt", agencies.boxed(), false).await?;
+
+    let parents: HashMap<String, String> = queries::stream_stops(pool)
+        .try_filter_map(|stop| ready(Ok(stop.parent_station.map(|p| (stop.stop_id, p)))))
+        .try_collect()
+        .await?;
+    for stop_id in stop_ids.clone() {
+        let mut stop_id = &stop_id;
+        while let Some(parent) = parents.get(stop_id) {
+            if !stop_ids.insert(parent.clone()) {
+                break;
+            }
+            stop_id = parent;
+        }
+    }
+    let stops =
+        queries::stream_stops(pool).try_filter(|stop| ready(stop_ids.contains(&stop.stop_id)));
+    gtfs.write("stops.txt", stops.boxed(), false).await?;
+
+    let shapes =
+        queries::stream_shapes(pool).try_filter(|shape| ready(shape_ids.contains(&shape.shape_id)));
+    gtfs.write("shapes.txt", shapes.boxed(), true).await?;
+
+    let calendars = queries::stream_calendars(pool).try_filter_map(|mut calendar| {
+        let clipped = subset.clip_dates(calendar.start_date, calendar.end_date);
+        ready(Ok(match clipped {
+            Some((start, end)) if service_ids.contains(&calendar.service_id) => {
+                (calendar.start_date, calendar.end_date) = (start, end);
+                Some(calendar)
+            }
+            _ => None,
+        }))
+    });
+    gtfs.write("calendar.txt", calendars.boxed(), true).await?;
+
+    let calendar_dates = queries::stream_calendar_dates(pool).try_filter(|date| {
+        ready(service_ids.contains(&date.service_id) && subset.contains_date(date.date))
+    });
+    gtfs.write("calendar_dates.txt", calendar_dates.boxed(), true)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,355 @@
+//! Export tests
+//!
+//! Tests the Parquet and GTFS writers, and a feed round-tripping through the db.
+//! GeoJSON is covered by the API.
+
+use std::{
+    fs::File,
+    io::{Cursor, Read, Write},
+    sync::Arc,
+};
+
+use arrow_array::{Array, Int32Array, StringArray};
+use chrono::NaiveDate;
+use futures::StreamExt;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use sqlx::{PgPool, postgres::types::PgInterval};
+use tracing_test::traced_test;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use super::{
+    gtfs::{GtfsWriter, cached_gtfs, write_gtfs},
+    parquet::{ToArrow, write_table},
+};
+use crate::{
+    bridge::static_bridge::ImportMode,
+    db::{
+        Db,
+        types::{LastUpdate, Route, Shape, StopTime},
+    },
+    diff::{self, Feed},
+    gtfs::{StaticGtfs, static_gtfs},
+};
+
+fn stop_time(trip_id: &str, stop_sequence: i32, minutes: i64) -> StopTime {
+    let time = PgInterval {
+        months: 0,
+        days: 0,
+        microseconds: minutes * 60 * 1_000_000,
+    };
+    StopTime {
+        trip_id: trip_id.into(),
+        arrival_time: (stop_sequence > 1).then_some(time),
+        departure_time: time,
+        stop_id: format!("stop-{stop_sequence}"),
+        stop_sequence,
+        pickup_type: 0,
+        drop_off_type: 0,
+    }
+}
+
+#[test]
+fn test_to_batch_columns() {
+    let rows = vec![stop_time("a", 1, 8 * 60), stop_time("a", 2, 25 * 60)];
+    let batch = StopTime::to_batch(&rows).unwrap();
+
+    assert_eq!(batch.num_rows(), 2);
+    let names: Vec<&str> = batch
+        .schema_ref()
+        .fields()
+        .iter()
+        .map(|f| f.name().as_str())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "trip_id",
+            "arrival_time",
+            "departure_time",
+            "stop_id",
+            "stop_sequence",
+            "pickup_type",
+            "drop_off_type"
+        ]
+    );
+    let arrivals = batch["arrival_time"]
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .unwrap();
+    // No arrival time at the first stop, and times run past midnight.
+    assert!(arrivals.is_null(0));
+    assert_eq!(arrivals.value(1), 25 * 3600);
+}
+
+#[tokio::test]
+async fn test_write_table() {
+    let path = std::env::temp_dir()
+        .join(format!("gtfs-export-{}", std::process::id()))
+        .join("stop_times/feed_version=test/data.parquet");
+    // Only the odd rows are past the first stop, so only they have an arrival time.
+    let rows: Vec<StopTime> = (0..5)
+        .map(|i| stop_time(&format!("trip-{i}"), 1 + i as i32 % 2, 8 * 60 + i))
+        .collect();
+    let stream = futures::stream::iter(rows.into_iter().map(Ok)).boxed();
+    assert_eq!(write_table(stream, &path).await.unwrap(), 5);
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let batches: Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+    assert!(
+        batches[0]
+            .schema()
+            .field_with_name("arrival_time")
+            .unwrap()
+            .is_nullable()
+    );
+    assert!(
+        !batches[0]
+            .schema()
+            .field_with_name("trip_id")
+            .unwrap()
+            .is_nullable()
+    );
+
+    let mut trip_ids = vec![];
+    let mut arrivals = vec![];
+    for batch in &batches {
+        let column = batch["trip_id"]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        trip_ids.extend(column.iter().map(|t| t.unwrap().to_owned()));
+        let column = batch["arrival_time"]
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        arrivals.extend(column.iter());
+    }
+    assert_eq!(
+        trip_ids,
+        vec!["trip-0", "trip-1", "trip-2", "trip-3", "trip-4"]
+    );
+    // Missing arrival times come back as nulls, not zeroes.
+    assert_eq!(
+        arrivals,
+        vec![None, Some(481 * 60), None, Some(483 * 60), None]
+    );
+    assert!(!path.with_extension("parquet.partial").exists());
+}
+
+fn route(route_id: &str, route_color: Option<&str>) -> Route {
+    Route {
+        route_id: route_id.into(),
+        agency_id: None,
+        route_short_name: Some("27".into()),
+        route_long_name: Some("Kangaroo Point - City shuttle".into()),
+        route_desc: None,
+        route_type: 3,
+        route_url: None,
+        route_color: route_color.map(String::from),
+        route_text_color: Some("000000".into()),
+    }
+}
+
+fn read_entry(zip: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+    let mut text = String::new();
+    zip.by_name(name)
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    text
+}
+
+#[tokio::test]
+async fn test_write_gtfs_files() {
+    let mut writer = GtfsWriter::new(Cursor::new(vec![]));
+    let stop_times = vec![stop_time("a", 1, 8 * 60), stop_time("a", 2, 25 * 60 + 1)];
+    let written = writer
+        .write(
+            "stop_times.txt",
+            futures::stream::iter(stop_times.into_iter().map(Ok)).boxed(),
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(written, 2);
+    let routes = vec![route("27-3251", Some("#8dc63f")), route("28-3251", None)];
+    writer
+        .write(
+            "routes.txt",
+            futures::stream::iter(routes.into_iter().map(Ok)).boxed(),
+            false,
+        )
+        .await
+        .unwrap();
+    // Optional files with nothing in them are left out.
+    let written = writer
+        .write(
+            "shapes.txt",
+            futures::stream::empty::<Result<Shape, sqlx::Error>>().boxed(),
+            true,
+        )
+        .await
+        .unwrap();
+    assert_eq!(written, 0);
+
+    let mut zip = zip::ZipArchive::new(writer.finish().unwrap()).unwrap();
+    assert_eq!(zip.len(), 2);
+    assert_eq!(
+        read_entry(&mut zip, "stop_times.txt"),
+        "trip_id,arrival_time,departure_time,stop_id,stop_sequence,pickup_type,drop_off_type\n\
+         a,,08:00:00,stop-1,1,0,0\n\
+         a,25:01:00,25:01:00,stop-2,2,0,0\n"
+    );
+    assert_eq!(
+        read_entry(&mut zip, "routes.txt"),
+        "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type,route_url,route_color,route_text_color\n\
+         27-3251,,27,Kangaroo Point - City shuttle,,3,,8DC63F,000000\n\
+         28-3251,,27,Kangaroo Point - City shuttle,,3,,,000000\n"
+    );
+}
+
+/// A feed using every file the db stores, with the awkward cases: empty
+/// fields, times past midnight and colours.
+fn feed_zip() -> Arc<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in [
+        (
+            "agency.txt",
+            "agency_id,agency_name,agency_url,agency_timezone\nTL,Translink,https://translink.com.au/,Australia/Brisbane\n",
+        ),
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station\nplace,Place,-27.46,153.02,1,\n1,A,-27.46,153.02,0,place\n2,B,-27.47,153.03,0,\n",
+        ),
+        (
+            "routes.txt",
+            "route_id,agency_id,route_short_name,route_type,route_color\n27-3251,TL,27,3,8DC63F\n",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id,shape_id\n27-3251,weekdays,t1,s1\n27-3251,weekdays,t2,\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nt1,,08:00:00,1,1\nt1,08:05:00,08:05:00,2,2\nt2,24:50:00,24:50:00,1,1\nt2,25:01:00,25:01:00,2,2\n",
+        ),
+        (
+            "calendar.txt",
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\nweekdays,1,1,1,1,1,0,0,20250701,20251231\n",
+        ),
+        (
+            "calendar_dates.txt",
+            "service_id,date,exception_type\nweekdays,20251225,2\n",
+        ),
+        (
+            "shapes.txt",
+            "shape_id,shape_pt_lat,shape_pt_lon,shape_pt_sequence\ns1,-27.46,153.02,1\ns1,-27.47,153.03,2\n",
+        ),
+        (
+            "feed_info.txt",
+            "feed_publisher_name,feed_publisher_url,feed_lang,feed_start_date,feed_end_date\nTranslink,https://translink.com.au/,en,20250701,20251231\n",
+        ),
+    ] {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    Arc::new(zip.finish().unwrap().into_inner())
+}
+
+async fn export(db: &Db) -> Arc<Vec<u8>> {
+    let zip = write_gtfs(db, &Default::default(), None, Cursor::new(vec![]))
+        .await
+        .unwrap();
+    Arc::new(zip.into_inner())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "crate::db::MIGRATOR")]
+async fn test_gtfs_round_trip(pool: PgPool) -> sqlx::Result<()> {
+    let db = Db(pool);
+    let original = feed_zip();
+    StaticGtfs::new(original.clone(), LastUpdate::new("SEQ".into()))
+        .insert_db(db.clone(), ImportMode::Full)
+        .await
+        .unwrap();
+    let exported = export(&db).await;
+
+    // Importing the export back over the feed changes nothing...
+    let metrics = StaticGtfs::new(exported.clone(), LastUpdate::new("SEQ".into()))
+        .insert_db(db.clone(), ImportMode::Incremental)
+        .await
+        .unwrap();
+    assert!(
+        metrics
+            .tables
+            .iter()
+            .all(|t| t.changed == 0 && t.deleted == 0),
+        "{metrics:?}"
+    );
+    let parse = |zip: &Arc<Vec<u8>>| static_gtfs::parse(zip.clone()).collect();
+    let original = Feed::from_rows(parse(&original).await.unwrap());
+    assert!(diff::diff(&original, &Feed::load(&db).await.unwrap()).is_empty());
+    assert!(diff::diff(&original, &Feed::from_rows(parse(&exported).await.unwrap())).is_empty());
+
+    // ...and exporting it again gives the same files.
+    let mut first = zip::ZipArchive::new(Cursor::new(exported.to_vec())).unwrap();
+    let mut second = zip::ZipArchive::new(Cursor::new(export(&db).await.to_vec())).unwrap();
+    let names: Vec<String> = first.file_names().map(Into::into).collect();
+    assert_eq!(names.len(), 9);
+    for name in names {
+        assert_eq!(
+            read_entry(&mut first, &name),
+            read_entry(&mut second, &name),
+            "{name}"
+        );
+    }
+    Ok(())
+}
+
+#[traced_test]
+#[sqlx::test(migrator = "crate::db::MIGRATOR")]
+async fn test_cached_gtfs(pool: PgPool) -> sqlx::Result<()> {
+    let db = Db(pool);
+    let dir = tempfile::tempdir().unwrap();
+    let import = |hour| {
+        let db = db.clone();
+        async move {
+            let last_update = LastUpdate {
+                feed_region: "SEQ".into(),
+                feed_last_update: NaiveDate::from_ymd_opt(2025, 7, 1)
+                    .unwrap()
+                    .and_hms_opt(hour, 0, 0)
+                    .unwrap(),
+            };
+            StaticGtfs::new(feed_zip(), last_update)
+                .insert_db(db, ImportMode::Full)
+                .await
+                .unwrap();
+        }
+    };
+    let cached = || async {
+        let mut zip = vec![];
+        let mut file = cached_gtfs(&db, dir.path()).await.unwrap();
+        file.read_to_end(&mut zip).unwrap();
+        let names: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        (zip, names)
+    };
+
+    import(8).await;
+    let (zip, names) = cached().await;
+    assert_eq!(zip, *export(&db).await);
+    assert_eq!(cached().await.1, names);
+
+    // A new import gets a new zip, and the old one is cleared out.
+    import(9).await;
+    let (_, new_names) = cached().await;
+    assert_eq!(new_names.len(), 1);
+    assert_ne!(new_names, names);
+    Ok(())
+}
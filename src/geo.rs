@@ -0,0 +1,81 @@
+//! GEO
+//!
+//! Small geographic helpers: great circle distances,
+//! and a grid index for "what's near here" lookups.
+
+use std::collections::HashMap;
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Roughly how many metres are in a degree of latitude.
+pub const METRES_PER_DEGREE: f64 = 111_320.0;
+
+/// Haversine distance in metres between two lat/lon points.
+pub fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = (lat2 - lat1).to_radians();
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Buckets lat/lon points into square-ish cells so radius searches
+/// only look at nearby cells instead of every point.
+pub struct PointGrid {
+    cell_deg: f64,
+    points: Vec<(f64, f64)>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl PointGrid {
+    /// Cell size should be around the radius you plan to search with.
+    pub fn new(points: Vec<(f64, f64)>, cell_m: f64) -> PointGrid {
+        let cell_deg = cell_m / METRES_PER_DEGREE;
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (i, &(lat, lon)) in points.iter().enumerate() {
+            cells
+                .entry(Self::cell(cell_deg, lat, lon))
+                .or_default()
+                .push(i);
+        }
+        PointGrid {
+            cell_deg,
+            points,
+            cells,
+        }
+    }
+
+    fn cell(cell_deg: f64, lat: f64, lon: f64) -> (i32, i32) {
+        (
+            (lat / cell_deg).floor() as i32,
+            (lon / cell_deg).floor() as i32,
+        )
+    }
+
+    /// Indices of points within radius_m of lat/lon, with their distance, closest first.
+    pub fn within(&self, lat: f64, lon: f64, radius_m: f64) -> Vec<(usize, f64)> {
+        let (cell_lat, cell_lon) = Self::cell(self.cell_deg, lat, lon);
+        let cell_m = self.cell_deg * METRES_PER_DEGREE;
+        // Longitude cells shrink away from the equator, so more of them are needed.
+        let lat_cells = (radius_m / cell_m).ceil() as i32;
+        let lon_cells = (radius_m / (cell_m * lat.to_radians().cos().max(0.01))).ceil() as i32;
+
+        let mut found = vec![];
+        for d_lat in -lat_cells..=lat_cells {
+            for d_lon in -lon_cells..=lon_cells {
+                let Some(cell) = self.cells.get(&(cell_lat + d_lat, cell_lon + d_lon)) else {
+                    continue;
+                };
+                for &i in cell {
+                    let (p_lat, p_lon) = self.points[i];
+                    let distance = distance_m(lat, lon, p_lat, p_lon);
+                    if distance <= radius_m {
+                        found.push((i, distance));
+                    }
+                }
+            }
+        }
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+}
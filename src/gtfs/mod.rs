@@ -1,13 +1,18 @@
 //! GTFS
 //!
 //! This module handles a few responsibilities:
-//! - Loading static gtfs data via gtfs-structures.
+//! - Loading static gtfs data with our own streaming parser.
 //! - Loading real time gtfs data via protobufs.
 //! - Cleaning that up and verifying it.
-mod static_gtfs;
+pub mod realtime;
+pub mod static_gtfs;
+#[cfg(test)]
+mod tests;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::bridge::static_bridge::GtfsDbModel;
 use crate::db::queries;
 use crate::db::types::LastUpdate;
 use crate::transit_realtime::FeedMessage;
@@ -19,26 +24,25 @@ use chrono::FixedOffset;
 use chrono::NaiveDateTime;
 use chrono::Utc;
 use futures::future::try_join_all;
-use gtfs_structures::Gtfs;
-use gtfs_structures::RawGtfs;
 use prost::Message;
 use reqwest::{Client, header::LAST_MODIFIED};
 use sqlx::PgPool;
-use tokio::task::spawn_blocking;
 use tracing::{info, instrument};
 
-/// Static GTFS wrapper.
+/// Static GTFS wrapper. Holds the zip as is, it's parsed as it's inserted.
 pub struct StaticGtfs {
-    pub raw_gtfs: RawGtfs,
+    pub zip: Arc<Vec<u8>>,
     pub last_update: LastUpdate,
 }
 
 impl StaticGtfs {
-    pub fn new(raw_gtfs: RawGtfs, last_update: LastUpdate) -> StaticGtfs {
-        StaticGtfs {
-            raw_gtfs,
-            last_update,
-        }
+    pub fn new(zip: Arc<Vec<u8>>, last_update: LastUpdate) -> StaticGtfs {
+        StaticGtfs { zip, last_update }
+    }
+
+    /// Starts parsing the feed into streams of db rows.
+    pub fn parse(&self) -> GtfsDbModel {
+        static_gtfs::parse(self.zip.clone())
     }
 }
 
@@ -58,7 +62,7 @@ pub async fn last_modified(url: String, client: &Client) -> Result<Option<DateTi
 }
 
 /// Loads a static gtfs feed from the given path, which is either a file or url.
-/// With the translink dataset this can take quite a while (~40 seconds on my pc).
+/// This only reads the zip, parsing happens while it's inserted.
 #[instrument]
 pub async fn load_static_gtfs(
     url: String,
@@ -70,15 +74,27 @@ pub async fn load_static_gtfs(
         return Ok(None);
     }
 
-    info!("Loading static GTFS. This may take a while.");
-    let gtfs = spawn_blocking(move || RawGtfs::new(&url)).await??;
+    info!("Loading static GTFS.");
+    let zip = read_zip(&url).await?;
     info!("Finished loading static GTFS");
     Ok(Some(StaticGtfs::new(
-        gtfs,
+        zip,
         LastUpdate::new("SEQ".to_owned()),
     )))
 }
 
+/// Reads a GTFS zip from a file, or downloads it if given a url.
+pub async fn read_zip(path: &str) -> Result<Arc<Vec<u8>>> {
+    let zip = if path.starts_with("https://") || path.starts_with("http://") {
+        reqwest::get(path).await?.bytes().await?.to_vec()
+    } else {
+        tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {path}"))?
+    };
+    Ok(Arc::new(zip))
+}
+
 pub async fn is_url_content_outdated(url: &String, cutoff: NaiveDateTime) -> Result<bool> {
     if !url.starts_with("https://") {
         return Ok(false);
@@ -106,17 +122,39 @@ pub async fn is_url_content_outdated(url: &String, cutoff: NaiveDateTime) -> Res
     bail!("No Last-Modified header found.");
 }
 
-/// Loads realtime gtfs updates.
+/// A realtime payload as fetched, before it's decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFeed {
+    pub url: String,
+    pub fetched_at: DateTime<Utc>,
+    pub payload: Vec<u8>,
+}
+
+/// Fetches realtime gtfs updates without decoding them.
 /// Takes a vec of urls as translink dont have one unified feed.
 #[instrument]
-pub async fn load_realtime_gtfs(urls: Vec<String>) -> Result<RealtimeGtfs> {
+pub async fn fetch_realtime_gtfs(urls: Vec<String>) -> Result<Vec<RawFeed>> {
     info!("Loading realtime GTFS.");
     let futures = urls.into_iter().map(|url| async {
-        let pb = reqwest::get(url).await?.bytes().await?;
-        FeedMessage::decode(pb).context("Failed to decode")
+        let payload = reqwest::get(&url).await?.bytes().await?.to_vec();
+        anyhow::Ok(RawFeed {
+            url,
+            fetched_at: Utc::now(),
+            payload,
+        })
     });
 
-    let messages = try_join_all(futures).await?;
+    let feeds = try_join_all(futures).await?;
     info!("Finished loading realtime GTFS.");
-    Ok(RealtimeGtfs(messages))
+    Ok(feeds)
+}
+
+impl RealtimeGtfs {
+    pub fn decode(feeds: &[RawFeed]) -> Result<RealtimeGtfs> {
+        let messages = feeds
+            .iter()
+            .map(|feed| FeedMessage::decode(feed.payload.as_slice()).context("Failed to decode"))
+            .collect::<Result<_>>()?;
+        Ok(RealtimeGtfs(messages))
+    }
 }
@@ -0,0 +1,175 @@
+//! Realtime feed helpers.
+//!
+//! Enriches polled feeds with what we know from the static feed,
+//! and merges/filters them back into a single FeedMessage for re-publishing.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{Result, bail};
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::{
+    db::{Db, queries},
+    gtfs::RealtimeGtfs,
+    transit_realtime::{FeedEntity, FeedHeader, FeedMessage, TripDescriptor, feed_header},
+};
+
+/// The three kinds of realtime feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    TripUpdates,
+    VehiclePositions,
+    Alerts,
+}
+
+impl FromStr for FeedKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<FeedKind> {
+        Ok(match s {
+            "trip_updates" => FeedKind::TripUpdates,
+            "vehicle_positions" => FeedKind::VehiclePositions,
+            "alerts" => FeedKind::Alerts,
+            _ => bail!("Unknown feed {s}"),
+        })
+    }
+}
+
+/// Which entities to keep when merging.
+#[derive(Debug, Default)]
+pub struct RealtimeFilter {
+    /// Only keep entities for these routes.
+    pub route_ids: Option<HashSet<String>>,
+    /// Alerts can target an agency directly, rather than through its routes.
+    pub agency: Option<String>,
+}
+
+impl RealtimeFilter {
+    fn route_matches(&self, route_id: Option<&String>) -> bool {
+        match (&self.route_ids, route_id) {
+            (None, _) => true,
+            (Some(route_ids), Some(route_id)) => route_ids.contains(route_id),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn matches(&self, entity: &FeedEntity) -> bool {
+        if let Some(update) = &entity.trip_update {
+            return self.route_matches(update.trip.route_id.as_ref());
+        }
+        if let Some(vehicle) = &entity.vehicle {
+            return self.route_matches(vehicle.trip.as_ref().and_then(|t| t.route_id.as_ref()));
+        }
+        if let Some(alert) = &entity.alert {
+            if self.route_ids.is_none() && self.agency.is_none() {
+                return true;
+            }
+            return alert.informed_entity.iter().any(|selector| {
+                let route_id = selector
+                    .route_id
+                    .as_ref()
+                    .or(selector.trip.as_ref().and_then(|t| t.route_id.as_ref()));
+                (route_id.is_some() && self.route_matches(route_id))
+                    || (self.agency.is_some() && selector.agency_id == self.agency)
+            });
+        }
+        false
+    }
+}
+
+impl RealtimeGtfs {
+    fn trip_descriptors_mut(&mut self) -> Vec<&mut TripDescriptor> {
+        let mut descriptors = vec![];
+        for entity in self.0.iter_mut().flat_map(|m| &mut m.entity) {
+            if let Some(update) = &mut entity.trip_update {
+                descriptors.push(&mut update.trip);
+            }
+            if let Some(trip) = entity.vehicle.as_mut().and_then(|v| v.trip.as_mut()) {
+                descriptors.push(trip);
+            }
+            if let Some(alert) = &mut entity.alert {
+                descriptors.extend(
+                    alert
+                        .informed_entity
+                        .iter_mut()
+                        .filter_map(|e| e.trip.as_mut()),
+                );
+            }
+        }
+        descriptors
+    }
+
+    /// Fills in route_id and direction_id on trip descriptors from the static trips,
+    /// as the feed often only gives a trip_id.
+    #[instrument(skip_all)]
+    pub async fn enrich(&mut self, db: &Db) -> Result<()> {
+        let trip_ids: Vec<String> = self
+            .trip_descriptors_mut()
+            .into_iter()
+            .filter_map(|t| t.trip_id.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let trips = queries::get_trips_by_id(&trip_ids, &db.0).await?;
+        let trips: HashMap<_, _> = trips.into_iter().map(|t| (t.trip_id.clone(), t)).collect();
+
+        for descriptor in self.trip_descriptors_mut() {
+            let Some(trip) = descriptor.trip_id.as_ref().and_then(|id| trips.get(id)) else {
+                continue;
+            };
+            if descriptor.route_id.is_none() {
+                descriptor.route_id = Some(trip.route_id.clone());
+            }
+            if descriptor.direction_id.is_none() {
+                descriptor.direction_id = trip.direction_id.map(u32::from);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges every polled feed into a single message of one kind, keeping matching entities.
+    pub fn merged(&self, kind: FeedKind, filter: &RealtimeFilter) -> FeedMessage {
+        let timestamp = self.0.iter().filter_map(|m| m.header.timestamp).max();
+        let entity = self
+            .0
+            .iter()
+            .flat_map(|m| &m.entity)
+            .filter(|e| match kind {
+                FeedKind::TripUpdates => e.trip_update.is_some(),
+                FeedKind::VehiclePositions => e.vehicle.is_some(),
+                FeedKind::Alerts => e.alert.is_some(),
+            })
+            .filter(|e| filter.matches(e))
+            .cloned()
+            .collect();
+
+        FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: "2.0".to_owned(),
+                incrementality: Some(feed_header::Incrementality::FullDataset as i32),
+                timestamp,
+                ..Default::default()
+            },
+            entity,
+        }
+    }
+}
+
+/// The most recent realtime poll, shared between the poller and the API.
+#[derive(Clone, Default)]
+pub struct LatestRealtime(Arc<RwLock<Option<Arc<RealtimeGtfs>>>>);
+
+impl LatestRealtime {
+    pub async fn get(&self) -> Option<Arc<RealtimeGtfs>> {
+        self.0.read().await.clone()
+    }
+
+    pub async fn set(&self, realtime: Arc<RealtimeGtfs>) {
+        *self.0.write().await = Some(realtime);
+    }
+}
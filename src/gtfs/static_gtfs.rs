@@ -1,23 +1,45 @@
-use chrono::DateTime;
-use csv::{ReaderBuilder, StringRecord};
+//! Streaming static GTFS parser.
+//!
+//! Every file in the zip gets its own blocking task, which reads records in
+//! chunks, deserializes each chunk in parallel with rayon and sends the rows
+//! on as db types. Channels are bounded, so parsing only runs as far ahead of
+//! the loader as they allow and the feed is never held in memory all at once.
+
+use std::{
+    io::{BufRead, BufReader, Cursor, Read, Seek},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use csv::{ErrorKind, ReaderBuilder, StringRecord, Trim};
+use futures::StreamExt;
 use rayon::prelude::*;
-use serde::Deserialize;
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
+use serde::{Deserialize, de::DeserializeOwned};
+use tokio::{
+    sync::mpsc::{Sender, channel, error::TrySendError},
+    task::spawn_blocking,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
+use zip::ZipArchive;
+
+use crate::bridge::static_bridge::{GtfsDbModel, TableStream, ToDB};
+
+/// Records deserialized in parallel at a time.
+const CHUNK_SIZE: usize = 8192;
 
-// TODO: Use rayon here.
-// Task for each file.
-// For each task, do a par iter over the csv readers lines.
-// Deserialize them all excessively fast.
+/// Rows each file can get ahead of the loader by.
+const CHANNEL_SIZE: usize = 16384;
 
 #[derive(Debug, Deserialize)]
 pub struct Agency {
+    pub agency_id: Option<String>,
     pub agency_name: String,
     pub agency_url: String,
     pub agency_timezone: String,
-    pub agency_lang: String,
-    pub agency_phone: String,
+    pub agency_lang: Option<String>,
+    pub agency_phone: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,28 +60,29 @@ pub struct Calendar {
 pub struct CalendarDate {
     pub service_id: String,
     pub date: String,
-    pub exception_type: u8,
+    pub exception_type: i32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct FeedInfo {
     pub feed_publisher_name: String,
     pub feed_publisher_url: String,
-    pub feed_lang: String,
-    pub feed_start_date: String,
-    pub feed_end_date: String,
+    pub feed_lang: Option<String>,
+    pub feed_start_date: Option<String>,
+    pub feed_end_date: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Route {
     pub route_id: String,
-    pub route_short_name: String,
-    pub route_long_name: String,
+    pub agency_id: Option<String>,
+    pub route_short_name: Option<String>,
+    pub route_long_name: Option<String>,
     pub route_desc: Option<String>,
-    pub route_type: u8,
-    pub route_url: String,
-    pub route_color: String,
-    pub route_text_color: String,
+    pub route_type: i32,
+    pub route_url: Option<String>,
+    pub route_color: Option<String>,
+    pub route_text_color: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -67,31 +90,31 @@ pub struct Shape {
     pub shape_id: String,
     pub shape_pt_lat: f64,
     pub shape_pt_lon: f64,
-    pub shape_pt_sequence: u32,
+    pub shape_pt_sequence: i32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct StopTime {
     pub trip_id: String,
-    pub arrival_time: String,
-    pub departure_time: String,
+    pub arrival_time: Option<String>,
+    pub departure_time: Option<String>,
     pub stop_id: String,
-    pub stop_sequence: u32,
-    pub pickup_type: u8,
-    pub drop_off_type: u8,
+    pub stop_sequence: i32,
+    pub pickup_type: Option<i32>,
+    pub drop_off_type: Option<i32>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Stop {
     pub stop_id: String,
-    pub stop_code: String,
-    pub stop_name: String,
+    pub stop_code: Option<String>,
+    pub stop_name: Option<String>,
     pub stop_desc: Option<String>,
-    pub stop_lat: f64,
-    pub stop_lon: f64,
-    pub zone_id: String,
-    pub stop_url: String,
-    pub location_type: u8,
+    pub stop_lat: Option<f64>,
+    pub stop_lon: Option<f64>,
+    pub zone_id: Option<String>,
+    pub stop_url: Option<String>,
+    pub location_type: Option<i32>,
     pub parent_station: Option<String>,
     pub platform_code: Option<String>,
 }
@@ -101,29 +124,184 @@ pub struct Trip {
     pub route_id: String,
     pub service_id: String,
     pub trip_id: String,
-    pub trip_headsign: String,
-    pub direction_id: u8,
+    pub trip_headsign: Option<String>,
+    pub direction_id: Option<u8>,
     pub block_id: Option<String>,
     pub shape_id: Option<String>,
 }
 
-// fn () -> Result<(), Box<dyn Error>> {
-//     let file = File::open("./seq_gtfs/stop_times.txt")?;
-//     let mut rdr = ReaderBuilder::new()
-//         .has_headers(true)
-//         .from_reader(BufReader::new(file));
+/// How parsing one file went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseStats {
+    pub file: &'static str,
+    pub parsed: u64,
+    pub skipped: u64,
+    /// Most rows that were ever waiting in the channel for the loader.
+    pub max_queued: usize,
+    /// Time spent waiting for the loader to make room, i.e. held back by backpressure.
+    pub blocked: Duration,
+}
 
-//     let headers = rdr.headers()?.clone();
-//     let records: Vec<StringRecord> = rdr.records().filter_map(Result::ok).collect();
+/// Starts parsing every file in a GTFS zip, giving the streams of rows as they come.
+pub fn parse(zip: Arc<Vec<u8>>) -> GtfsDbModel {
+    GtfsDbModel {
+        agencies: spawn_parser::<Agency, _>(&zip, "agency.txt", true),
+        stops: spawn_parser::<Stop, _>(&zip, "stops.txt", true),
+        routes: spawn_parser::<Route, _>(&zip, "routes.txt", true),
+        trips: spawn_parser::<Trip, _>(&zip, "trips.txt", true),
+        stop_times: spawn_parser::<StopTime, _>(&zip, "stop_times.txt", true),
+        calendar: spawn_parser::<Calendar, _>(&zip, "calendar.txt", false),
+        calendar_dates: spawn_parser::<CalendarDate, _>(&zip, "calendar_dates.txt", false),
+        shapes: spawn_parser::<Shape, _>(&zip, "shapes.txt", false),
+        feed_info: spawn_parser::<FeedInfo, _>(&zip, "feed_info.txt", false),
+    }
+}
 
-//     let parsed: Vec<StopTime> = records
-//         .into_par_iter()
-//         .filter_map(|record| record.deserialize(Some(&headers)).ok())
-//         .collect();
+fn spawn_parser<T, U>(zip: &Arc<Vec<u8>>, name: &'static str, required: bool) -> TableStream<U>
+where
+    T: DeserializeOwned + ToDB<U>,
+    U: Send + 'static,
+{
+    let (sender, receiver) = channel(CHANNEL_SIZE);
+    let checksum = ZipArchive::new(Cursor::new(zip.as_slice()))
+        .ok()
+        .and_then(|mut archive| {
+            let index = file_index(&archive, name)?;
+            archive.by_index(index).ok().map(|file| file.crc32())
+        });
+    let total = Arc::new(OnceLock::new());
+    let parser = spawn_blocking({
+        let (zip, total) = (zip.clone(), total.clone());
+        move || parse_file::<T, U>(&zip, name, required, &total, sender)
+    });
+    TableStream {
+        rows: ReceiverStream::new(receiver).boxed(),
+        parser: Some(parser),
+        checksum,
+        total,
+    }
+}
 
-//     for stop_time in &parsed {
-//         println!("{:?}", stop_time);
-//     }
+/// Where a file is in the zip. Feeds are sometimes zipped up inside a folder,
+/// so a file in a subfolder will do when there isn't one at the root.
+fn file_index<R: Read + Seek>(archive: &ZipArchive<R>, name: &str) -> Option<usize> {
+    archive.index_for_name(name).or_else(|| {
+        let suffix = format!("/{name}");
+        let path = archive.file_names().find(|path| path.ends_with(&suffix))?;
+        archive.index_for_name(path)
+    })
+}
+
+/// Counts lines to give progress a total, which is quick next to parsing them.
+/// Quoted newlines are counted too, so it's only an estimate.
+fn count_lines(file: impl Read) -> u64 {
+    let mut reader = BufReader::new(file);
+    let mut lines = 0;
+    while let Ok(buf) = reader.fill_buf() {
+        if buf.is_empty() {
+            break;
+        }
+        lines += buf.iter().filter(|&&b| b == b'\n').count() as u64;
+        let len = buf.len();
+        reader.consume(len);
+    }
+    lines
+}
 
-//     Ok(())
-// }
+/// Parses one file, sending rows until it's done or nothing is listening.
+/// Records that aren't valid CSV and rows that don't parse are skipped, with a
+/// count logged at the end. Only failing to read the file stops it.
+fn parse_file<T, U>(
+    zip: &[u8],
+    name: &'static str,
+    required: bool,
+    total: &OnceLock<u64>,
+    sender: Sender<U>,
+) -> Result<ParseStats>
+where
+    T: DeserializeOwned + ToDB<U>,
+    U: Send,
+{
+    let mut stats = ParseStats {
+        file: name,
+        parsed: 0,
+        skipped: 0,
+        max_queued: 0,
+        blocked: Duration::ZERO,
+    };
+    let mut archive = ZipArchive::new(Cursor::new(zip))?;
+    let index = match file_index(&archive, name) {
+        Some(index) => index,
+        None if !required => return Ok(stats),
+        None => bail!("{name} is missing from the feed"),
+    };
+    if let Ok(file) = archive.by_index(index) {
+        total.get_or_init(|| count_lines(file).saturating_sub(1));
+    }
+    let file = archive.by_index(index)?;
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(file);
+    let headers = reader.headers()?.clone();
+    let mut records = reader.into_records();
+
+    loop {
+        let mut chunk: Vec<StringRecord> = Vec::with_capacity(CHUNK_SIZE);
+        let mut read = 0;
+        for record in records.by_ref().take(CHUNK_SIZE) {
+            read += 1;
+            match record {
+                Ok(record) => chunk.push(record),
+                // Reading would only keep failing, so give up on the file.
+                Err(e) if matches!(e.kind(), ErrorKind::Io(_)) => return Err(e.into()),
+                Err(_) => stats.skipped += 1,
+            }
+        }
+        if read == 0 {
+            break;
+        }
+        let rows: Vec<Option<U>> = chunk
+            .into_par_iter()
+            .map(|record| {
+                let row: T = record.deserialize(Some(&headers)).ok()?;
+                row.to_db().ok()
+            })
+            .collect();
+        for row in rows {
+            let Some(row) = row else {
+                stats.skipped += 1;
+                continue;
+            };
+            stats.max_queued = stats
+                .max_queued
+                .max(sender.max_capacity() - sender.capacity());
+            // Only time sends that have to wait, a clock read per row adds up.
+            let row = match sender.try_send(row) {
+                Ok(()) => None,
+                Err(TrySendError::Full(row)) => Some(row),
+                Err(TrySendError::Closed(_)) => return Ok(stats),
+            };
+            if let Some(row) = row {
+                let start = Instant::now();
+                if sender.blocking_send(row).is_err() {
+                    return Ok(stats);
+                }
+                stats.blocked += start.elapsed();
+            }
+            stats.parsed += 1;
+        }
+    }
+
+    if stats.skipped > 0 {
+        warn!(name, stats.skipped, "Skipped rows that didn't parse");
+    }
+    info!(
+        name,
+        stats.parsed,
+        stats.max_queued,
+        blocked = ?stats.blocked,
+        "Parsed static GTFS file"
+    );
+    Ok(stats)
+}
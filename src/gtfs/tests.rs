@@ -0,0 +1,127 @@
+//! GTFS tests
+//!
+//! Tests the static parser against the sample SEQ files, plus a few made up ones.
+
+use std::{
+    fs,
+    io::{Cursor, Write},
+    sync::Arc,
+};
+
+use futures::StreamExt;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use super::static_gtfs::parse;
+
+const STOPS: &str = "stop_id,stop_code,stop_name,stop_desc,stop_lat,stop_lon,zone_id,stop_url,location_type,parent_station,platform_code
+place_a,,A Station,,-27.46,153.02,,,1,,
+1,001,A Platform 1,,-27.46,153.02,1,,,place_a,1
+2,002,B Stop,,-27.47,153.03,1,,0,,
+3,003,Nowhere,,not a number,153.03,1,,0,,
+";
+
+const TRIPS: &str = "route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id
+27-3251,ATS_HBL 23-34836,t1,City,1,,
+27-3251,ATS_HBL 23-34836,t2,,,,
+";
+
+const STOP_TIMES: &str =
+    "trip_id,arrival_time,departure_time,stop_id,stop_sequence,pickup_type,drop_off_type
+t1,,23:59:00,1,1,,
+t1,25:01:00,25:01:30,2,2,0,1
+t2,8:00:00,,1,1,,
+";
+
+fn sample_zip(extra: &[(&str, &str)]) -> Arc<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for file in [
+        "agency.txt",
+        "calendar.txt",
+        "calendar_dates.txt",
+        "feed_info.txt",
+        "routes.txt",
+    ] {
+        zip.start_file(file, SimpleFileOptions::default()).unwrap();
+        zip.write_all(&fs::read(format!("seq_gtfs/{file}")).unwrap())
+            .unwrap();
+    }
+    for (file, contents) in extra {
+        zip.start_file(*file, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    Arc::new(zip.finish().unwrap().into_inner())
+}
+
+#[tokio::test]
+async fn test_parse_static_gtfs() {
+    let zip = sample_zip(&[
+        ("stops.txt", STOPS),
+        ("trips.txt", TRIPS),
+        ("stop_times.txt", STOP_TIMES),
+    ]);
+    let feed = parse(zip).collect().await.unwrap();
+
+    assert_eq!(feed.agencies.len(), 1);
+    assert_eq!(feed.agencies[0].agency_name, "Translink");
+    assert_eq!(feed.routes.len(), 1105);
+    assert_eq!(feed.calendar.len(), 154);
+    assert_eq!(feed.calendar_dates.len(), 144);
+    assert_eq!(feed.feed_info.len(), 1);
+    assert!(feed.shapes.is_empty());
+
+    let route = feed
+        .routes
+        .iter()
+        .find(|r| r.route_id == "27-3251")
+        .unwrap();
+    assert_eq!(route.route_short_name.as_deref(), Some("27"));
+    assert_eq!(route.route_color.as_deref(), Some("8DC63F"));
+
+    // The stop with a bad latitude is skipped, the rest keep file order.
+    let stop_ids: Vec<&str> = feed.stops.iter().map(|s| s.stop_id.as_str()).collect();
+    assert_eq!(stop_ids, ["place_a", "1", "2"]);
+    assert_eq!(feed.stops[0].location_type, Some(1));
+    assert_eq!(feed.stops[1].location_type, Some(0));
+    assert_eq!(feed.stops[1].parent_station.as_deref(), Some("place_a"));
+
+    assert_eq!(feed.trips[0].direction_id, Some(true));
+    assert_eq!(feed.trips[1].direction_id, None);
+
+    // Stop times without a departure time are skipped.
+    assert_eq!(feed.stop_times.len(), 2);
+    assert!(feed.stop_times[0].arrival_time.is_none());
+    let after_midnight = &feed.stop_times[1];
+    assert_eq!(
+        after_midnight.arrival_time.unwrap().microseconds,
+        (25 * 3600 + 60) * 1_000_000
+    );
+    assert_eq!(after_midnight.drop_off_type, 1);
+}
+
+#[tokio::test]
+async fn test_parse_missing_required_file() {
+    let zip = sample_zip(&[("stops.txt", STOPS), ("trips.txt", TRIPS)]);
+    let err = parse(zip).collect().await.unwrap_err();
+    assert!(err.to_string().contains("stop_times.txt"));
+}
+
+#[tokio::test]
+async fn test_parse_nested_malformed_file() {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    // Zipped inside a folder, with a record that isn't valid UTF-8.
+    let mut stops = STOPS.as_bytes().to_vec();
+    stops.extend(b"4,004,\xff\xfe,,-27.48,153.04,1,,0,,\n5,005,E Stop,,-27.49,153.05,1,,0,,\n");
+    zip.start_file("SEQ_GTFS/stops.txt", SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(&stops).unwrap();
+    let zip = Arc::new(zip.finish().unwrap().into_inner());
+
+    let stops = parse(zip).stops;
+    assert!(stops.checksum.is_some());
+    let rows: Vec<_> = stops.rows.collect().await;
+    let stats = stops.parser.unwrap().await.unwrap().unwrap();
+
+    let stop_ids: Vec<&str> = rows.iter().map(|s| s.stop_id.as_str()).collect();
+    assert_eq!(stop_ids, ["place_a", "1", "2", "5"]);
+    assert_eq!((stats.parsed, stats.skipped), (4, 2));
+}
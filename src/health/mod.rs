@@ -0,0 +1,174 @@
+//! HEALTH
+//!
+//! Liveness and readiness checks, for /healthz and /readyz.
+//! Live only needs the db to answer. Ready also needs the schema migrated,
+//! a static feed that hasn't run out and a recent successful realtime poll.
+
+#[cfg(test)]
+mod tests;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::{
+    db::{Db, MIGRATOR, queries},
+    vars,
+};
+
+/// When the last realtime poll succeeded, shared between the poller and the API.
+#[derive(Clone, Default)]
+pub struct LastPoll(Arc<RwLock<Option<DateTime<Utc>>>>);
+
+impl LastPoll {
+    pub async fn get(&self) -> Option<DateTime<Utc>> {
+        *self.0.read().await
+    }
+
+    pub async fn succeeded(&self, at: DateTime<Utc>) {
+        *self.0.write().await = Some(at);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, ok: bool, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            ok,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl From<Vec<Check>> for Report {
+    fn from(checks: Vec<Check>) -> Report {
+        Report {
+            ok: checks.iter().all(|c| c.ok),
+            checks,
+        }
+    }
+}
+
+/// Whether the service is up at all.
+pub async fn live(db: &Db) -> Report {
+    vec![db_check(db).await].into()
+}
+
+/// Whether the service is up and has data worth serving.
+pub async fn ready(db: &Db, last_poll: &LastPoll) -> Report {
+    let db_check = db_check(db).await;
+    if !db_check.ok {
+        // Everything else needs the db too, so there's no point asking.
+        return vec![db_check].into();
+    }
+    vec![
+        db_check,
+        migrations_check(db).await,
+        static_check(db).await,
+        realtime_check(
+            last_poll.get().await,
+            Utc::now(),
+            TimeDelta::seconds(vars::realtime_max_age_secs()),
+        ),
+    ]
+    .into()
+}
+
+async fn db_check(db: &Db) -> Check {
+    match queries::ping(&db.0).await {
+        Ok(()) => Check::new("db", true, "connected"),
+        Err(e) => Check::new("db", false, e.to_string()),
+    }
+}
+
+async fn migrations_check(db: &Db) -> Check {
+    match db.applied_migrations().await {
+        Ok(applied) => pending_migrations_check(&applied),
+        Err(e) => Check::new("migrations", false, e.to_string()),
+    }
+}
+
+/// Fails if any of MIGRATOR's migrations aren't in the applied versions.
+fn pending_migrations_check(applied: &[i64]) -> Check {
+    let pending: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration() && !applied.contains(&m.version))
+        .map(|m| m.version.to_string())
+        .collect();
+    if pending.is_empty() {
+        Check::new("migrations", true, format!("{} applied", applied.len()))
+    } else {
+        Check::new(
+            "migrations",
+            false,
+            format!("pending: {}", pending.join(", ")),
+        )
+    }
+}
+
+async fn static_check(db: &Db) -> Check {
+    match feed_status(db).await {
+        Ok(check) => check,
+        Err(e) => Check::new("static", false, e.to_string()),
+    }
+}
+
+/// Checks the feed against today in its own timezone, which the end date is in.
+async fn feed_status(db: &Db) -> Result<Check> {
+    let last_update = queries::get_feed_last_update("SEQ".into(), &db.0).await?;
+    let end_date = queries::get_feed_end_date(&db.0).await?;
+    let today = Utc::now().with_timezone(&db.timezone().await?).date_naive();
+    Ok(feed_check(last_update, end_date, today))
+}
+
+/// Fails without a loaded feed, or once today is past its feed_end_date.
+/// Feeds without an end date are fine for as long as they're loaded.
+fn feed_check(
+    last_update: Option<NaiveDateTime>,
+    end_date: Option<NaiveDate>,
+    today: NaiveDate,
+) -> Check {
+    match (last_update, end_date) {
+        (None, _) => Check::new("static", false, "no feed loaded"),
+        (Some(_), Some(end)) if today > end => {
+            Check::new("static", false, format!("feed ended {end}"))
+        }
+        (Some(updated), Some(end)) => {
+            Check::new("static", true, format!("updated {updated}, ends {end}"))
+        }
+        (Some(updated), None) => Check::new("static", true, format!("updated {updated}")),
+    }
+}
+
+/// Fails if there's never been a successful poll, or it was more than max_age ago.
+fn realtime_check(
+    last_poll: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    max_age: TimeDelta,
+) -> Check {
+    let Some(last_poll) = last_poll else {
+        return Check::new("realtime", false, "no successful poll yet");
+    };
+    let age = now - last_poll;
+    Check::new(
+        "realtime",
+        age <= max_age,
+        format!("last polled {}s ago", age.num_seconds()),
+    )
+}
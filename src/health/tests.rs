@@ -0,0 +1,57 @@
+//! Health tests
+//!
+//! Tests the checks that don't need a db.
+
+use chrono::{NaiveDate, TimeDelta, Utc};
+
+use super::*;
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, 8, day).unwrap()
+}
+
+#[test]
+fn test_feed_check() {
+    let updated = date(1).and_hms_opt(3, 0, 0);
+
+    assert!(!feed_check(None, None, date(10)).ok);
+    assert!(feed_check(updated, None, date(10)).ok);
+    assert!(feed_check(updated, Some(date(10)), date(10)).ok);
+
+    let ended = feed_check(updated, Some(date(9)), date(10));
+    assert!(!ended.ok);
+    assert_eq!(ended.detail, "feed ended 2025-08-09");
+}
+
+#[test]
+fn test_realtime_check() {
+    let now = Utc::now();
+    let max_age = TimeDelta::minutes(5);
+
+    assert!(!realtime_check(None, now, max_age).ok);
+    assert!(realtime_check(Some(now - TimeDelta::minutes(1)), now, max_age).ok);
+
+    let stale = realtime_check(Some(now - TimeDelta::minutes(6)), now, max_age);
+    assert!(!stale.ok);
+    assert_eq!(stale.detail, "last polled 360s ago");
+}
+
+#[test]
+fn test_pending_migrations_check() {
+    let all: Vec<i64> = MIGRATOR.iter().map(|m| m.version).collect();
+    assert!(pending_migrations_check(&all).ok);
+
+    let pending = pending_migrations_check(&all[..all.len() - 1]);
+    assert!(!pending.ok);
+    assert_eq!(pending.detail, format!("pending: {}", all[all.len() - 1]));
+}
+
+#[test]
+fn test_report() {
+    let report = Report::from(vec![
+        Check::new("db", true, "connected"),
+        Check::new("realtime", false, "no successful poll yet"),
+    ]);
+    assert!(!report.ok);
+    assert!(Report::from(vec![Check::new("db", true, "connected")]).ok);
+}
@@ -0,0 +1,201 @@
+//! Live events, the JSON friendly view of realtime entities that gets pushed to subscribers.
+
+use serde::Serialize;
+
+use crate::transit_realtime::{Alert, FeedEntity, TranslatedString, TripUpdate, VehiclePosition};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveEvent {
+    Vehicle(VehicleEvent),
+    VehicleRemoved {
+        vehicle_id: String,
+        route_id: Option<String>,
+    },
+    TripUpdate(TripUpdateEvent),
+    TripUpdateRemoved {
+        trip_id: String,
+        route_id: Option<String>,
+    },
+    Alert(AlertEvent),
+    AlertRemoved {
+        alert_id: String,
+    },
+}
+
+impl LiveEvent {
+    /// Name used for the SSE event field.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LiveEvent::Vehicle(_) => "vehicle",
+            LiveEvent::VehicleRemoved { .. } => "vehicle_removed",
+            LiveEvent::TripUpdate(_) => "trip_update",
+            LiveEvent::TripUpdateRemoved { .. } => "trip_update_removed",
+            LiveEvent::Alert(_) => "alert",
+            LiveEvent::AlertRemoved { .. } => "alert_removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VehicleEvent {
+    pub vehicle_id: String,
+    pub label: Option<String>,
+    pub trip_id: Option<String>,
+    pub route_id: Option<String>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub bearing: Option<f32>,
+    pub speed: Option<f32>,
+    pub stop_id: Option<String>,
+    pub current_stop_sequence: Option<u32>,
+    pub current_status: Option<String>,
+    pub occupancy_status: Option<String>,
+    pub timestamp: Option<u64>,
+}
+
+impl VehicleEvent {
+    pub fn new(entity_id: &str, vehicle: &VehiclePosition) -> VehicleEvent {
+        let descriptor = vehicle.vehicle.as_ref();
+        VehicleEvent {
+            vehicle_id: descriptor
+                .and_then(|v| v.id.clone())
+                .unwrap_or_else(|| entity_id.to_owned()),
+            label: descriptor.and_then(|v| v.label.clone()),
+            trip_id: vehicle.trip.as_ref().and_then(|t| t.trip_id.clone()),
+            route_id: vehicle.trip.as_ref().and_then(|t| t.route_id.clone()),
+            latitude: vehicle.position.as_ref().map(|p| p.latitude),
+            longitude: vehicle.position.as_ref().map(|p| p.longitude),
+            bearing: vehicle.position.as_ref().and_then(|p| p.bearing),
+            speed: vehicle.position.as_ref().and_then(|p| p.speed),
+            stop_id: vehicle.stop_id.clone(),
+            current_stop_sequence: vehicle.current_stop_sequence,
+            current_status: vehicle
+                .current_status
+                .map(|_| vehicle.current_status().as_str_name().to_owned()),
+            occupancy_status: vehicle
+                .occupancy_status
+                .map(|_| vehicle.occupancy_status().as_str_name().to_owned()),
+            timestamp: vehicle.timestamp,
+        }
+    }
+}
+
+/// A trip update. The feed timestamp is left out on purpose,
+/// so unchanged predictions compare equal between polls.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TripUpdateEvent {
+    pub trip_id: String,
+    pub route_id: Option<String>,
+    pub start_date: Option<String>,
+    pub schedule_relationship: String,
+    pub vehicle_id: Option<String>,
+    pub delay: Option<i32>,
+    pub stop_time_updates: Vec<StopTimeUpdateEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StopTimeUpdateEvent {
+    pub stop_sequence: Option<u32>,
+    pub stop_id: Option<String>,
+    pub arrival_delay: Option<i32>,
+    pub arrival_time: Option<i64>,
+    pub departure_delay: Option<i32>,
+    pub departure_time: Option<i64>,
+    pub schedule_relationship: String,
+}
+
+impl TripUpdateEvent {
+    pub fn new(entity_id: &str, update: &TripUpdate) -> TripUpdateEvent {
+        TripUpdateEvent {
+            trip_id: update
+                .trip
+                .trip_id
+                .clone()
+                .unwrap_or_else(|| entity_id.to_owned()),
+            route_id: update.trip.route_id.clone(),
+            start_date: update.trip.start_date.clone(),
+            schedule_relationship: update.trip.schedule_relationship().as_str_name().to_owned(),
+            vehicle_id: update.vehicle.as_ref().and_then(|v| v.id.clone()),
+            delay: update.delay,
+            stop_time_updates: update
+                .stop_time_update
+                .iter()
+                .map(|stu| StopTimeUpdateEvent {
+                    stop_sequence: stu.stop_sequence,
+                    stop_id: stu.stop_id.clone(),
+                    arrival_delay: stu.arrival.and_then(|a| a.delay),
+                    arrival_time: stu.arrival.and_then(|a| a.time),
+                    departure_delay: stu.departure.and_then(|d| d.delay),
+                    departure_time: stu.departure.and_then(|d| d.time),
+                    schedule_relationship: stu.schedule_relationship().as_str_name().to_owned(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertEvent {
+    pub alert_id: String,
+    pub cause: String,
+    pub effect: String,
+    pub header: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub active_periods: Vec<(Option<u64>, Option<u64>)>,
+    pub route_ids: Vec<String>,
+    pub stop_ids: Vec<String>,
+    pub trip_ids: Vec<String>,
+}
+
+impl AlertEvent {
+    pub fn new(entity_id: &str, alert: &Alert) -> AlertEvent {
+        let entities = &alert.informed_entity;
+        AlertEvent {
+            alert_id: entity_id.to_owned(),
+            cause: alert.cause().as_str_name().to_owned(),
+            effect: alert.effect().as_str_name().to_owned(),
+            header: alert.header_text.as_ref().and_then(english),
+            description: alert.description_text.as_ref().and_then(english),
+            url: alert.url.as_ref().and_then(english),
+            active_periods: alert
+                .active_period
+                .iter()
+                .map(|p| (p.start, p.end))
+                .collect(),
+            route_ids: entities.iter().filter_map(|e| e.route_id.clone()).collect(),
+            stop_ids: entities.iter().filter_map(|e| e.stop_id.clone()).collect(),
+            trip_ids: entities
+                .iter()
+                .filter_map(|e| e.trip.as_ref()?.trip_id.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Picks the english translation, falling back to whatever is first.
+fn english(text: &TranslatedString) -> Option<String> {
+    text.translation
+        .iter()
+        .find(|t| t.language.as_deref().is_some_and(|l| l.starts_with("en")))
+        .or(text.translation.first())
+        .map(|t| t.text.clone())
+}
+
+/// Splits a feed entity into the events it describes.
+pub fn entity_events(entity: &FeedEntity) -> Vec<LiveEvent> {
+    let mut events = vec![];
+    if let Some(vehicle) = &entity.vehicle {
+        events.push(LiveEvent::Vehicle(VehicleEvent::new(&entity.id, vehicle)));
+    }
+    if let Some(update) = &entity.trip_update {
+        events.push(LiveEvent::TripUpdate(TripUpdateEvent::new(
+            &entity.id, update,
+        )));
+    }
+    if let Some(alert) = &entity.alert {
+        events.push(LiveEvent::Alert(AlertEvent::new(&entity.id, alert)));
+    }
+    events
+}
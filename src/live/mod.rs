@@ -0,0 +1,297 @@
+//! LIVE
+//!
+//! Pushes realtime changes out to subscribers after each poll.
+//! Keeps the last snapshot of vehicles, trip updates and alerts around,
+//! diffs every new poll against it, and broadcasts just what changed.
+
+pub mod events;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use futures::{Stream, StreamExt, stream};
+use tokio::sync::{RwLock, broadcast, broadcast::error::RecvError};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    gtfs::RealtimeGtfs,
+    live::events::{AlertEvent, LiveEvent, TripUpdateEvent, VehicleEvent, entity_events},
+    tiles::geometry::BBox,
+};
+
+/// How many polls worth of changes a slow subscriber can fall behind by.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// The current state of the realtime feeds.
+#[derive(Debug, Default)]
+struct Snapshot {
+    vehicles: HashMap<String, VehicleEvent>,
+    trip_updates: HashMap<String, TripUpdateEvent>,
+    alerts: HashMap<String, AlertEvent>,
+}
+
+impl Snapshot {
+    fn from_realtime(realtime: &RealtimeGtfs) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for entity in realtime.0.iter().flat_map(|m| &m.entity) {
+            if entity.is_deleted() {
+                continue;
+            }
+            for event in entity_events(entity) {
+                match event {
+                    LiveEvent::Vehicle(v) => {
+                        snapshot.vehicles.insert(v.vehicle_id.clone(), v);
+                    }
+                    LiveEvent::TripUpdate(t) => {
+                        snapshot.trip_updates.insert(t.trip_id.clone(), t);
+                    }
+                    LiveEvent::Alert(a) => {
+                        snapshot.alerts.insert(a.alert_id.clone(), a);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Every event needed to bring an empty client up to date.
+    /// Vehicles go first so bbox filters know where trips are.
+    fn events(&self) -> Vec<LiveEvent> {
+        self.vehicles
+            .values()
+            .cloned()
+            .map(LiveEvent::Vehicle)
+            .chain(
+                self.trip_updates
+                    .values()
+                    .cloned()
+                    .map(LiveEvent::TripUpdate),
+            )
+            .chain(self.alerts.values().cloned().map(LiveEvent::Alert))
+            .collect()
+    }
+
+    /// Events that turn self into next.
+    fn diff(&self, next: &Snapshot) -> Vec<LiveEvent> {
+        let mut events = vec![];
+
+        for (id, vehicle) in &next.vehicles {
+            if self.vehicles.get(id) != Some(vehicle) {
+                events.push(LiveEvent::Vehicle(vehicle.clone()));
+            }
+        }
+        for (id, vehicle) in &self.vehicles {
+            if !next.vehicles.contains_key(id) {
+                events.push(LiveEvent::VehicleRemoved {
+                    vehicle_id: id.clone(),
+                    route_id: vehicle.route_id.clone(),
+                });
+            }
+        }
+
+        for (id, update) in &next.trip_updates {
+            if self.trip_updates.get(id) != Some(update) {
+                events.push(LiveEvent::TripUpdate(update.clone()));
+            }
+        }
+        for (id, update) in &self.trip_updates {
+            if !next.trip_updates.contains_key(id) {
+                events.push(LiveEvent::TripUpdateRemoved {
+                    trip_id: id.clone(),
+                    route_id: update.route_id.clone(),
+                });
+            }
+        }
+
+        for (id, alert) in &next.alerts {
+            if self.alerts.get(id) != Some(alert) {
+                events.push(LiveEvent::Alert(alert.clone()));
+            }
+        }
+        for id in self.alerts.keys() {
+            if !next.alerts.contains_key(id) {
+                events.push(LiveEvent::AlertRemoved {
+                    alert_id: id.clone(),
+                });
+            }
+        }
+
+        events
+    }
+}
+
+/// Broadcasts realtime changes. Cheap to clone, all clones share one feed.
+#[derive(Clone)]
+pub struct LiveFeed {
+    sender: broadcast::Sender<Arc<Vec<LiveEvent>>>,
+    snapshot: Arc<RwLock<Snapshot>>,
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        LiveFeed {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            snapshot: Arc::default(),
+        }
+    }
+}
+
+impl LiveFeed {
+    /// Diffs a fresh poll against the last one and broadcasts the changes.
+    #[instrument(skip_all)]
+    pub async fn publish(&self, realtime: &RealtimeGtfs) {
+        let next = Snapshot::from_realtime(realtime);
+        let mut snapshot = self.snapshot.write().await;
+        let events = snapshot.diff(&next);
+        *snapshot = next;
+
+        debug!(
+            changes = events.len(),
+            subscribers = self.sender.receiver_count()
+        );
+        if !events.is_empty() {
+            // Only errors when nobody is listening, which is fine.
+            let _ = self.sender.send(Arc::new(events));
+        }
+    }
+
+    /// Returns the current state along with a receiver for every change after it.
+    pub async fn subscribe(&self) -> (Vec<LiveEvent>, broadcast::Receiver<Arc<Vec<LiveEvent>>>) {
+        // Holding the read lock means no publish can sneak in between the two.
+        let snapshot = self.snapshot.read().await;
+        (snapshot.events(), self.sender.subscribe())
+    }
+}
+
+/// A subscriber's changes, an event at a time. A subscriber that falls more than
+/// CHANNEL_CAPACITY polls behind has missed changes it can't get back, so its
+/// stream ends there, and the client reconnects to start over from a fresh snapshot.
+pub fn changes(
+    receiver: broadcast::Receiver<Arc<Vec<LiveEvent>>>,
+) -> impl Stream<Item = LiveEvent> {
+    stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(events) => Some((events, receiver)),
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Live subscriber lagged, closing it to resync");
+                None
+            }
+            Err(RecvError::Closed) => None,
+        }
+    })
+    .flat_map(|events| stream::iter(events.to_vec()))
+}
+
+/// What a subscriber wants to hear about.
+///
+/// Vehicles are filtered by route and position. Trip updates and alerts have no
+/// position of their own, so under a bbox they follow the vehicles: they pass
+/// when a vehicle on that trip (or route, for alerts) was last seen inside it.
+#[derive(Debug, Default)]
+pub struct LiveFilter {
+    pub route_id: Option<String>,
+    pub bbox: Option<BBox>,
+    trips_inside: HashSet<String>,
+    routes_inside: HashMap<String, usize>,
+    /// vehicle_id -> (trip_id, route_id) of every vehicle inside the bbox.
+    vehicles_inside: HashMap<String, (Option<String>, Option<String>)>,
+}
+
+impl LiveFilter {
+    pub fn new(route_id: Option<String>, bbox: Option<BBox>) -> LiveFilter {
+        LiveFilter {
+            route_id,
+            bbox,
+            ..Default::default()
+        }
+    }
+
+    fn route_matches(&self, route_id: Option<&String>) -> bool {
+        self.route_id.is_none() || self.route_id.as_ref() == route_id
+    }
+
+    /// Whether the event should be sent. Takes &mut as it tracks vehicles for the bbox.
+    pub fn matches(&mut self, event: &LiveEvent) -> bool {
+        match event {
+            LiveEvent::Vehicle(vehicle) => {
+                let Some(bbox) = self.bbox else {
+                    return self.route_matches(vehicle.route_id.as_ref());
+                };
+                let inside = match (vehicle.longitude, vehicle.latitude) {
+                    (Some(lon), Some(lat)) => bbox.contains(lon.into(), lat.into()),
+                    _ => false,
+                };
+                let was_inside = self.vehicles_inside.contains_key(&vehicle.vehicle_id);
+                self.track_vehicle(vehicle, inside);
+                self.route_matches(vehicle.route_id.as_ref()) && (inside || was_inside)
+            }
+            LiveEvent::VehicleRemoved {
+                vehicle_id,
+                route_id,
+            } => {
+                let was_inside = self.vehicles_inside.contains_key(vehicle_id);
+                self.untrack_vehicle(vehicle_id);
+                self.route_matches(route_id.as_ref()) && (self.bbox.is_none() || was_inside)
+            }
+            LiveEvent::TripUpdate(TripUpdateEvent {
+                trip_id, route_id, ..
+            })
+            | LiveEvent::TripUpdateRemoved { trip_id, route_id } => {
+                self.route_matches(route_id.as_ref())
+                    && (self.bbox.is_none() || self.trips_inside.contains(trip_id))
+            }
+            LiveEvent::Alert(alert) => {
+                let route_ok = self.route_id.is_none()
+                    || alert.route_ids.is_empty()
+                    || alert.route_ids.iter().any(|r| self.route_matches(Some(r)));
+                let bbox_ok = self.bbox.is_none()
+                    || alert.route_ids.is_empty()
+                    || alert
+                        .route_ids
+                        .iter()
+                        .any(|r| self.routes_inside.contains_key(r));
+                route_ok && bbox_ok
+            }
+            LiveEvent::AlertRemoved { .. } => true,
+        }
+    }
+
+    fn track_vehicle(&mut self, vehicle: &VehicleEvent, inside: bool) {
+        self.untrack_vehicle(&vehicle.vehicle_id);
+        if !inside {
+            return;
+        }
+        if let Some(trip_id) = &vehicle.trip_id {
+            self.trips_inside.insert(trip_id.clone());
+        }
+        if let Some(route_id) = &vehicle.route_id {
+            *self.routes_inside.entry(route_id.clone()).or_default() += 1;
+        }
+        self.vehicles_inside.insert(
+            vehicle.vehicle_id.clone(),
+            (vehicle.trip_id.clone(), vehicle.route_id.clone()),
+        );
+    }
+
+    fn untrack_vehicle(&mut self, vehicle_id: &str) {
+        let Some((trip_id, route_id)) = self.vehicles_inside.remove(vehicle_id) else {
+            return;
+        };
+        if let Some(trip_id) = trip_id {
+            self.trips_inside.remove(&trip_id);
+        }
+        if let Some(route_id) = route_id
+            && let Some(count) = self.routes_inside.get_mut(&route_id)
+        {
+            *count -= 1;
+            if *count == 0 {
+                self.routes_inside.remove(&route_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Live Tests
+//!
+//! Checks snapshot diffing and subscriber filtering.
+
+use std::sync::Arc;
+
+use futures::StreamExt;
+
+use super::{CHANNEL_CAPACITY, LiveFeed, LiveFilter, Snapshot, changes};
+use crate::live::events::{LiveEvent, TripUpdateEvent, VehicleEvent};
+use crate::tiles::geometry::BBox;
+
+fn vehicle(vehicle_id: &str, trip_id: &str, lon: f32, lat: f32) -> VehicleEvent {
+    VehicleEvent {
+        vehicle_id: vehicle_id.into(),
+        label: None,
+        trip_id: Some(trip_id.into()),
+        route_id: Some("66-3454".into()),
+        latitude: Some(lat),
+        longitude: Some(lon),
+        bearing: None,
+        speed: None,
+        stop_id: None,
+        current_stop_sequence: None,
+        current_status: None,
+        occupancy_status: None,
+        timestamp: None,
+    }
+}
+
+fn trip_update(trip_id: &str, delay: i32) -> TripUpdateEvent {
+    TripUpdateEvent {
+        trip_id: trip_id.into(),
+        route_id: Some("66-3454".into()),
+        start_date: None,
+        schedule_relationship: "SCHEDULED".into(),
+        vehicle_id: None,
+        delay: Some(delay),
+        stop_time_updates: vec![],
+    }
+}
+
+#[test]
+fn test_snapshot_diff() {
+    let mut before = Snapshot::default();
+    before.trip_updates.insert("a".into(), trip_update("a", 60));
+    before.trip_updates.insert("b".into(), trip_update("b", 0));
+
+    let mut after = Snapshot::default();
+    after.trip_updates.insert("a".into(), trip_update("a", 60));
+    after.trip_updates.insert("c".into(), trip_update("c", 120));
+
+    let events = before.diff(&after);
+    assert_eq!(events.len(), 2);
+    assert!(events.contains(&LiveEvent::TripUpdate(trip_update("c", 120))));
+    assert!(events.contains(&LiveEvent::TripUpdateRemoved {
+        trip_id: "b".into(),
+        route_id: Some("66-3454".into()),
+    }));
+}
+
+#[test]
+fn test_bbox_filter_follows_vehicles() {
+    let bbox = BBox {
+        min_lon: 153.0,
+        min_lat: -27.5,
+        max_lon: 153.1,
+        max_lat: -27.4,
+    };
+    let mut filter = LiveFilter::new(None, Some(bbox));
+
+    let inside = LiveEvent::Vehicle(vehicle("v1", "a", 153.05, -27.45));
+    let outside = LiveEvent::Vehicle(vehicle("v2", "b", 152.0, -27.45));
+    assert!(filter.matches(&inside));
+    assert!(!filter.matches(&outside));
+
+    assert!(filter.matches(&LiveEvent::TripUpdate(trip_update("a", 30))));
+    assert!(!filter.matches(&LiveEvent::TripUpdate(trip_update("b", 30))));
+
+    // Leaving the bbox is sent once so clients can drop the vehicle, then it goes quiet.
+    let left = LiveEvent::Vehicle(vehicle("v1", "a", 152.0, -27.45));
+    assert!(filter.matches(&left));
+    assert!(!filter.matches(&left));
+    assert!(!filter.matches(&LiveEvent::TripUpdate(trip_update("a", 30))));
+}
+
+#[tokio::test]
+async fn test_lagged_subscriber_is_closed() {
+    let event = || Arc::new(vec![LiveEvent::TripUpdate(trip_update("a", 30))]);
+
+    let feed = LiveFeed::default();
+    let (_, receiver) = feed.subscribe().await;
+    feed.sender.send(event()).unwrap();
+    feed.sender.send(event()).unwrap();
+    drop(feed);
+    assert_eq!(changes(receiver).count().await, 2);
+
+    // Changes have been dropped, so rather than carry on without them the stream ends.
+    let feed = LiveFeed::default();
+    let (_, receiver) = feed.subscribe().await;
+    for _ in 0..=CHANNEL_CAPACITY {
+        feed.sender.send(event()).unwrap();
+    }
+    assert_eq!(changes(receiver).count().await, 0);
+}
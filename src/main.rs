@@ -1,21 +1,45 @@
+pub mod analytics;
+pub mod api;
+pub mod archive;
 pub mod bridge;
 pub mod db;
+pub mod diff;
+pub mod export;
+pub mod geo;
 pub mod gtfs;
+pub mod health;
+pub mod live;
+pub mod merge;
+pub mod metrics;
+pub mod routing;
+pub mod search;
+pub mod tiles;
 pub mod vars;
 
 use anyhow::{Result, bail};
-use prost::Message;
+use chrono::{DateTime, Days, Local, NaiveDate, Utc};
 use reqwest::Client;
-use std::time::Duration;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use std::{env, sync::Arc};
 use tokio_cron_scheduler::{Job, JobScheduler};
-use tracing::{debug, error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, field::MakeExt};
 
 use crate::db::queries;
 use crate::{
+    archive::RawArchive,
+    bridge::static_bridge::ImportMode,
     db::Db,
-    gtfs::{last_modified, load_realtime_gtfs, load_static_gtfs},
+    gtfs::{
+        RealtimeGtfs, StaticGtfs, fetch_realtime_gtfs, last_modified, load_static_gtfs,
+        realtime::LatestRealtime,
+    },
+    health::LastPoll,
+    live::LiveFeed,
+    metrics::Metrics,
+    routing::Planner,
+    tiles::TileCache,
     transit_realtime::FeedMessage,
     vars::{REALTIME_URL, STATIC_URL, realtime_urls},
 };
@@ -24,10 +48,21 @@ pub mod transit_realtime {
     include!(concat!(env!("OUT_DIR"), "/transit_realtime.rs"));
 }
 
+pub mod vector_tile {
+    include!(concat!(env!("OUT_DIR"), "/vector_tile.rs"));
+}
+
 #[derive(Clone)]
 pub struct State {
     db: Db,
     client: Client,
+    tiles: TileCache,
+    live: LiveFeed,
+    realtime: LatestRealtime,
+    planner: Planner,
+    archive: Option<RawArchive>,
+    metrics: Metrics,
+    last_poll: LastPoll,
 }
 
 #[tokio::main]
@@ -37,6 +72,10 @@ async fn main() -> Result<()> {
         .map_fmt_fields(|f| f.debug_alt())
         .init();
 
+    if let Some((old, new)) = vars::diff_feeds() {
+        return diff_feeds(&old, &new).await;
+    }
+
     // Set up the DB connection pool
     let mut db = Db::connect().await?;
     db.run_migrations().await?;
@@ -46,15 +85,39 @@ async fn main() -> Result<()> {
     // Set up the reqwest client
     let client = Client::new();
 
-    let state = State { db, client };
+    let state = State {
+        db,
+        client,
+        tiles: TileCache::default(),
+        live: LiveFeed::default(),
+        realtime: LatestRealtime::default(),
+        planner: Planner::default(),
+        archive: RawArchive::from_env(),
+        metrics: Metrics::default(),
+        last_poll: LastPoll::default(),
+    };
 
     // fire poll once immediately on boot
     static_poll(state.clone()).await?;
 
     setup_static_poll_schedule(state.clone()).await?;
+    setup_analytics_schedule(state.clone()).await?;
+
+    tokio::spawn({
+        let state = state.clone();
+        async move {
+            if let Err(e) = api::serve(state).await {
+                error!(e=?e);
+            }
+        }
+    });
+
+    if let Some(from) = vars::replay_from() {
+        return replay(&state, from, vars::replay_to().unwrap_or(from)).await;
+    }
 
     loop {
-        if let Err(e) = dynamic_poll().await {
+        if let Err(e) = dynamic_poll(&state).await {
             error!(e=?e);
         }
         tokio::time::sleep(Duration::from_mins(1)).await;
@@ -88,6 +151,28 @@ async fn setup_static_poll_schedule(state: State) -> Result<()> {
     return Ok(());
 }
 
+/// Rebuilds the analytics summaries (and their Parquet export) a few minutes past every hour.
+async fn setup_analytics_schedule(state: State) -> Result<()> {
+    let sched = JobScheduler::new().await?;
+    sched
+        .add(Job::new_async("0 5 * * * *", move |_uuid, _l| {
+            let state = state.clone();
+            Box::pin(async move {
+                if let Err(e) = analytics::summarise_recent(&state.db).await {
+                    error!(e=?e);
+                }
+                if let Some(dir) = vars::parquet_export_dir()
+                    && let Err(e) = export::parquet::write_recent(&state.db, &dir).await
+                {
+                    error!(e=?e);
+                }
+            })
+        })?)
+        .await?;
+    sched.start().await?;
+    Ok(())
+}
+
 async fn static_poll(state: State) -> Result<()> {
     // do the stuff
     let last_update = queries::get_feed_last_update("SEQ".into(), &state.db.0).await?;
@@ -95,35 +180,152 @@ async fn static_poll(state: State) -> Result<()> {
     let gtfs = load_static_gtfs("./seq_gtfs.zip".to_owned(), last_update).await?;
 
     if let Some(gtfs) = gtfs {
-        gtfs.insert_db(state.db.clone()).await;
+        // Once there's a feed in, later ones update it in place.
+        let mode = match last_update {
+            Some(_) => ImportMode::Incremental,
+            None => ImportMode::Full,
+        };
+        if let Some(dir) = vars::feed_diff_dir() {
+            // The diff is only a report, so it mustn't hold up the import.
+            if let Err(e) = write_static_diff(&state, &gtfs, &dir).await {
+                warn!(e=?e, "Couldn't diff the static feed");
+            }
+        }
+        let merge_sources = vars::merge_feeds();
+        let import = if merge_sources.is_empty() {
+            gtfs.insert_db(state.db.clone(), mode).await
+        } else {
+            let mut feeds = vec![("SEQ".to_owned(), gtfs.parse().collect().await?)];
+            feeds.extend(merge::load_feeds(&merge_sources).await?);
+            merge::merge(feeds, &merge::MergeConfig::from_env())
+                .insert_db(&state.db, &gtfs.last_update, mode)
+                .await
+        };
+        let import = import.inspect_err(|_| state.metrics.static_import_failed())?;
+        state.metrics.static_import(&import);
+        routing::transfers::refresh(&state.db).await?;
+        state.tiles.invalidate().await;
+        state.planner.invalidate().await;
+
+        if let Some(dir) = vars::geojson_export_dir() {
+            export::geojson::write_geojson(&state.db, &dir).await?;
+        }
+        if let Some(dir) = vars::parquet_export_dir() {
+            export::parquet::write_static(&state.db, &dir).await?;
+        }
     }
 
     Ok(())
 }
 
-async fn dynamic_poll() -> Result<()> {
-    let pb = reqwest::get("https://gtfsrt.api.translink.com.au/api/realtime/SEQ/TripUpdates")
-        .await?
-        .bytes()
-        .await?;
+/// Logs what changed between the feed in the db and the one about to be imported.
+async fn write_static_diff(state: &State, gtfs: &StaticGtfs, dir: &Path) -> Result<()> {
+    let old = diff::Feed::load(&state.db).await?;
+    let new = diff::Feed::from_rows(gtfs.parse().collect().await?);
+    let diff = diff::diff(&old, &new);
+    info!("Static feed changes:\n{diff}");
+    diff::write_diff(dir, &diff, Local::now())?;
+    Ok(())
+}
+
+/// Prints what changed between two feed zips, also writing it to FEED_DIFF_DIR if set.
+async fn diff_feeds(old: &Path, new: &Path) -> Result<()> {
+    let (old, new) = tokio::try_join!(diff::Feed::from_path(old), diff::Feed::from_path(new))?;
+    let diff = diff::diff(&old, &new);
+    print!("{diff}");
+    if let Some(dir) = vars::feed_diff_dir() {
+        diff::write_diff(&dir, &diff, Local::now())?;
+    }
+    Ok(())
+}
 
-    let message = transit_realtime::FeedMessage::decode(pb)?;
+async fn dynamic_poll(state: &State) -> Result<()> {
+    let start = Instant::now();
+    let polled = poll_realtime(state).await;
+    state
+        .metrics
+        .realtime_polled(start.elapsed(), polled.is_ok());
+    polled
+}
 
-    // let time = message.header.timestamp() as i64;
-    // let time = DateTime::from_timestamp(time, 0);
+async fn poll_realtime(state: &State) -> Result<()> {
+    let start = Instant::now();
+    let feeds = fetch_realtime_gtfs(realtime_urls()).await?;
+    state.metrics.realtime_fetched(start.elapsed());
+    let realtime = RealtimeGtfs::decode(&feeds)
+        .inspect_err(|_| state.metrics.realtime_decode_failed(&feeds))?;
+    state.metrics.realtime_decoded(&feeds, &realtime);
+    if let Some(archive) = &state.archive {
+        // A missed archive poll shouldn't stop the live feed updating.
+        if let Err(e) = archive.append(&feeds, &realtime).await {
+            warn!(e=?e);
+        }
+    }
+    process_realtime(state, realtime, Utc::now()).await?;
+    state.last_poll.succeeded(Utc::now()).await;
 
-    // debug!(header=?message.header);
-    // debug!(time=?time);
+    info!("Polled");
 
-    for entity in message.entity {
-        let Some(trip) = entity.trip_update.as_ref() else {
-            continue;
-        };
+    Ok(())
+}
+
+/// Everything done with a realtime poll, whether it was just fetched or is being replayed.
+async fn process_realtime(
+    state: &State,
+    mut realtime: RealtimeGtfs,
+    fetched_at: DateTime<Utc>,
+) -> Result<()> {
+    realtime.enrich(&state.db).await?;
+    let realtime = Arc::new(realtime);
 
-        debug!(trip=?trip);
+    state.live.publish(&realtime).await;
+    // Analytics shouldn't hold back the live feed.
+    if let Err(e) = analytics::archive(&state.db, &realtime, fetched_at).await {
+        error!(e=?e);
     }
+    state.planner.update_realtime(realtime.clone()).await?;
+    state.realtime.set(realtime).await;
 
-    info!("Polled");
+    Ok(())
+}
+
+/// Feeds archived polls from a range of days back through process_realtime,
+/// waiting between them REPLAY_SPEED times faster than they were fetched.
+async fn replay(state: &State, from: NaiveDate, to: NaiveDate) -> Result<()> {
+    let Some(archive) = &state.archive else {
+        bail!("REPLAY_FROM needs REALTIME_ARCHIVE_DIR to replay from");
+    };
+    let speed = vars::replay_speed();
+    info!(%from, %to, speed, "Replaying realtime archive");
+
+    let mut previous: Option<DateTime<Utc>> = None;
+    let mut date = from;
+    while date <= to {
+        for poll in archive.read_day(date)? {
+            if let Some(previous) = previous
+                && speed > 0.0
+                && let Ok(gap) = (poll.fetched_at - previous).to_std()
+            {
+                tokio::time::sleep(gap.div_f64(speed)).await;
+            }
+            previous = Some(poll.fetched_at);
+
+            let feeds: Vec<_> = poll.feeds.into_iter().map(|f| f.feed).collect();
+            let realtime = match RealtimeGtfs::decode(&feeds) {
+                Ok(realtime) => realtime,
+                Err(e) => {
+                    error!(fetched_at = %poll.fetched_at, e=?e, "Skipping archived poll");
+                    continue;
+                }
+            };
+            if let Err(e) = process_realtime(state, realtime, poll.fetched_at).await {
+                error!(e=?e);
+            }
+            info!(fetched_at = %poll.fetched_at, "Replayed");
+        }
+        date = date + Days::new(1);
+    }
 
+    info!("Finished replaying realtime archive");
     Ok(())
 }
@@ -0,0 +1,240 @@
+//! MERGE
+//!
+//! Combines several static feeds into one before import, for planning across regions.
+//! The first feed keeps its ids. Ids in later feeds that clash with ones already
+//! taken get the feed's prefix, and stops with the same name close enough to a
+//! stop from an earlier feed are folded into it. Agencies are stored by name, so
+//! ones with the same name as an earlier feed's are folded into it too.
+
+#[cfg(test)]
+mod tests;
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use futures::future::try_join_all;
+use tracing::{info, instrument};
+
+use crate::{
+    bridge::static_bridge::FeedRows,
+    db::types::Stop,
+    geo::PointGrid,
+    gtfs::{read_zip, static_gtfs},
+    vars,
+};
+
+#[derive(Debug, Clone)]
+pub struct MergeConfig {
+    /// Furthest apart two stops with the same name can be and still be the same stop.
+    pub stop_distance_m: f64,
+}
+
+impl MergeConfig {
+    pub fn from_env() -> MergeConfig {
+        MergeConfig {
+            stop_distance_m: vars::merge_stop_distance_m(),
+        }
+    }
+}
+
+/// Loads each (prefix, path or url) feed to merge.
+pub async fn load_feeds(sources: &[(String, String)]) -> Result<Vec<(String, FeedRows)>> {
+    try_join_all(sources.iter().cloned().map(|(prefix, path)| async move {
+        info!(prefix, path, "Loading static GTFS to merge");
+        let zip = read_zip(&path).await?;
+        anyhow::Ok((prefix, static_gtfs::parse(zip).collect().await?))
+    }))
+    .await
+}
+
+/// Merges (prefix, feed) pairs into one feed. feed_info comes from the first feed.
+#[instrument(skip_all)]
+pub fn merge(feeds: Vec<(String, FeedRows)>, config: &MergeConfig) -> FeedRows {
+    let many = feeds.len() > 1;
+    let mut merged = FeedRows::default();
+    let mut taken = Taken::default();
+
+    for (i, (prefix, mut feed)) in feeds.into_iter().enumerate() {
+        // Routes can leave out agency_id when their feed has one agency, which stops
+        // being enough once there are others. The prefix stands in for it.
+        if many && let [agency] = &mut feed.agencies[..] {
+            let agency_id = agency.agency_id.get_or_insert_with(|| prefix.clone());
+            for route in &mut feed.routes {
+                route.agency_id.get_or_insert_with(|| agency_id.clone());
+            }
+        }
+
+        // The earlier agency's id, for each of this feed's agencies folded into one.
+        let mut same_agencies: HashMap<Option<String>, Option<String>> = HashMap::new();
+        feed.agencies.retain(|agency| {
+            let Some(same) = merged
+                .agencies
+                .iter()
+                .find(|a| a.agency_name == agency.agency_name)
+            else {
+                return true;
+            };
+            same_agencies.insert(agency.agency_id.clone(), same.agency_id.clone());
+            false
+        });
+
+        let rename = |taken: &mut HashSet<String>, id: &String| {
+            let new = match taken.contains(id) {
+                true => format!("{prefix}:{id}"),
+                false => id.clone(),
+            };
+            taken.insert(new.clone());
+            (id.clone(), new)
+        };
+        let agency_ids: HashMap<String, String> = feed
+            .agencies
+            .iter()
+            .filter_map(|a| a.agency_id.as_ref())
+            .map(|id| rename(&mut taken.agencies, id))
+            .collect();
+        let route_ids: HashMap<String, String> = feed
+            .routes
+            .iter()
+            .map(|r| rename(&mut taken.routes, &r.route_id))
+            .collect();
+        let trip_ids: HashMap<String, String> = feed
+            .trips
+            .iter()
+            .map(|t| rename(&mut taken.trips, &t.trip_id))
+            .collect();
+        let service_ids: HashSet<&String> = feed
+            .calendar
+            .iter()
+            .map(|c| &c.service_id)
+            .chain(feed.calendar_dates.iter().map(|c| &c.service_id))
+            .chain(feed.trips.iter().map(|t| &t.service_id))
+            .collect();
+        let service_ids: HashMap<String, String> = service_ids
+            .into_iter()
+            .map(|id| rename(&mut taken.services, id))
+            .collect();
+        let shape_ids: HashSet<&String> = feed.shapes.iter().map(|s| &s.shape_id).collect();
+        let shape_ids: HashMap<String, String> = shape_ids
+            .into_iter()
+            .map(|id| rename(&mut taken.shapes, id))
+            .collect();
+
+        // Only stops from earlier feeds are candidates, a feed's own stops are all kept.
+        let existing: Vec<(usize, (f64, f64))> = merged
+            .stops
+            .iter()
+            .enumerate()
+            .filter_map(|(s, stop)| Some((s, (stop.stop_lat?, stop.stop_lon?))))
+            .collect();
+        let grid = PointGrid::new(
+            existing.iter().map(|&(_, point)| point).collect(),
+            config.stop_distance_m,
+        );
+        let mut stop_ids = HashMap::new();
+        let mut stops = vec![];
+        for stop in feed.stops {
+            let same = match (stop.stop_lat, stop.stop_lon) {
+                (Some(lat), Some(lon)) if i > 0 => grid
+                    .within(lat, lon, config.stop_distance_m)
+                    .into_iter()
+                    .map(|(p, _)| &merged.stops[existing[p].0])
+                    .find(|other| same_stop(&stop, other)),
+                _ => None,
+            };
+            match same {
+                Some(other) => {
+                    stop_ids.insert(stop.stop_id, other.stop_id.clone());
+                }
+                None => {
+                    let (old, new) = rename(&mut taken.stops, &stop.stop_id);
+                    stop_ids.insert(old, new);
+                    stops.push(stop);
+                }
+            }
+        }
+
+        let map = |ids: &HashMap<String, String>, id: &mut String| {
+            if let Some(new) = ids.get(id) {
+                *id = new.clone();
+            }
+        };
+        for mut agency in feed.agencies {
+            if let Some(id) = &mut agency.agency_id {
+                map(&agency_ids, id);
+            }
+            merged.agencies.push(agency);
+        }
+        for mut stop in stops {
+            map(&stop_ids, &mut stop.stop_id);
+            if let Some(parent) = &mut stop.parent_station {
+                map(&stop_ids, parent);
+            }
+            merged.stops.push(stop);
+        }
+        for mut route in feed.routes {
+            map(&route_ids, &mut route.route_id);
+            if let Some(same) = same_agencies.get(&route.agency_id) {
+                route.agency_id = same.clone();
+            } else if let Some(agency_id) = &mut route.agency_id {
+                map(&agency_ids, agency_id);
+            }
+            merged.routes.push(route);
+        }
+        for mut trip in feed.trips {
+            map(&trip_ids, &mut trip.trip_id);
+            map(&route_ids, &mut trip.route_id);
+            map(&service_ids, &mut trip.service_id);
+            if let Some(shape_id) = &mut trip.shape_id {
+                map(&shape_ids, shape_id);
+            }
+            merged.trips.push(trip);
+        }
+        for mut stop_time in feed.stop_times {
+            map(&trip_ids, &mut stop_time.trip_id);
+            map(&stop_ids, &mut stop_time.stop_id);
+            merged.stop_times.push(stop_time);
+        }
+        for mut calendar in feed.calendar {
+            map(&service_ids, &mut calendar.service_id);
+            merged.calendar.push(calendar);
+        }
+        for mut calendar_date in feed.calendar_dates {
+            map(&service_ids, &mut calendar_date.service_id);
+            merged.calendar_dates.push(calendar_date);
+        }
+        for mut shape in feed.shapes {
+            map(&shape_ids, &mut shape.shape_id);
+            merged.shapes.push(shape);
+        }
+        if i == 0 {
+            merged.feed_info = feed.feed_info;
+        }
+    }
+
+    info!(
+        stops = merged.stops.len(),
+        routes = merged.routes.len(),
+        trips = merged.trips.len(),
+        "Merged static GTFS"
+    );
+    merged
+}
+
+/// Ids used so far, per table.
+#[derive(Default)]
+struct Taken {
+    agencies: HashSet<String>,
+    stops: HashSet<String>,
+    routes: HashSet<String>,
+    trips: HashSet<String>,
+    services: HashSet<String>,
+    shapes: HashSet<String>,
+}
+
+/// Stops close together are the same when they're the same kind and have the same name.
+fn same_stop(a: &Stop, b: &Stop) -> bool {
+    let name = |s: &Stop| s.stop_name.as_deref().map(|n| n.trim().to_lowercase());
+    a.location_type.unwrap_or(0) == b.location_type.unwrap_or(0)
+        && name(a).is_some()
+        && name(a) == name(b)
+}
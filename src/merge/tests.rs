@@ -0,0 +1,223 @@
+//! Merge tests
+//!
+//! Tests merging small feeds built in memory.
+
+use chrono::NaiveDate;
+
+use super::*;
+use crate::db::types::{Agency, Calendar, Route, StopTime, Trip};
+
+fn agency(name: &str) -> Agency {
+    Agency {
+        agency_id: None,
+        agency_name: name.into(),
+        agency_url: "https://translink.com.au/".into(),
+        agency_timezone: "Australia/Brisbane".into(),
+        agency_lang: None,
+        agency_phone: None,
+    }
+}
+
+fn stop(stop_id: &str, name: &str, lat: f64, lon: f64) -> Stop {
+    Stop {
+        stop_id: stop_id.into(),
+        stop_code: None,
+        stop_name: Some(name.into()),
+        stop_desc: None,
+        stop_lat: Some(lat),
+        stop_lon: Some(lon),
+        zone_id: None,
+        stop_url: None,
+        location_type: Some(0),
+        parent_station: None,
+        platform_code: None,
+    }
+}
+
+/// A feed with one route, running one trip between two stops.
+fn feed(agency_name: &str, stops: Vec<Stop>) -> FeedRows {
+    let stop_times = stops
+        .iter()
+        .enumerate()
+        .map(|(i, stop)| {
+            let time = sqlx::postgres::types::PgInterval {
+                months: 0,
+                days: 0,
+                microseconds: (8 * 3600 + i as i64 * 300) * 1_000_000,
+            };
+            StopTime {
+                trip_id: "t1".into(),
+                arrival_time: Some(time),
+                departure_time: time,
+                stop_id: stop.stop_id.clone(),
+                stop_sequence: i as i32 + 1,
+                pickup_type: 0,
+                drop_off_type: 0,
+            }
+        })
+        .collect();
+    FeedRows {
+        agencies: vec![agency(agency_name)],
+        routes: vec![Route {
+            route_id: "r1".into(),
+            agency_id: None,
+            route_short_name: Some("1".into()),
+            route_long_name: None,
+            route_desc: None,
+            route_type: 3,
+            route_url: None,
+            route_color: None,
+            route_text_color: None,
+        }],
+        trips: vec![Trip {
+            route_id: "r1".into(),
+            service_id: "weekdays".into(),
+            trip_id: "t1".into(),
+            trip_headsign: None,
+            direction_id: None,
+            block_id: None,
+            shape_id: None,
+        }],
+        stops,
+        stop_times,
+        calendar: vec![Calendar {
+            service_id: "weekdays".into(),
+            monday: true,
+            tuesday: true,
+            wednesday: true,
+            thursday: true,
+            friday: true,
+            saturday: false,
+            sunday: false,
+            start_date: NaiveDate::from_ymd_opt(2025, 7, 1).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2025, 12, 31).unwrap(),
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_merge_one_feed_unchanged() {
+    let config = MergeConfig {
+        stop_distance_m: 25.0,
+    };
+    let stops = vec![stop("a", "Central", -27.466, 153.026)];
+    let merged = merge(vec![("SEQ".into(), feed("Translink", stops))], &config);
+    assert_eq!(merged.agencies[0].agency_id, None);
+    assert_eq!(merged.routes[0].agency_id, None);
+    assert_eq!(merged.stops[0].stop_id, "a");
+}
+
+#[test]
+fn test_merge() {
+    let config = MergeConfig {
+        stop_distance_m: 25.0,
+    };
+    let seq = feed(
+        "Translink",
+        vec![
+            stop("a", "Central", -27.466, 153.026),
+            stop("b", "Roma Street", -27.465, 153.019),
+        ],
+    );
+    let other = feed(
+        "Other",
+        vec![
+            // 10 m from SEQ's Central, so the same stop.
+            stop("x", "central ", -27.46609, 153.026),
+            // Same id as a SEQ stop, but somewhere else.
+            stop("b", "Bowen Hills", -27.443, 153.039),
+        ],
+    );
+    let merged = merge(vec![("SEQ".into(), seq), ("OTH".into(), other)], &config);
+
+    let agencies: Vec<_> = merged
+        .agencies
+        .iter()
+        .map(|a| a.agency_id.clone())
+        .collect();
+    assert_eq!(agencies, vec![Some("SEQ".into()), Some("OTH".into())]);
+    let routes: Vec<_> = merged
+        .routes
+        .iter()
+        .map(|r| (r.route_id.as_str(), r.agency_id.as_deref()))
+        .collect();
+    assert_eq!(routes, vec![("r1", Some("SEQ")), ("OTH:r1", Some("OTH"))]);
+
+    let stops: Vec<_> = merged.stops.iter().map(|s| s.stop_id.as_str()).collect();
+    assert_eq!(stops, vec!["a", "b", "OTH:b"]);
+    let trips: Vec<_> = merged
+        .trips
+        .iter()
+        .map(|t| {
+            (
+                t.trip_id.as_str(),
+                t.route_id.as_str(),
+                t.service_id.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        trips,
+        vec![
+            ("t1", "r1", "weekdays"),
+            ("OTH:t1", "OTH:r1", "OTH:weekdays")
+        ]
+    );
+    let stop_times: Vec<_> = merged
+        .stop_times
+        .iter()
+        .map(|st| (st.trip_id.as_str(), st.stop_id.as_str()))
+        .collect();
+    assert_eq!(
+        stop_times,
+        vec![
+            ("t1", "a"),
+            ("t1", "b"),
+            ("OTH:t1", "a"),
+            ("OTH:t1", "OTH:b")
+        ]
+    );
+    let services: Vec<_> = merged
+        .calendar
+        .iter()
+        .map(|c| c.service_id.as_str())
+        .collect();
+    assert_eq!(services, vec!["weekdays", "OTH:weekdays"]);
+}
+
+#[test]
+fn test_merge_same_agency() {
+    let config = MergeConfig {
+        stop_distance_m: 25.0,
+    };
+    let seq = feed("Translink", vec![stop("a", "Central", -27.466, 153.026)]);
+    let mut other = feed(
+        "Translink",
+        vec![stop("b", "Bowen Hills", -27.443, 153.039)],
+    );
+    other.agencies.push(Agency {
+        agency_id: Some("QR".into()),
+        ..agency("Queensland Rail")
+    });
+    other.agencies[0].agency_id = Some("TL".into());
+    other.routes[0].agency_id = Some("TL".into());
+    let merged = merge(vec![("SEQ".into(), seq), ("OTH".into(), other)], &config);
+
+    // The db keys agencies on name, so the second Translink is the first one.
+    let agencies: Vec<_> = merged
+        .agencies
+        .iter()
+        .map(|a| (a.agency_id.as_deref(), a.agency_name.as_str()))
+        .collect();
+    assert_eq!(
+        agencies,
+        vec![(Some("SEQ"), "Translink"), (Some("QR"), "Queensland Rail")]
+    );
+    let routes: Vec<_> = merged
+        .routes
+        .iter()
+        .map(|r| (r.route_id.as_str(), r.agency_id.as_deref()))
+        .collect();
+    assert_eq!(routes, vec![("r1", Some("SEQ")), ("OTH:r1", Some("SEQ"))]);
+}
@@ -0,0 +1,207 @@
+//! METRICS
+//!
+//! Prometheus metrics for the static import and realtime polling, served at /metrics.
+//! Everything's registered on its own registry, shared by cloning.
+
+#[cfg(test)]
+mod tests;
+
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use prost::Message;
+
+use crate::{
+    bridge::static_bridge::ImportMetrics,
+    db::Db,
+    gtfs::{RawFeed, RealtimeGtfs},
+    transit_realtime::FeedMessage,
+};
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    static_import_seconds: Histogram,
+    static_import_rows: IntCounterVec,
+    static_import_failures: IntCounter,
+    realtime_fetch_seconds: Histogram,
+    realtime_poll_seconds: Histogram,
+    realtime_poll_failures: IntCounter,
+    realtime_decode_failures: IntCounterVec,
+    realtime_entities: IntGaugeVec,
+    realtime_header_age_seconds: GaugeVec,
+    db_pool_connections: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new().expect("Metrics have valid names and are only registered once")
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics> {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            static_import_seconds: Histogram::with_opts(
+                HistogramOpts::new("static_import_seconds", "Time taken by static imports")
+                    .buckets(vec![10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 2400.0]),
+            )?,
+            static_import_rows: IntCounterVec::new(
+                Opts::new(
+                    "static_import_rows_total",
+                    "Rows through static imports, by table and what happened to them",
+                ),
+                &["table", "outcome"],
+            )?,
+            static_import_failures: IntCounter::new(
+                "static_import_failures_total",
+                "Static imports that failed",
+            )?,
+            realtime_fetch_seconds: Histogram::with_opts(
+                HistogramOpts::new(
+                    "realtime_fetch_seconds",
+                    "Time taken to fetch realtime feeds",
+                )
+                .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+            )?,
+            realtime_poll_seconds: Histogram::with_opts(
+                HistogramOpts::new(
+                    "realtime_poll_seconds",
+                    "Time taken by realtime polls, from fetching to publishing",
+                )
+                .buckets(vec![0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+            )?,
+            realtime_poll_failures: IntCounter::new(
+                "realtime_poll_failures_total",
+                "Realtime polls that failed",
+            )?,
+            realtime_decode_failures: IntCounterVec::new(
+                Opts::new(
+                    "realtime_decode_failures_total",
+                    "Realtime feeds that failed to decode",
+                ),
+                &["feed"],
+            )?,
+            realtime_entities: IntGaugeVec::new(
+                Opts::new("realtime_entities", "Entities in the latest realtime poll"),
+                &["feed"],
+            )?,
+            realtime_header_age_seconds: GaugeVec::new(
+                Opts::new(
+                    "realtime_header_age_seconds",
+                    "Age of the latest realtime poll's header timestamp when it was fetched",
+                ),
+                &["feed"],
+            )?,
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Connections in the db pool"),
+                &["state"],
+            )?,
+            registry,
+        };
+        metrics.register()?;
+        Ok(metrics)
+    }
+
+    fn register(&self) -> Result<()> {
+        let r = &self.registry;
+        r.register(Box::new(self.static_import_seconds.clone()))?;
+        r.register(Box::new(self.static_import_rows.clone()))?;
+        r.register(Box::new(self.static_import_failures.clone()))?;
+        r.register(Box::new(self.realtime_fetch_seconds.clone()))?;
+        r.register(Box::new(self.realtime_poll_seconds.clone()))?;
+        r.register(Box::new(self.realtime_poll_failures.clone()))?;
+        r.register(Box::new(self.realtime_decode_failures.clone()))?;
+        r.register(Box::new(self.realtime_entities.clone()))?;
+        r.register(Box::new(self.realtime_header_age_seconds.clone()))?;
+        r.register(Box::new(self.db_pool_connections.clone()))?;
+        Ok(())
+    }
+
+    pub fn static_import(&self, import: &ImportMetrics) {
+        self.static_import_seconds
+            .observe(import.elapsed.as_secs_f64());
+        for table in &import.tables {
+            for (outcome, rows) in [
+                ("loaded", table.rows),
+                ("changed", table.changed),
+                ("deleted", table.deleted),
+            ] {
+                self.static_import_rows
+                    .with_label_values(&[table.table, outcome])
+                    .inc_by(rows);
+            }
+        }
+    }
+
+    pub fn static_import_failed(&self) {
+        self.static_import_failures.inc();
+    }
+
+    pub fn realtime_fetched(&self, elapsed: Duration) {
+        self.realtime_fetch_seconds.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn realtime_polled(&self, elapsed: Duration, ok: bool) {
+        self.realtime_poll_seconds.observe(elapsed.as_secs_f64());
+        if !ok {
+            self.realtime_poll_failures.inc();
+        }
+    }
+
+    /// Counts the feeds that don't decode, after decoding them all has failed.
+    pub fn realtime_decode_failed(&self, feeds: &[RawFeed]) {
+        for feed in feeds {
+            if FeedMessage::decode(feed.payload.as_slice()).is_err() {
+                self.realtime_decode_failures
+                    .with_label_values(&[feed_label(&feed.url)])
+                    .inc();
+            }
+        }
+    }
+
+    /// Entity counts and header ages of a decoded poll, which has a message per feed.
+    pub fn realtime_decoded(&self, feeds: &[RawFeed], realtime: &RealtimeGtfs) {
+        for (feed, message) in feeds.iter().zip(&realtime.0) {
+            let label = feed_label(&feed.url);
+            self.realtime_entities
+                .with_label_values(&[label])
+                .set(message.entity.len() as i64);
+            if let Some(timestamp) = message.header.timestamp
+                && let Some(timestamp) = DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+            {
+                let age = feed.fetched_at - timestamp;
+                self.realtime_header_age_seconds
+                    .with_label_values(&[label])
+                    .set(age.as_seconds_f64());
+            }
+        }
+    }
+
+    /// Everything in the Prometheus text format, with the pool read as of now.
+    pub fn render(&self, db: &Db) -> Result<String> {
+        let size = db.0.size() as i64;
+        let idle = db.0.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// The last part of a realtime url, e.g. TripUpdates.
+fn feed_label(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
@@ -0,0 +1,93 @@
+//! Metrics tests
+//!
+//! Tests what gets recorded, without a db.
+
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use prometheus::Encoder;
+
+use super::*;
+use crate::{
+    bridge::static_bridge::TableMetrics,
+    transit_realtime::{FeedEntity, FeedHeader},
+};
+
+fn text(metrics: &Metrics) -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[test]
+fn test_static_import_metrics() {
+    let metrics = Metrics::default();
+    metrics.static_import(&ImportMetrics {
+        tables: vec![TableMetrics {
+            table: "stops",
+            rows: 10,
+            skipped: false,
+            changed: 3,
+            deleted: 1,
+            elapsed: Duration::from_secs(2),
+        }],
+        elapsed: Duration::from_secs(2),
+        ..Default::default()
+    });
+    metrics.static_import_failed();
+
+    let text = text(&metrics);
+    assert!(text.contains(r#"static_import_rows_total{outcome="loaded",table="stops"} 10"#));
+    assert!(text.contains(r#"static_import_rows_total{outcome="changed",table="stops"} 3"#));
+    assert!(text.contains(r#"static_import_rows_total{outcome="deleted",table="stops"} 1"#));
+    assert!(text.contains("static_import_seconds_count 1"));
+    assert!(text.contains("static_import_failures_total 1"));
+}
+
+#[test]
+fn test_realtime_metrics() {
+    let metrics = Metrics::default();
+    let fetched_at = Utc::now();
+    let message = FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: "2.0".into(),
+            timestamp: Some((fetched_at - TimeDelta::seconds(30)).timestamp() as u64),
+            ..Default::default()
+        },
+        entity: vec![
+            FeedEntity {
+                id: "1".into(),
+                ..Default::default()
+            },
+            FeedEntity {
+                id: "2".into(),
+                ..Default::default()
+            },
+        ],
+    };
+    let feeds = vec![
+        RawFeed {
+            url: "https://gtfsrt.api.translink.com.au/api/realtime/SEQ/TripUpdates".into(),
+            fetched_at,
+            payload: message.encode_to_vec(),
+        },
+        RawFeed {
+            url: "https://gtfsrt.api.translink.com.au/api/realtime/SEQ/alerts".into(),
+            fetched_at,
+            payload: b"not protobuf".to_vec(),
+        },
+    ];
+
+    metrics.realtime_decoded(&feeds[..1], &RealtimeGtfs(vec![message]));
+    metrics.realtime_decode_failed(&feeds);
+    metrics.realtime_polled(Duration::from_secs(1), false);
+
+    let text = text(&metrics);
+    assert!(text.contains(r#"realtime_entities{feed="TripUpdates"} 2"#));
+    assert!(text.contains(r#"realtime_header_age_seconds{feed="TripUpdates"} 30"#));
+    assert!(text.contains(r#"realtime_decode_failures_total{feed="alerts"} 1"#));
+    assert!(!text.contains(r#"realtime_decode_failures_total{feed="TripUpdates"}"#));
+    assert!(text.contains("realtime_poll_failures_total 1"));
+}
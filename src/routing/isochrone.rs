@@ -0,0 +1,334 @@
+//! Isochrones: everywhere reachable from a place within some time.
+//!
+//! RAPTOR gives the earliest arrival at every stop, then walking on from
+//! each stop is rasterised onto a grid. Each time band's cells are traced
+//! into polygons, so the edges follow the grid rather than being smooth.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    geo::{METRES_PER_DEGREE, distance_m},
+    routing::{
+        Endpoint, MAX_ACCESS_WALK_M, endpoint_stops,
+        raptor::earliest_arrivals,
+        timetable::{DETOUR_FACTOR, Time, Timetable, WALK_SPEED_MPS, walk_time},
+    },
+};
+
+/// Size of the grid cells polygons are traced from.
+pub const CELL_M: f64 = 100.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReachableStop {
+    pub stop_id: String,
+    pub name: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+    /// Seconds from leaving to arriving at the stop.
+    pub travel_time: Time,
+}
+
+/// Polygons as GeoJSON MultiPolygon coordinates: polygons, of rings, of [lon, lat].
+pub type MultiPolygon = Vec<Vec<Vec<[f64; 2]>>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Band {
+    /// Seconds.
+    pub travel_time: Time,
+    pub area: MultiPolygon,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Isochrone {
+    pub stops: Vec<ReachableStop>,
+    pub bands: Vec<Band>,
+}
+
+/// Everywhere reachable from an endpoint, leaving at departure, within each band (in seconds).
+/// Walks to and from stops are capped at MAX_ACCESS_WALK_M, same as planning.
+pub fn isochrone(
+    timetable: &Timetable,
+    from: &Endpoint,
+    departure: Time,
+    bands: &[Time],
+) -> Isochrone {
+    let network = &timetable.network;
+    let Some(&limit) = bands.iter().max() else {
+        return Isochrone {
+            stops: vec![],
+            bands: vec![],
+        };
+    };
+
+    let access = endpoint_stops(network, from);
+    let arrivals = earliest_arrivals(timetable, &access, departure, departure + limit);
+    let mut stops: Vec<ReachableStop> = arrivals
+        .iter()
+        .enumerate()
+        .filter_map(|(i, arrival)| {
+            let stop = &network.stops[i];
+            Some(ReachableStop {
+                stop_id: stop.stop_id.clone(),
+                name: stop.name.clone(),
+                lat: stop.lat?,
+                lon: stop.lon?,
+                travel_time: (*arrival)? - departure,
+            })
+        })
+        .collect();
+    stops.sort_by_key(|s| s.travel_time);
+
+    // Walking straight from a coordinate reaches places no stop does.
+    let mut sources: Vec<(f64, f64, Time)> = stops
+        .iter()
+        .map(|s| (s.lat, s.lon, s.travel_time))
+        .collect();
+    if let Endpoint::Coord { lat, lon } = from {
+        sources.push((*lat, *lon, 0));
+    }
+
+    let grid = TravelGrid::new(&sources, limit);
+    let mut bands: Vec<Band> = bands
+        .iter()
+        .map(|&travel_time| Band {
+            travel_time,
+            area: grid.polygons(travel_time),
+        })
+        .collect();
+    bands.sort_by_key(|b| b.travel_time);
+
+    Isochrone { stops, bands }
+}
+
+/// How far you can walk in some time, straight line.
+fn walk_distance(time: Time) -> f64 {
+    time as f64 * WALK_SPEED_MPS / DETOUR_FACTOR
+}
+
+/// Travel time to the middle of each cell, over a lat/lon aligned grid.
+struct TravelGrid {
+    lat0: f64,
+    lon0: f64,
+    cell_lat: f64,
+    cell_lon: f64,
+    rows: usize,
+    cols: usize,
+    times: Vec<Time>,
+}
+
+impl TravelGrid {
+    fn new(sources: &[(f64, f64, Time)], limit: Time) -> TravelGrid {
+        let max_walk = MAX_ACCESS_WALK_M.min(walk_distance(limit));
+        let (mut min_lat, mut max_lat) = (f64::MAX, f64::MIN);
+        let (mut min_lon, mut max_lon) = (f64::MAX, f64::MIN);
+        for &(lat, lon, _) in sources {
+            (min_lat, max_lat) = (min_lat.min(lat), max_lat.max(lat));
+            (min_lon, max_lon) = (min_lon.min(lon), max_lon.max(lon));
+        }
+        if sources.is_empty() {
+            (min_lat, max_lat, min_lon, max_lon) = (0.0, 0.0, 0.0, 0.0);
+        }
+
+        let cell_lat = CELL_M / METRES_PER_DEGREE;
+        let cell_lon = cell_lat / ((min_lat + max_lat) / 2.0).to_radians().cos().max(0.01);
+        // One cell of margin, so every traced ring is closed by outside cells.
+        let margin_lat = max_walk / METRES_PER_DEGREE + cell_lat;
+        let margin_lon = margin_lat * cell_lon / cell_lat;
+        let lat0 = min_lat - margin_lat;
+        let lon0 = min_lon - margin_lon;
+        let rows = ((max_lat + margin_lat - lat0) / cell_lat).ceil() as usize + 1;
+        let cols = ((max_lon + margin_lon - lon0) / cell_lon).ceil() as usize + 1;
+
+        let mut grid = TravelGrid {
+            lat0,
+            lon0,
+            cell_lat,
+            cell_lon,
+            rows,
+            cols,
+            times: vec![Time::MAX; rows * cols],
+        };
+        for &(lat, lon, time) in sources {
+            grid.walk_from(
+                lat,
+                lon,
+                time,
+                MAX_ACCESS_WALK_M.min(walk_distance(limit - time)),
+            );
+        }
+        grid
+    }
+
+    /// Lowers the time of every cell within radius_m of a point reached at time.
+    fn walk_from(&mut self, lat: f64, lon: f64, time: Time, radius_m: f64) {
+        let row = ((lat - self.lat0) / self.cell_lat) as isize;
+        let col = ((lon - self.lon0) / self.cell_lon) as isize;
+        let d_rows = (radius_m / CELL_M).ceil() as isize + 1;
+        let d_cols = (radius_m / METRES_PER_DEGREE / self.cell_lon).ceil() as isize + 1;
+        for r in (row - d_rows).max(0)..=(row + d_rows).min(self.rows as isize - 1) {
+            for c in (col - d_cols).max(0)..=(col + d_cols).min(self.cols as isize - 1) {
+                let (r, c) = (r as usize, c as usize);
+                let (cell_lat, cell_lon) = self.center(r, c);
+                let distance = distance_m(lat, lon, cell_lat, cell_lon);
+                if distance <= radius_m {
+                    let cell = &mut self.times[r * self.cols + c];
+                    *cell = (*cell).min(time + walk_time(distance));
+                }
+            }
+        }
+    }
+
+    fn center(&self, row: usize, col: usize) -> (f64, f64) {
+        (
+            self.lat0 + (row as f64 + 0.5) * self.cell_lat,
+            self.lon0 + (col as f64 + 0.5) * self.cell_lon,
+        )
+    }
+
+    fn polygons(&self, travel_time: Time) -> MultiPolygon {
+        let inside = |r: isize, c: isize| {
+            r >= 0
+                && c >= 0
+                && (r as usize) < self.rows
+                && (c as usize) < self.cols
+                && self.times[r as usize * self.cols + c as usize] <= travel_time
+        };
+        trace(self.rows, self.cols, inside)
+            .into_iter()
+            .map(|polygon| {
+                polygon
+                    .into_iter()
+                    .map(|ring| {
+                        ring.into_iter()
+                            .map(|(x, y)| {
+                                [
+                                    self.lon0 + x as f64 * self.cell_lon,
+                                    self.lat0 + y as f64 * self.cell_lat,
+                                ]
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// A grid corner, x along columns and y along rows.
+type Vertex = (isize, isize);
+
+/// Traces the outlines of the inside cells into polygons of closed rings,
+/// outer rings anticlockwise and holes clockwise.
+pub fn trace(
+    rows: usize,
+    cols: usize,
+    inside: impl Fn(isize, isize) -> bool,
+) -> Vec<Vec<Vec<Vertex>>> {
+    // Every edge between an inside and outside cell, going anticlockwise around the inside.
+    let mut edges: HashMap<Vertex, Vec<Vertex>> = HashMap::new();
+    for r in 0..rows as isize {
+        for c in 0..cols as isize {
+            if !inside(r, c) {
+                continue;
+            }
+            let sides = [
+                (inside(r - 1, c), (c, r), (c + 1, r)),
+                (inside(r, c + 1), (c + 1, r), (c + 1, r + 1)),
+                (inside(r + 1, c), (c + 1, r + 1), (c, r + 1)),
+                (inside(r, c - 1), (c, r + 1), (c, r)),
+            ];
+            for (neighbour, from, to) in sides {
+                if !neighbour {
+                    edges.entry(from).or_default().push(to);
+                }
+            }
+        }
+    }
+
+    let mut rings = vec![];
+    // Starting from the lowest corner left, so rings come out the same every time.
+    while let Some(&start) = edges.keys().min() {
+        let mut ring = vec![start];
+        let mut at = start;
+        let mut direction: Option<Vertex> = None;
+        while let Some(outgoing) = edges.get_mut(&at) {
+            // Where two cells only touch at a corner, turn left to keep them apart.
+            let i = match direction {
+                Some((dx, dy)) if outgoing.len() > 1 => outgoing
+                    .iter()
+                    .position(|&(x, y)| (x - at.0, y - at.1) == (-dy, dx))
+                    .unwrap_or(0),
+                _ => 0,
+            };
+            let next = outgoing.swap_remove(i);
+            if outgoing.is_empty() {
+                edges.remove(&at);
+            }
+            direction = Some((next.0 - at.0, next.1 - at.1));
+            at = next;
+            ring.push(at);
+            if at == start {
+                break;
+            }
+        }
+        rings.push(simplify_ring(ring));
+    }
+
+    let (outers, holes): (Vec<_>, Vec<_>) = rings.into_iter().partition(|r| area(r) > 0);
+    let mut polygons: Vec<Vec<Vec<Vertex>>> = outers.into_iter().map(|r| vec![r]).collect();
+    for hole in holes {
+        // The middle of the inside cell next to the hole's first edge.
+        let ((x1, y1), (x2, y2)) = (hole[0], hole[1]);
+        let (dx, dy) = ((x2 - x1).signum(), (y2 - y1).signum());
+        let point = (
+            x1 as f64 + 0.5 * dx as f64 - 0.5 * dy as f64,
+            y1 as f64 + 0.5 * dy as f64 + 0.5 * dx as f64,
+        );
+        // Outer rings can sit inside holes of others, so the smallest one around it owns it.
+        let owner = polygons
+            .iter_mut()
+            .filter(|p| contains(&p[0], point))
+            .min_by_key(|p| area(&p[0]));
+        if let Some(owner) = owner {
+            owner.push(hole);
+        }
+    }
+    polygons
+}
+
+/// Drops the vertices in the middle of straight runs.
+fn simplify_ring(ring: Vec<Vertex>) -> Vec<Vertex> {
+    let n = ring.len() - 1;
+    let mut simplified: Vec<Vertex> = (0..n)
+        .filter(|&i| {
+            let (prev, here, next) = (ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]);
+            (here.0 - prev.0) * (next.1 - here.1) != (here.1 - prev.1) * (next.0 - here.0)
+        })
+        .map(|i| ring[i])
+        .collect();
+    simplified.push(simplified[0]);
+    simplified
+}
+
+/// Twice the signed area, positive for anticlockwise rings.
+fn area(ring: &[Vertex]) -> isize {
+    ring.windows(2)
+        .map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1)
+        .sum()
+}
+
+fn contains(ring: &[Vertex], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for w in ring.windows(2) {
+        let ((x1, y1), (x2, y2)) = (
+            (w[0].0 as f64, w[0].1 as f64),
+            (w[1].0 as f64, w[1].1 as f64),
+        );
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
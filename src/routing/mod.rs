@@ -0,0 +1,542 @@
+//! ROUTING
+//!
+//! Point to point journey planning over the stored timetable, using RAPTOR.
+//! The timetable for a day is loaded from the db on first use and kept in
+//! memory, so queries never touch the db. Each realtime poll is applied on
+//! top of it, so delays and cancellations are planned around.
+
+pub mod isochrone;
+pub mod raptor;
+pub mod realtime;
+#[cfg(test)]
+mod tests;
+pub mod timetable;
+pub mod transfers;
+
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::{Result, bail};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::{
+    db::Db,
+    export::css_color,
+    geo::distance_m,
+    gtfs::RealtimeGtfs,
+    routing::{
+        isochrone::{Isochrone, isochrone},
+        raptor::{Journey, JourneyLeg, Query, raptor},
+        realtime::apply_trip_updates,
+        timetable::{Network, StopIdx, Time, Timetable, TripTimes},
+    },
+};
+
+/// Furthest we'll have someone walk to their first stop or from their last.
+pub const MAX_ACCESS_WALK_M: f64 = 1000.0;
+
+/// How many days of timetables to keep loaded.
+const CACHED_DAYS: usize = 3;
+
+/// Range queries give up after this many searches, however wide the range.
+const MAX_RANGE_SEARCHES: usize = 64;
+
+/// Where a journey starts or ends.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    /// A stop, or every platform of a station.
+    Stop(String),
+    Coord {
+        lat: f64,
+        lon: f64,
+    },
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    /// "lat,lon" or a stop id.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((lat, lon)) = s.split_once(',')
+            && let (Ok(lat), Ok(lon)) = (lat.trim().parse::<f64>(), lon.trim().parse::<f64>())
+        {
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                bail!("Coordinates out of range");
+            }
+            return Ok(Endpoint::Coord { lat, lon });
+        }
+        if s.is_empty() {
+            bail!("Empty endpoint");
+        }
+        Ok(Endpoint::Stop(s.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Place {
+    pub stop_id: Option<String>,
+    pub name: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Leg {
+    Walk {
+        from: Place,
+        to: Place,
+        departure: NaiveDateTime,
+        arrival: NaiveDateTime,
+    },
+    Transit {
+        from: Place,
+        to: Place,
+        departure: NaiveDateTime,
+        arrival: NaiveDateTime,
+        trip_id: String,
+        route_id: String,
+        route_short_name: Option<String>,
+        route_long_name: Option<String>,
+        route_color: Option<String>,
+        headsign: Option<String>,
+        /// Whether the times are realtime predictions rather than the schedule.
+        realtime: bool,
+        /// Every stop the leg passes, including where it boards and alights.
+        stops: Vec<Place>,
+        /// (lon, lat) points along the trip's shape, or between the stops if it has none.
+        shape: Vec<(f64, f64)>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Itinerary {
+    pub departure: NaiveDateTime,
+    pub arrival: NaiveDateTime,
+    pub transfers: usize,
+    pub legs: Vec<Leg>,
+}
+
+/// Lazily loaded, shared routing data.
+/// Invalidate it whenever the static feed changes, and update it with each realtime poll.
+#[derive(Clone, Default)]
+pub struct Planner(Arc<RwLock<PlannerCache>>);
+
+#[derive(Default)]
+struct PlannerCache {
+    network: Option<Arc<Network>>,
+    /// Most recently used last.
+    days: Vec<CachedDay>,
+    /// The latest poll, applied to days as they load.
+    realtime: Option<Arc<RealtimeGtfs>>,
+}
+
+/// A day's timetable as scheduled, and with the latest realtime applied.
+struct CachedDay {
+    scheduled: Arc<Timetable>,
+    current: Arc<Timetable>,
+}
+
+impl Planner {
+    /// The timetable for a date, with the latest realtime applied.
+    pub async fn timetable(&self, db: &Db, date: NaiveDate) -> Result<Arc<Timetable>> {
+        if let Some(day) = self
+            .0
+            .read()
+            .await
+            .days
+            .iter()
+            .find(|d| d.scheduled.date == date)
+        {
+            return Ok(day.current.clone());
+        }
+
+        let mut cache = self.0.write().await;
+        // Someone else may have loaded it while we waited on the lock.
+        if let Some(i) = cache.days.iter().position(|d| d.scheduled.date == date) {
+            let day = cache.days.remove(i);
+            let current = day.current.clone();
+            cache.days.push(day);
+            return Ok(current);
+        }
+        let network = match &cache.network {
+            Some(network) => network.clone(),
+            None => {
+                let network = Arc::new(Network::load(db).await?);
+                cache.network = Some(network.clone());
+                network
+            }
+        };
+        let scheduled = Arc::new(Timetable::load(db, network, date).await?);
+        let current = match cache.realtime.clone() {
+            Some(realtime) => {
+                let scheduled = scheduled.clone();
+                let today = scheduled.network.today();
+                Arc::new(
+                    tokio::task::spawn_blocking(move || {
+                        apply_trip_updates(&scheduled, &realtime, today)
+                    })
+                    .await?,
+                )
+            }
+            None => scheduled.clone(),
+        };
+        if cache.days.len() >= CACHED_DAYS {
+            cache.days.remove(0);
+        }
+        cache.days.push(CachedDay {
+            scheduled,
+            current: current.clone(),
+        });
+        Ok(current)
+    }
+
+    /// Drops the loaded timetables, keeping the latest realtime to apply once they reload.
+    pub async fn invalidate(&self) {
+        let mut cache = self.0.write().await;
+        cache.network = None;
+        cache.days.clear();
+    }
+
+    /// Reapplies realtime to every loaded day, starting from the schedule each time.
+    #[instrument(skip_all)]
+    pub async fn update_realtime(&self, realtime: Arc<RealtimeGtfs>) -> Result<()> {
+        let scheduled: Vec<Arc<Timetable>> = self
+            .0
+            .read()
+            .await
+            .days
+            .iter()
+            .map(|d| d.scheduled.clone())
+            .collect();
+        let updated = tokio::task::spawn_blocking({
+            let realtime = realtime.clone();
+            move || {
+                scheduled
+                    .into_iter()
+                    .map(|s| {
+                        let today = s.network.today();
+                        let current = Arc::new(apply_trip_updates(&s, &realtime, today));
+                        (s, current)
+                    })
+                    .collect::<Vec<_>>()
+            }
+        })
+        .await?;
+
+        let mut cache = self.0.write().await;
+        // Days may have been dropped or reloaded in the meantime, those are left alone.
+        for (scheduled, current) in updated {
+            if let Some(day) = cache
+                .days
+                .iter_mut()
+                .find(|d| Arc::ptr_eq(&d.scheduled, &scheduled))
+            {
+                day.current = current;
+            }
+        }
+        cache.realtime = Some(realtime);
+        Ok(())
+    }
+
+    /// Plans journeys leaving from after date and time.
+    /// With range_mins, gives every worthwhile journey leaving in that window, not just the first.
+    #[instrument(skip(self, db))]
+    pub async fn plan(
+        &self,
+        db: &Db,
+        from: Endpoint,
+        to: Endpoint,
+        date: NaiveDate,
+        time: NaiveTime,
+        range_mins: Option<u32>,
+    ) -> Result<Vec<Itinerary>> {
+        let timetable = self.timetable(db, date).await?;
+        let departure = time.num_seconds_from_midnight() as Time;
+        let range = range_mins.unwrap_or(0) as Time * 60;
+        Ok(
+            tokio::task::spawn_blocking(move || plan(&timetable, &from, &to, departure, range))
+                .await?,
+        )
+    }
+
+    /// Everywhere reachable from an endpoint leaving at date and time, within each band of minutes.
+    #[instrument(skip(self, db))]
+    pub async fn isochrone(
+        &self,
+        db: &Db,
+        from: Endpoint,
+        date: NaiveDate,
+        time: NaiveTime,
+        band_mins: Vec<u32>,
+    ) -> Result<Isochrone> {
+        let timetable = self.timetable(db, date).await?;
+        let departure = time.num_seconds_from_midnight() as Time;
+        let bands: Vec<Time> = band_mins.into_iter().map(|m| m as Time * 60).collect();
+        Ok(
+            tokio::task::spawn_blocking(move || isochrone(&timetable, &from, departure, &bands))
+                .await?,
+        )
+    }
+}
+
+/// Runs the queries for a plan, and turns the journeys into itineraries.
+pub fn plan(
+    timetable: &Timetable,
+    from: &Endpoint,
+    to: &Endpoint,
+    departure: Time,
+    range: Time,
+) -> Vec<Itinerary> {
+    let access = endpoint_stops(&timetable.network, from);
+    let egress = endpoint_stops(&timetable.network, to);
+    if access.is_empty() || egress.is_empty() {
+        return vec![];
+    }
+
+    journeys(timetable, &access, &egress, departure, range)
+        .into_iter()
+        .map(|(departure, journey)| itinerary(timetable, from, to, departure, &journey))
+        .collect()
+}
+
+/// The stops an endpoint can be reached from, with how long walking takes.
+fn endpoint_stops(network: &Network, endpoint: &Endpoint) -> Vec<(StopIdx, Time)> {
+    match endpoint {
+        Endpoint::Stop(stop_id) => network
+            .resolve_stop(stop_id)
+            .into_iter()
+            .map(|stop| (stop, 0))
+            .collect(),
+        Endpoint::Coord { lat, lon } => network.stops_near(*lat, *lon, MAX_ACCESS_WALK_M),
+    }
+}
+
+/// Earliest arrival searches, repeated just after each found departure until past the range.
+/// Gives the journeys (with when they leave) no other journey beats on
+/// leaving later, arriving sooner and transferring less.
+fn journeys(
+    timetable: &Timetable,
+    access: &[(StopIdx, Time)],
+    egress: &[(StopIdx, Time)],
+    departure: Time,
+    range: Time,
+) -> Vec<(Time, Journey)> {
+    let mut found: Vec<(Time, Journey)> = vec![];
+    let mut time = departure;
+    for _ in 0..MAX_RANGE_SEARCHES {
+        let journeys = raptor(
+            timetable,
+            &Query {
+                access,
+                egress,
+                departure: time,
+            },
+        );
+        let mut next = None;
+        for journey in journeys {
+            let leaves = journey.departure(timetable);
+            // The first search always counts. Later ones only for what leaves in the range,
+            // and walking the whole way would just be found again.
+            if time != departure && leaves.is_none_or(|leaves| leaves > departure + range) {
+                continue;
+            }
+            next = next.max(leaves);
+            found.push((leaves.unwrap_or(time), journey));
+        }
+        match next {
+            Some(leaves) if range > 0 && leaves < departure + range => time = leaves + 1,
+            _ => break,
+        }
+    }
+
+    let dominates = |(a_leaves, a): &(Time, Journey), (b_leaves, b): &(Time, Journey)| {
+        a_leaves >= b_leaves && a.arrival <= b.arrival && a.trips() <= b.trips()
+    };
+    let mut kept: Vec<(Time, Journey)> = vec![];
+    for (i, journey) in found.iter().enumerate() {
+        let beaten = found.iter().enumerate().any(|(j, other)| {
+            j != i && dominates(other, journey) && (!dominates(journey, other) || j > i)
+        });
+        if !beaten {
+            kept.push(journey.clone());
+        }
+    }
+    kept.sort_by_key(|(leaves, journey)| (*leaves, journey.arrival));
+    kept
+}
+
+fn stop_place(network: &Network, stop: StopIdx) -> Place {
+    let stop = &network.stops[stop];
+    Place {
+        stop_id: Some(stop.stop_id.clone()),
+        name: stop.name.clone(),
+        lat: stop.lat,
+        lon: stop.lon,
+    }
+}
+
+fn endpoint_place(network: &Network, endpoint: &Endpoint, stop: StopIdx) -> Place {
+    match endpoint {
+        Endpoint::Stop(_) => stop_place(network, stop),
+        Endpoint::Coord { lat, lon } => Place {
+            stop_id: None,
+            name: None,
+            lat: Some(*lat),
+            lon: Some(*lon),
+        },
+    }
+}
+
+/// Turns a journey into legs with real times, places and shapes.
+/// Walks to the first stop are timed to just make the first trip.
+fn itinerary(
+    timetable: &Timetable,
+    from: &Endpoint,
+    to: &Endpoint,
+    leaves: Time,
+    journey: &Journey,
+) -> Itinerary {
+    let network = &timetable.network;
+    let midnight = timetable.date.and_time(NaiveTime::MIN);
+    let at = |time: Time| midnight + chrono::Duration::seconds(time as i64);
+
+    let mut legs = vec![];
+    let mut time = leaves;
+    for leg in &journey.legs {
+        match *leg {
+            JourneyLeg::Access { to: stop, walk } => {
+                if walk > 0 {
+                    legs.push(Leg::Walk {
+                        from: endpoint_place(network, from, stop),
+                        to: stop_place(network, stop),
+                        departure: at(time),
+                        arrival: at(time + walk),
+                    });
+                }
+                time += walk;
+            }
+            JourneyLeg::Transfer {
+                from: from_stop,
+                to: to_stop,
+                walk,
+            } => {
+                legs.push(Leg::Walk {
+                    from: stop_place(network, from_stop),
+                    to: stop_place(network, to_stop),
+                    departure: at(time),
+                    arrival: at(time + walk),
+                });
+                time += walk;
+            }
+            JourneyLeg::Transit {
+                trip,
+                board_pos,
+                alight_pos,
+            } => {
+                let trip = &timetable.trips[trip];
+                let route = network.routes.get(&trip.route_id);
+                let stops: Vec<Place> = trip.stops[board_pos..=alight_pos]
+                    .iter()
+                    .map(|&stop| stop_place(network, stop))
+                    .collect();
+                legs.push(Leg::Transit {
+                    from: stop_place(network, trip.stops[board_pos]),
+                    to: stop_place(network, trip.stops[alight_pos]),
+                    departure: at(trip.departures[board_pos]),
+                    arrival: at(trip.arrivals[alight_pos]),
+                    trip_id: trip.trip_id.clone(),
+                    route_id: trip.route_id.clone(),
+                    route_short_name: route.and_then(|r| r.route_short_name.clone()),
+                    route_long_name: route.and_then(|r| r.route_long_name.clone()),
+                    route_color: route.and_then(|r| css_color(&r.route_color)),
+                    headsign: trip.headsign.clone(),
+                    realtime: trip.realtime,
+                    shape: leg_shape(network, trip, board_pos, alight_pos),
+                    stops,
+                });
+                time = trip.arrivals[alight_pos];
+            }
+            JourneyLeg::Egress { from: stop, walk } => {
+                if walk > 0 {
+                    legs.push(Leg::Walk {
+                        from: stop_place(network, stop),
+                        to: endpoint_place(network, to, stop),
+                        departure: at(time),
+                        arrival: at(time + walk),
+                    });
+                }
+                time += walk;
+            }
+        }
+    }
+
+    Itinerary {
+        departure: at(leaves),
+        arrival: at(time),
+        transfers: journey.trips().saturating_sub(1),
+        legs,
+    }
+}
+
+/// The part of a trip's shape between two of its stops.
+/// Falls back to straight lines between the stops without a shape.
+fn leg_shape(
+    network: &Network,
+    trip: &TripTimes,
+    board_pos: usize,
+    alight_pos: usize,
+) -> Vec<(f64, f64)> {
+    let coords = |stop: StopIdx| {
+        let stop = &network.stops[stop];
+        Some((stop.lon?, stop.lat?))
+    };
+    let straight = || {
+        trip.stops[board_pos..=alight_pos]
+            .iter()
+            .filter_map(|&stop| coords(stop))
+            .collect()
+    };
+
+    let Some(shape) = trip.shape_id.as_ref().and_then(|s| network.shapes.get(s)) else {
+        return straight();
+    };
+    let (Some(start), Some(end)) = (
+        coords(trip.stops[board_pos]),
+        coords(trip.stops[alight_pos]),
+    ) else {
+        return straight();
+    };
+
+    // Nearest vertex to a point, looking no earlier than after.
+    let nearest = |(lon, lat): (f64, f64), after: usize| {
+        (after..shape.len()).min_by(|&a, &b| {
+            let (a_lon, a_lat) = shape[a];
+            let (b_lon, b_lat) = shape[b];
+            distance_m(lat, lon, a_lat, a_lon).total_cmp(&distance_m(lat, lon, b_lat, b_lon))
+        })
+    };
+    match nearest(start, 0).and_then(|first| Some((first, nearest(end, first)?))) {
+        Some((first, last)) if last > first => shape[first..=last].to_vec(),
+        _ => straight(),
+    }
+}
+
+/// When a query leaves, from an optional date and HH:MM[:SS] time.
+/// Without a time it's now, or midnight if the query is for some other day.
+pub fn parse_departure(
+    date: Option<NaiveDate>,
+    time: Option<&str>,
+    now: NaiveDateTime,
+) -> Result<(NaiveDate, NaiveTime)> {
+    let date = date.unwrap_or(now.date());
+    let time = match time {
+        Some(time) => NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))?,
+        None if date == now.date() => now.time(),
+        None => NaiveTime::MIN,
+    };
+    Ok((date, time))
+}
@@ -0,0 +1,361 @@
+//! Round-based public transit routing (RAPTOR).
+//!
+//! Round k finds the earliest arrival at every stop using at most k trips.
+//! Every round that improves on the destination gives another journey,
+//! so the result is the set of journeys trading transfers for arrival time.
+
+use super::timetable::{PatternIdx, StopIdx, Time, Timetable, TripIdx};
+
+/// Most trips a single journey may use.
+pub const MAX_ROUNDS: usize = 8;
+
+const UNREACHED: Time = Time::MAX;
+
+/// How a stop was reached in a round.
+#[derive(Debug, Clone, Copy)]
+enum Label {
+    /// Walked straight from the origin.
+    Access { walk: Time },
+    /// Rode a trip, boarded at board_pos of the pattern.
+    Transit {
+        trip: TripIdx,
+        pattern: PatternIdx,
+        board_pos: usize,
+        alight_pos: usize,
+    },
+    /// Walked from another stop reached this round.
+    Footpath { from: StopIdx, walk: Time },
+}
+
+/// Where a journey starts or ends: stops, and how long walking to/from them takes.
+pub struct Query<'a> {
+    pub access: &'a [(StopIdx, Time)],
+    pub egress: &'a [(StopIdx, Time)],
+    pub departure: Time,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JourneyLeg {
+    /// From the origin to a stop.
+    Access { to: StopIdx, walk: Time },
+    Transit {
+        trip: TripIdx,
+        board_pos: usize,
+        alight_pos: usize,
+    },
+    /// Between two stops.
+    Transfer {
+        from: StopIdx,
+        to: StopIdx,
+        walk: Time,
+    },
+    /// From a stop to the destination.
+    Egress { from: StopIdx, walk: Time },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journey {
+    pub arrival: Time,
+    pub legs: Vec<JourneyLeg>,
+}
+
+impl Journey {
+    pub fn trips(&self) -> usize {
+        self.legs
+            .iter()
+            .filter(|l| matches!(l, JourneyLeg::Transit { .. }))
+            .count()
+    }
+
+    /// When the first trip leaves, less the walk to it. None for journeys that only walk.
+    pub fn departure(&self, timetable: &Timetable) -> Option<Time> {
+        let mut walk = 0;
+        for leg in &self.legs {
+            match *leg {
+                JourneyLeg::Access { walk: w, .. } | JourneyLeg::Transfer { walk: w, .. } => {
+                    walk += w
+                }
+                JourneyLeg::Transit {
+                    trip, board_pos, ..
+                } => return Some(timetable.trips[trip].departures[board_pos] - walk),
+                JourneyLeg::Egress { .. } => break,
+            }
+        }
+        None
+    }
+}
+
+struct Round {
+    /// Earliest arrival at each stop using at most this many trips.
+    arrival: Vec<Time>,
+    /// Set for stops improved this round.
+    label: Vec<Option<Label>>,
+    /// How stops were reached this round before walking on, so footpaths can point back at them.
+    ride: Vec<Option<(Time, Label)>>,
+}
+
+/// Earliest arrival journeys from the query's access stops to its egress stops.
+/// Gives at most one journey per number of trips, each arriving earlier than the last.
+pub fn raptor(timetable: &Timetable, query: &Query) -> Vec<Journey> {
+    let mut best = vec![UNREACHED; timetable.network.stops.len()];
+    let mut best_target = UNREACHED;
+    let mut rounds: Vec<Round> = vec![];
+    let mut journeys = vec![];
+
+    let (round, mut marked) = access_round(timetable, query.access, query.departure, &mut best);
+    rounds.push(round);
+
+    for k in 0..=MAX_ROUNDS {
+        if k > 0 {
+            if marked.is_empty() {
+                break;
+            }
+            let (mut round, reached) =
+                transit_round(timetable, &rounds[k - 1], &marked, &mut best, best_target);
+            marked = relax_footpaths(timetable, &mut round, &mut best, reached);
+            rounds.push(round);
+        }
+
+        // Did this round get to the destination any sooner?
+        let arrival = &rounds[k].arrival;
+        let Some((stop, walk)) = query
+            .egress
+            .iter()
+            .filter(|(stop, _)| arrival[*stop] != UNREACHED)
+            .min_by_key(|(stop, walk)| arrival[*stop] + walk)
+            .copied()
+        else {
+            continue;
+        };
+        if arrival[stop] + walk < best_target {
+            best_target = arrival[stop] + walk;
+            let mut legs = reconstruct(timetable, &rounds, k, stop);
+            legs.push(JourneyLeg::Egress { from: stop, walk });
+            journeys.push(Journey {
+                arrival: best_target,
+                legs,
+            });
+        }
+    }
+
+    journeys
+}
+
+/// Earliest arrival at every stop, for isochrones. Stops only reached after until are None.
+pub fn earliest_arrivals(
+    timetable: &Timetable,
+    access: &[(StopIdx, Time)],
+    departure: Time,
+    until: Time,
+) -> Vec<Option<Time>> {
+    let mut best = vec![UNREACHED; timetable.network.stops.len()];
+    let (mut round, mut marked) = access_round(timetable, access, departure, &mut best);
+    for _ in 0..MAX_ROUNDS {
+        if marked.is_empty() {
+            break;
+        }
+        let (mut next, reached) = transit_round(timetable, &round, &marked, &mut best, until);
+        marked = relax_footpaths(timetable, &mut next, &mut best, reached);
+        round = next;
+    }
+    best.into_iter()
+        .map(|time| (time <= until).then_some(time))
+        .collect()
+}
+
+/// Round 0: walking from the origin, then on to nearby stops.
+fn access_round(
+    timetable: &Timetable,
+    access: &[(StopIdx, Time)],
+    departure: Time,
+    best: &mut [Time],
+) -> (Round, Vec<StopIdx>) {
+    let stops = best.len();
+    let mut round = Round {
+        arrival: vec![UNREACHED; stops],
+        label: vec![None; stops],
+        ride: vec![None; stops],
+    };
+    let mut reached = vec![];
+    for &(stop, walk) in access {
+        let time = departure + walk;
+        if time < round.arrival[stop] {
+            round.arrival[stop] = time;
+            round.label[stop] = Some(Label::Access { walk });
+            round.ride[stop] = Some((time, Label::Access { walk }));
+            best[stop] = time;
+            reached.push(stop);
+        }
+    }
+    let marked = relax_footpaths(timetable, &mut round, best, reached);
+    (round, marked)
+}
+
+/// Rides every pattern through the stops marked last round.
+/// Gives the new round and the stops it reached by transit.
+fn transit_round(
+    timetable: &Timetable,
+    previous: &Round,
+    marked: &[StopIdx],
+    best: &mut [Time],
+    best_target: Time,
+) -> (Round, Vec<StopIdx>) {
+    let stops = best.len();
+    let mut round = Round {
+        arrival: previous.arrival.clone(),
+        label: vec![None; stops],
+        ride: vec![None; stops],
+    };
+
+    // Each pattern only needs scanning from the earliest marked stop on it.
+    let mut queue: Vec<PatternIdx> = vec![];
+    let mut first_pos = vec![usize::MAX; timetable.patterns.len()];
+    for &stop in marked {
+        for &(pattern, pos) in &timetable.stop_patterns[stop] {
+            if first_pos[pattern] == usize::MAX {
+                queue.push(pattern);
+            }
+            first_pos[pattern] = first_pos[pattern].min(pos);
+        }
+    }
+
+    let mut reached = vec![];
+    for pattern_idx in queue {
+        let pattern = &timetable.patterns[pattern_idx];
+        let mut current: Option<(TripIdx, usize)> = None;
+
+        for pos in first_pos[pattern_idx]..pattern.stops.len() {
+            let stop = pattern.stops[pos];
+
+            if let Some((trip, board_pos)) = current
+                && pattern.can_alight[pos]
+            {
+                let arrival = timetable.trips[trip].arrivals[pos];
+                if arrival < best[stop] && arrival < best_target {
+                    if round.ride[stop].is_none() {
+                        reached.push(stop);
+                    }
+                    let label = Label::Transit {
+                        trip,
+                        pattern: pattern_idx,
+                        board_pos,
+                        alight_pos: pos,
+                    };
+                    round.arrival[stop] = arrival;
+                    round.label[stop] = Some(label);
+                    round.ride[stop] = Some((arrival, label));
+                    best[stop] = arrival;
+                }
+            }
+
+            // Catch an earlier trip here if we can.
+            let ready = previous.arrival[stop];
+            if ready == UNREACHED || !pattern.can_board[pos] {
+                continue;
+            }
+            let boarded = current.map(|(trip, _)| timetable.trips[trip].departures[pos]);
+            if boarded.is_some_and(|departure| departure <= ready) {
+                continue;
+            }
+            let next = pattern
+                .trips
+                .partition_point(|&t| timetable.trips[t].departures[pos] < ready);
+            if let Some(&trip) = pattern.trips.get(next)
+                && boarded.is_none_or(|departure| timetable.trips[trip].departures[pos] < departure)
+            {
+                current = Some((trip, pos));
+            }
+        }
+    }
+
+    (round, reached)
+}
+
+/// Walks on from the stops just reached. Walking twice in a row isn't allowed,
+/// so footpaths only start at stops reached without one.
+fn relax_footpaths(
+    timetable: &Timetable,
+    round: &mut Round,
+    best: &mut [Time],
+    reached: Vec<StopIdx>,
+) -> Vec<StopIdx> {
+    let mut marked = reached.clone();
+    for &from in &reached {
+        let Some((time, _)) = round.ride[from] else {
+            continue;
+        };
+        for &(to, walk) in &timetable.network.footpaths[from] {
+            let arrival = time + walk;
+            if arrival < best[to] {
+                if round.label[to].is_none() {
+                    marked.push(to);
+                }
+                round.arrival[to] = arrival;
+                round.label[to] = Some(Label::Footpath { from, walk });
+                best[to] = arrival;
+            }
+        }
+    }
+    marked
+}
+
+/// Follows the labels back from a stop reached in round k to the origin.
+fn reconstruct(
+    timetable: &Timetable,
+    rounds: &[Round],
+    k: usize,
+    stop: StopIdx,
+) -> Vec<JourneyLeg> {
+    let mut legs = vec![];
+    let mut k = k;
+    let mut stop = stop;
+    loop {
+        // The stop was last improved in this round or an earlier one.
+        let Some((round, label)) = (0..=k)
+            .rev()
+            .find_map(|j| rounds[j].label[stop].map(|label| (j, label)))
+        else {
+            unreachable!("reached stops always have a label");
+        };
+        k = round;
+
+        let label = match label {
+            Label::Footpath { from, walk } => {
+                legs.push(JourneyLeg::Transfer {
+                    from,
+                    to: stop,
+                    walk,
+                });
+                stop = from;
+                rounds[k].ride[from]
+                    .expect("footpaths start at ridden stops")
+                    .1
+            }
+            label => label,
+        };
+
+        match label {
+            Label::Access { walk } => {
+                legs.push(JourneyLeg::Access { to: stop, walk });
+                break;
+            }
+            Label::Transit {
+                trip,
+                pattern,
+                board_pos,
+                alight_pos,
+            } => {
+                legs.push(JourneyLeg::Transit {
+                    trip,
+                    board_pos,
+                    alight_pos,
+                });
+                stop = timetable.patterns[pattern].stops[board_pos];
+                k -= 1;
+            }
+            Label::Footpath { .. } => unreachable!("footpaths never follow footpaths"),
+        }
+    }
+    legs.reverse();
+    legs
+}
@@ -0,0 +1,314 @@
+//! Applies realtime trip updates to a scheduled timetable.
+//!
+//! Delays, skipped stops, cancelled and added trips all end up as plain
+//! TripTimes, so RAPTOR never has to know about realtime.
+
+use chrono::{Days, NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use tracing::{info, instrument};
+
+use crate::{
+    gtfs::RealtimeGtfs,
+    routing::timetable::{DAY, Network, Time, Timetable, TripIdx, TripTimes},
+    transit_realtime::{
+        TripUpdate,
+        trip_descriptor::ScheduleRelationship as TripRelationship,
+        trip_update::{
+            StopTimeEvent, StopTimeUpdate,
+            stop_time_update::ScheduleRelationship as StopRelationship,
+        },
+    },
+};
+
+/// Unix time of midnight at the start of date, in the feed's timezone.
+pub fn local_midnight(date: NaiveDate, timezone: Tz) -> i64 {
+    timezone
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+        .map(|midnight| midnight.timestamp())
+        .unwrap_or_else(|| date.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
+
+/// Parses GTFS times like "25:10:00", which can run past midnight.
+fn parse_gtfs_time(time: &str) -> Option<Time> {
+    let mut parts = time.split(':').map(|p| p.parse::<Time>().ok());
+    let (h, m, s) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(h * 3600 + m * 60 + s)
+}
+
+/// Parses realtime start dates, like "20250701".
+pub fn parse_date(date: &Option<String>) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.as_deref()?, "%Y%m%d").ok()
+}
+
+/// Builds the timetable as it currently stands, from the scheduled one and the latest poll.
+/// Updates without a start date are taken to be for today.
+#[instrument(skip_all, fields(date = %scheduled.date))]
+pub fn apply_trip_updates(
+    scheduled: &Timetable,
+    realtime: &RealtimeGtfs,
+    today: NaiveDate,
+) -> Timetable {
+    let network = &scheduled.network;
+    let midnight = local_midnight(scheduled.date, network.timezone);
+    let mut trips: Vec<Option<TripTimes>> = scheduled.trips.iter().cloned().map(Some).collect();
+    let mut added = vec![];
+    let (mut updated, mut cancelled) = (0, 0);
+
+    let updates = realtime
+        .0
+        .iter()
+        .flat_map(|m| &m.entity)
+        .filter(|e| !e.is_deleted())
+        .filter_map(|e| e.trip_update.as_ref());
+    for update in updates {
+        let descriptor = &update.trip;
+        let scheduled_trip = find_trip(
+            scheduled,
+            descriptor.trip_id.as_deref(),
+            parse_date(&descriptor.start_date),
+            today,
+        );
+
+        match descriptor.schedule_relationship() {
+            TripRelationship::Scheduled | TripRelationship::Replacement => {
+                if let Some(trip) = scheduled_trip.and_then(|i| trips[i].as_mut()) {
+                    apply_stop_time_updates(trip, &update.stop_time_update, midnight, network);
+                    updated += 1;
+                }
+            }
+            TripRelationship::Canceled | TripRelationship::Deleted => {
+                if let Some(i) = scheduled_trip {
+                    trips[i] = None;
+                    cancelled += 1;
+                }
+            }
+            TripRelationship::Duplicated => {
+                // The start date is for the copy, not the trip being copied.
+                let original = find_trip(scheduled, descriptor.trip_id.as_deref(), None, today);
+                added.extend(original.and_then(|i| {
+                    duplicate_trip(scheduled, &scheduled.trips[i], update, midnight)
+                }));
+            }
+            TripRelationship::Added | TripRelationship::New => {
+                added.extend(new_trip(scheduled, update, today, midnight));
+            }
+            // Frequency based, there's no timetable to change.
+            TripRelationship::Unscheduled => {}
+        }
+    }
+
+    info!(
+        updated,
+        cancelled,
+        added = added.len(),
+        "Applied trip updates"
+    );
+    let trips = trips.into_iter().flatten().chain(added).collect();
+    Timetable::new(scheduled.date, network.clone(), trips)
+}
+
+/// Finds a scheduled trip. Without a service date, today's trip is preferred over yesterday's.
+fn find_trip(
+    timetable: &Timetable,
+    trip_id: Option<&str>,
+    service_date: Option<NaiveDate>,
+    today: NaiveDate,
+) -> Option<TripIdx> {
+    let trip_id = trip_id?.to_owned();
+    let dates = match service_date {
+        Some(date) => vec![date],
+        None => vec![today, today - Days::new(1)],
+    };
+    dates
+        .into_iter()
+        .find_map(|date| timetable.trip_index.get(&(trip_id.clone(), date)))
+        .copied()
+}
+
+/// Seconds between the timetable's date and a service date, if the trip could be in it.
+fn service_offset(timetable: &Timetable, service_date: NaiveDate) -> Option<Time> {
+    if service_date == timetable.date {
+        Some(0)
+    } else if service_date == timetable.date - Days::new(1) {
+        Some(-DAY)
+    } else {
+        None
+    }
+}
+
+/// A copy of a scheduled trip, moved to the start date and time given in the trip properties.
+fn duplicate_trip(
+    timetable: &Timetable,
+    original: &TripTimes,
+    update: &TripUpdate,
+    midnight: i64,
+) -> Option<TripTimes> {
+    let properties = update.trip_properties.as_ref()?;
+    let service_date = parse_date(&properties.start_date)?;
+    let start = parse_gtfs_time(properties.start_time.as_deref()?)?
+        + service_offset(timetable, service_date)?;
+
+    let mut trip = original.clone();
+    trip.trip_id = properties.trip_id.clone()?;
+    trip.service_date = service_date;
+    let shift = start - trip.departures[0];
+    trip.arrivals.iter_mut().for_each(|t| *t += shift);
+    trip.departures.iter_mut().for_each(|t| *t += shift);
+    if let Some(shape_id) = &properties.shape_id {
+        trip.shape_id = Some(shape_id.clone());
+    }
+    if let Some(headsign) = &properties.trip_headsign {
+        trip.headsign = Some(headsign.clone());
+    }
+    apply_stop_time_updates(
+        &mut trip,
+        &update.stop_time_update,
+        midnight,
+        &timetable.network,
+    );
+    Some(trip)
+}
+
+/// A trip that isn't in the schedule at all, built from the stops and times in the update.
+fn new_trip(
+    timetable: &Timetable,
+    update: &TripUpdate,
+    today: NaiveDate,
+    midnight: i64,
+) -> Option<TripTimes> {
+    let descriptor = &update.trip;
+    let properties = update.trip_properties.as_ref();
+    let service_date = parse_date(&descriptor.start_date).unwrap_or(today);
+    service_offset(timetable, service_date)?;
+
+    let mut trip = TripTimes {
+        trip_id: properties
+            .and_then(|p| p.trip_id.clone())
+            .or(descriptor.trip_id.clone())?,
+        service_date,
+        route_id: descriptor.route_id.clone()?,
+        shape_id: properties.and_then(|p| p.shape_id.clone()),
+        headsign: properties.and_then(|p| p.trip_headsign.clone()),
+        stops: vec![],
+        stop_sequences: vec![],
+        arrivals: vec![],
+        departures: vec![],
+        can_board: vec![],
+        can_alight: vec![],
+        realtime: true,
+    };
+    let time = |event: &Option<StopTimeEvent>| Some((event.as_ref()?.time? - midnight) as Time);
+    for (i, stop_time) in update.stop_time_update.iter().enumerate() {
+        if stop_time.schedule_relationship() == StopRelationship::Skipped {
+            continue;
+        }
+        let Some(&stop) = stop_time
+            .stop_id
+            .as_ref()
+            .and_then(|id| timetable.network.stop_index.get(id))
+        else {
+            continue;
+        };
+        let (arrival, departure) = (time(&stop_time.arrival), time(&stop_time.departure));
+        let Some(departure) = departure.or(arrival) else {
+            continue;
+        };
+        trip.stops.push(stop);
+        trip.stop_sequences.push(
+            stop_time
+                .stop_sequence
+                .map(|s| s as i32)
+                .unwrap_or(i as i32),
+        );
+        trip.arrivals.push(arrival.unwrap_or(departure));
+        trip.departures.push(departure);
+        trip.can_board.push(true);
+        trip.can_alight.push(true);
+    }
+    keep_in_order(&mut trip);
+    (trip.stops.len() > 1).then_some(trip)
+}
+
+/// Moves a trip's times by the updates. Delays carry on to later stops until
+/// the next update, and stops before the first update keep their scheduled times.
+fn apply_stop_time_updates(
+    trip: &mut TripTimes,
+    updates: &[StopTimeUpdate],
+    midnight: i64,
+    network: &Network,
+) {
+    if updates.is_empty() {
+        return;
+    }
+    trip.realtime = true;
+
+    // Updates are in stop order, so stop_ids are matched from after the last match.
+    let mut by_pos: Vec<Option<&StopTimeUpdate>> = vec![None; trip.stops.len()];
+    let mut next = 0;
+    for update in updates {
+        let pos = match (update.stop_sequence, &update.stop_id) {
+            (Some(sequence), _) => trip
+                .stop_sequences
+                .iter()
+                .position(|&s| s == sequence as i32),
+            (None, Some(stop_id)) => {
+                (next..trip.stops.len()).find(|&p| network.stops[trip.stops[p]].stop_id == *stop_id)
+            }
+            (None, None) => None,
+        };
+        if let Some(pos) = pos {
+            by_pos[pos] = Some(update);
+            next = pos + 1;
+        }
+    }
+
+    // Absolute times win over delays, as they're what the delay was worked out from.
+    let event_delay = |event: &Option<StopTimeEvent>, scheduled: Time| {
+        let event = event.as_ref()?;
+        event
+            .time
+            .map(|time| (time - midnight) as Time - scheduled)
+            .or(event.delay)
+    };
+
+    let mut delay: Option<Time> = None;
+    for (pos, update) in by_pos.into_iter().enumerate() {
+        if let Some(update) = update {
+            match update.schedule_relationship() {
+                StopRelationship::Skipped => {
+                    trip.can_board[pos] = false;
+                    trip.can_alight[pos] = false;
+                }
+                StopRelationship::NoData => delay = None,
+                StopRelationship::Scheduled | StopRelationship::Unscheduled => {
+                    let arrival = event_delay(&update.arrival, trip.arrivals[pos]);
+                    let departure = event_delay(&update.departure, trip.departures[pos]);
+                    if let Some(arrival) = arrival.or(departure) {
+                        trip.arrivals[pos] += arrival;
+                    }
+                    delay = departure.or(arrival).or(delay);
+                    if let Some(delay) = delay {
+                        trip.departures[pos] += delay;
+                    }
+                    continue;
+                }
+            }
+        }
+        if let Some(delay) = delay {
+            trip.arrivals[pos] += delay;
+            trip.departures[pos] += delay;
+        }
+    }
+    keep_in_order(trip);
+}
+
+/// Predictions don't always agree with each other, but times along a trip can't go backwards.
+fn keep_in_order(trip: &mut TripTimes) {
+    for pos in 0..trip.stops.len() {
+        if pos > 0 {
+            trip.arrivals[pos] = trip.arrivals[pos].max(trip.departures[pos - 1]);
+        }
+        trip.departures[pos] = trip.departures[pos].max(trip.arrivals[pos]);
+    }
+}
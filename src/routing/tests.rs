@@ -0,0 +1,478 @@
+//! Routing Tests
+//!
+//! Journey planning over a small made up network, with and without
+//! realtime updates applied. No db needed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+
+use super::isochrone::{isochrone, trace};
+use super::realtime::{apply_trip_updates, local_midnight};
+use super::timetable::{Network, StopInfo, Time, Timetable, TripTimes};
+use super::transfers::{self, TransferConfig};
+use super::{Endpoint, Leg, plan};
+use crate::db::types::Stop;
+use crate::gtfs::RealtimeGtfs;
+use crate::transit_realtime::{
+    FeedEntity, FeedMessage, TripDescriptor, TripUpdate,
+    trip_descriptor::ScheduleRelationship as TripRelationship,
+    trip_update::{
+        StopTimeEvent, StopTimeUpdate, stop_time_update::ScheduleRelationship as StopRelationship,
+    },
+};
+
+/// A -> B -> C on route 1, then a short walk from C to C2 for route 2 to D.
+/// D is also served directly from A by the slow route 3.
+fn network() -> Arc<Network> {
+    let stop = |stop_id: &str, lat: f64| Stop {
+        stop_id: stop_id.into(),
+        stop_code: None,
+        stop_name: Some(stop_id.into()),
+        stop_desc: None,
+        stop_lat: Some(lat),
+        stop_lon: Some(153.0),
+        zone_id: None,
+        stop_url: None,
+        location_type: None,
+        parent_station: None,
+        platform_code: None,
+    };
+    let stops = vec![
+        stop("A", -27.40),
+        stop("B", -27.41),
+        stop("C", -27.42),
+        stop("C2", -27.4205),
+        stop("D", -27.45),
+    ];
+    let transfers = transfers::generate(
+        &stops,
+        &TransferConfig {
+            max_distance_m: 400.0,
+        },
+    );
+    Arc::new(Network::new(
+        stops.into_iter().map(StopInfo::from).collect(),
+        vec![],
+        transfers,
+        HashMap::new(),
+        chrono_tz::Australia::Brisbane,
+    ))
+}
+
+fn date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, 7, 1).unwrap()
+}
+
+fn hm(h: Time, m: Time) -> Time {
+    h * 3600 + m * 60
+}
+
+fn trip(network: &Network, trip_id: &str, route_id: &str, stops: &[(&str, Time)]) -> TripTimes {
+    TripTimes {
+        trip_id: trip_id.into(),
+        service_date: date(),
+        route_id: route_id.into(),
+        shape_id: None,
+        headsign: None,
+        stops: stops.iter().map(|(s, _)| network.stop_index[*s]).collect(),
+        stop_sequences: (1..=stops.len() as i32).collect(),
+        arrivals: stops.iter().map(|(_, t)| *t).collect(),
+        departures: stops.iter().map(|(_, t)| *t).collect(),
+        can_board: vec![true; stops.len()],
+        can_alight: vec![true; stops.len()],
+        realtime: false,
+    }
+}
+
+fn timetable() -> Timetable {
+    let network = network();
+    let mut trips = vec![];
+    for (i, start) in [hm(8, 0), hm(8, 15), hm(8, 30)].into_iter().enumerate() {
+        trips.push(trip(
+            &network,
+            &format!("r1-{i}"),
+            "1",
+            &[("A", start), ("B", start + 600), ("C", start + 1200)],
+        ));
+        trips.push(trip(
+            &network,
+            &format!("r2-{i}"),
+            "2",
+            &[("C2", start + 1500), ("D", start + 2100)],
+        ));
+    }
+    trips.push(trip(
+        &network,
+        "r3-0",
+        "3",
+        &[("A", hm(8, 0)), ("D", hm(9, 30))],
+    ));
+    Timetable::new(date(), network, trips)
+}
+
+fn transit_trips(legs: &[Leg]) -> Vec<&str> {
+    legs.iter()
+        .filter_map(|leg| match leg {
+            Leg::Transit { trip_id, .. } => Some(trip_id.as_str()),
+            Leg::Walk { .. } => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_boards_first_trip_after_departure() {
+    let timetable = timetable();
+    let plans = plan(
+        &timetable,
+        &Endpoint::Stop("A".into()),
+        &Endpoint::Stop("C".into()),
+        hm(8, 5),
+        0,
+    );
+    assert_eq!(plans.len(), 1);
+    assert_eq!(transit_trips(&plans[0].legs), vec!["r1-1"]);
+    assert_eq!(plans[0].transfers, 0);
+    assert_eq!(plans[0].arrival.format("%H:%M").to_string(), "08:35");
+}
+
+#[test]
+fn test_transfers_with_walk() {
+    let timetable = timetable();
+    let plans = plan(
+        &timetable,
+        &Endpoint::Stop("A".into()),
+        &Endpoint::Stop("D".into()),
+        hm(7, 55),
+        0,
+    );
+
+    // The faster one with a transfer, then the slow direct trip.
+    assert_eq!(plans.len(), 2);
+    assert_eq!(transit_trips(&plans[0].legs), vec!["r1-0", "r2-0"]);
+    assert_eq!(plans[0].transfers, 1);
+    assert!(matches!(plans[0].legs[1], Leg::Walk { .. }));
+    assert_eq!(plans[0].arrival.format("%H:%M").to_string(), "08:35");
+    assert_eq!(transit_trips(&plans[1].legs), vec!["r3-0"]);
+    assert_eq!(plans[1].transfers, 0);
+}
+
+#[test]
+fn test_range_query_finds_later_departures() {
+    let timetable = timetable();
+    let plans = plan(
+        &timetable,
+        &Endpoint::Stop("A".into()),
+        &Endpoint::Stop("C".into()),
+        hm(8, 0),
+        30 * 60,
+    );
+    let trips: Vec<Vec<&str>> = plans.iter().map(|p| transit_trips(&p.legs)).collect();
+    assert_eq!(trips, vec![vec!["r1-0"], vec!["r1-1"], vec!["r1-2"]]);
+}
+
+#[test]
+fn test_walks_from_coordinates() {
+    let timetable = timetable();
+    let plans = plan(
+        &timetable,
+        &Endpoint::Coord {
+            lat: -27.4003,
+            lon: 153.0,
+        },
+        &Endpoint::Stop("B".into()),
+        hm(8, 0),
+        0,
+    );
+    assert_eq!(plans.len(), 1);
+    assert!(matches!(plans[0].legs[0], Leg::Walk { .. }));
+    assert_eq!(transit_trips(&plans[0].legs), vec!["r1-1"]);
+}
+
+#[test]
+fn test_overtaking_trips_get_separate_patterns() {
+    let network = network();
+    let trips = vec![
+        trip(&network, "slow", "1", &[("A", hm(8, 0)), ("B", hm(8, 30))]),
+        trip(&network, "fast", "1", &[("A", hm(8, 5)), ("B", hm(8, 10))]),
+    ];
+    let timetable = Timetable::new(date(), network, trips);
+    assert_eq!(timetable.patterns.len(), 2);
+
+    let plans = plan(
+        &timetable,
+        &Endpoint::Stop("A".into()),
+        &Endpoint::Stop("B".into()),
+        hm(7, 55),
+        0,
+    );
+    assert_eq!(transit_trips(&plans[0].legs), vec!["fast"]);
+}
+
+#[test]
+fn test_parses_endpoints() {
+    assert_eq!(
+        "-27.47, 153.02".parse::<Endpoint>().unwrap(),
+        Endpoint::Coord {
+            lat: -27.47,
+            lon: 153.02
+        }
+    );
+    assert_eq!(
+        "600029".parse::<Endpoint>().unwrap(),
+        Endpoint::Stop("600029".into())
+    );
+    assert!("".parse::<Endpoint>().is_err());
+}
+
+fn realtime(updates: Vec<(&str, TripRelationship, Vec<StopTimeUpdate>)>) -> RealtimeGtfs {
+    let entity = updates
+        .into_iter()
+        .map(|(trip_id, relationship, stop_time_update)| {
+            let mut trip = TripDescriptor {
+                trip_id: Some(trip_id.into()),
+                route_id: Some("1".into()),
+                ..Default::default()
+            };
+            trip.set_schedule_relationship(relationship);
+            FeedEntity {
+                id: trip_id.into(),
+                trip_update: Some(TripUpdate {
+                    trip,
+                    stop_time_update,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+    RealtimeGtfs(vec![FeedMessage {
+        entity,
+        ..Default::default()
+    }])
+}
+
+fn stop_update(stop_sequence: u32, event: StopTimeEvent) -> StopTimeUpdate {
+    StopTimeUpdate {
+        stop_sequence: Some(stop_sequence),
+        arrival: Some(event),
+        ..Default::default()
+    }
+}
+
+fn plan_now(timetable: &Timetable, from: &str, to: &str, departure: Time) -> Vec<Vec<String>> {
+    plan(
+        timetable,
+        &Endpoint::Stop(from.into()),
+        &Endpoint::Stop(to.into()),
+        departure,
+        0,
+    )
+    .iter()
+    .map(|p| {
+        transit_trips(&p.legs)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    })
+    .collect()
+}
+
+#[test]
+fn test_delays_carry_on_to_later_stops() {
+    let scheduled = timetable();
+    let delayed = StopTimeEvent {
+        delay: Some(5 * 60),
+        ..Default::default()
+    };
+    let feed = realtime(vec![(
+        "r1-0",
+        TripRelationship::Scheduled,
+        vec![stop_update(2, delayed)],
+    )]);
+    let current = apply_trip_updates(&scheduled, &feed, date());
+
+    // Scheduled at B at 08:10, now leaving at 08:15 and reaching C at 08:25.
+    let plans = plan(
+        &current,
+        &Endpoint::Stop("B".into()),
+        &Endpoint::Stop("C".into()),
+        hm(8, 12),
+        0,
+    );
+    assert_eq!(transit_trips(&plans[0].legs), vec!["r1-0"]);
+    assert_eq!(plans[0].arrival.format("%H:%M").to_string(), "08:25");
+    assert!(matches!(
+        plans[0].legs[0],
+        Leg::Transit { realtime: true, .. }
+    ));
+
+    // Stops before the update keep their times.
+    let i = current.trip_index[&("r1-0".to_owned(), date())];
+    assert_eq!(current.trips[i].departures[0], hm(8, 0));
+}
+
+#[test]
+fn test_cancelled_and_skipped() {
+    let scheduled = timetable();
+    let mut skipped = StopTimeUpdate {
+        stop_sequence: Some(3),
+        ..Default::default()
+    };
+    skipped.set_schedule_relationship(StopRelationship::Skipped);
+    let feed = realtime(vec![
+        ("r1-0", TripRelationship::Scheduled, vec![skipped]),
+        ("r1-1", TripRelationship::Canceled, vec![]),
+    ]);
+    let current = apply_trip_updates(&scheduled, &feed, date());
+
+    assert_eq!(
+        plan_now(&scheduled, "A", "C", hm(7, 55)),
+        vec![vec!["r1-0"]]
+    );
+    assert_eq!(plan_now(&current, "A", "C", hm(7, 55)), vec![vec!["r1-2"]]);
+    // Still fine for the stops it doesn't skip.
+    assert_eq!(plan_now(&current, "A", "B", hm(7, 55)), vec![vec!["r1-0"]]);
+}
+
+#[test]
+fn test_added_trip() {
+    let scheduled = timetable();
+    let midnight = local_midnight(date(), scheduled.network.timezone);
+    let at = |time: Time| StopTimeEvent {
+        time: Some(midnight + time as i64),
+        ..Default::default()
+    };
+    let feed = realtime(vec![(
+        "extra",
+        TripRelationship::New,
+        vec![
+            StopTimeUpdate {
+                stop_id: Some("A".into()),
+                departure: Some(at(hm(8, 2))),
+                ..Default::default()
+            },
+            StopTimeUpdate {
+                stop_id: Some("C".into()),
+                arrival: Some(at(hm(8, 12))),
+                ..Default::default()
+            },
+        ],
+    )]);
+    let current = apply_trip_updates(&scheduled, &feed, date());
+    assert_eq!(plan_now(&current, "A", "C", hm(8, 1)), vec![vec!["extra"]]);
+}
+
+#[test]
+fn test_isochrone_stops_and_bands() {
+    let timetable = timetable();
+    let result = isochrone(
+        &timetable,
+        &Endpoint::Stop("A".into()),
+        hm(7, 55),
+        &[35 * 60, 20 * 60],
+    );
+
+    // A straight away, B on r1-0 at 08:10 and C at 08:20, but D isn't until 08:35.
+    let stops: Vec<(&str, Time)> = result
+        .stops
+        .iter()
+        .map(|s| (s.stop_id.as_str(), s.travel_time))
+        .collect();
+    assert_eq!(stops[..3], [("A", 0), ("B", 15 * 60), ("C", 25 * 60)]);
+    assert!(stops.iter().all(|(stop, _)| *stop != "D"));
+
+    assert_eq!(result.bands[0].travel_time, 20 * 60);
+    assert!(!result.bands[0].area.is_empty());
+    assert_eq!(result.bands[1].travel_time, 35 * 60);
+    assert!(!result.bands[1].area.is_empty());
+}
+
+#[test]
+fn test_trace_outlines_and_holes() {
+    // A ring of cells around an empty middle.
+    let ring = trace(3, 3, |r, c| {
+        (0..3).contains(&r) && (0..3).contains(&c) && (r, c) != (1, 1)
+    });
+    assert_eq!(ring.len(), 1);
+    assert_eq!(ring[0].len(), 2);
+    assert_eq!(ring[0][0], vec![(0, 0), (3, 0), (3, 3), (0, 3), (0, 0)]);
+    assert_eq!(ring[0][1].len(), 5);
+
+    // Cells touching only at a corner stay separate polygons.
+    let diagonal = trace(2, 2, |r, c| (r, c) == (0, 0) || (r, c) == (1, 1));
+    assert_eq!(diagonal.len(), 2);
+    assert!(diagonal.iter().all(|p| p.len() == 1 && p[0].len() == 5));
+}
+
+#[test]
+fn test_generates_transfers_between_nearby_stops() {
+    let stop = |stop_id: &str, lat: Option<f64>, location_type: Option<i32>| Stop {
+        stop_id: stop_id.into(),
+        stop_code: None,
+        stop_name: None,
+        stop_desc: None,
+        stop_lat: lat,
+        stop_lon: lat.map(|_| 153.0),
+        zone_id: None,
+        stop_url: None,
+        location_type,
+        parent_station: None,
+        platform_code: None,
+    };
+    let stops = vec![
+        stop("C", Some(-27.42), None),
+        stop("C2", Some(-27.4205), Some(0)),
+        stop("station", Some(-27.4202), Some(1)),
+        stop("far", Some(-27.43), None),
+        stop("nowhere", None, None),
+    ];
+    let config = TransferConfig {
+        max_distance_m: 400.0,
+    };
+    let mut transfers: Vec<(String, String, i32)> = transfers::generate(&stops, &config)
+        .into_iter()
+        .map(|t| {
+            // About 55m apart in a straight line.
+            assert!((t.distance_m - 72.3).abs() < 1.0, "{}", t.distance_m);
+            (t.from_stop_id, t.to_stop_id, t.walk_time)
+        })
+        .collect();
+    transfers.sort();
+    assert_eq!(
+        transfers,
+        vec![("C".into(), "C2".into(), 56), ("C2".into(), "C".into(), 56)]
+    );
+}
+
+#[test]
+fn test_local_midnight() {
+    // Brisbane is UTC+10 all year, whatever zone the server is in.
+    let midnight = local_midnight(date(), chrono_tz::Australia::Brisbane);
+    let utc = NaiveDate::from_ymd_opt(2025, 6, 30)
+        .unwrap()
+        .and_hms_opt(14, 0, 0)
+        .unwrap()
+        .and_utc();
+    assert_eq!(midnight, utc.timestamp());
+}
+
+#[test]
+fn test_parse_endpoint() {
+    assert_eq!(
+        "-27.46, 153.02".parse::<Endpoint>().unwrap(),
+        Endpoint::Coord {
+            lat: -27.46,
+            lon: 153.02
+        }
+    );
+    assert_eq!(
+        "600029".parse::<Endpoint>().unwrap(),
+        Endpoint::Stop("600029".into())
+    );
+    // Numbers that can't be a place aren't taken for a stop id either.
+    for invalid in ["", "91,153", "-27.46,181", "NaN,153"] {
+        assert!(invalid.parse::<Endpoint>().is_err(), "{invalid}");
+    }
+}
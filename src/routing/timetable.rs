@@ -0,0 +1,396 @@
+//! The in-memory timetable RAPTOR runs over.
+//!
+//! Split in two: the Network (stops, footpaths, shapes) doesn't depend on the
+//! date and is loaded once, while a Timetable holds the trips running on one day.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{Days, NaiveDate, Utc};
+use chrono_tz::Tz;
+use sqlx::postgres::types::PgInterval;
+use tracing::{info, instrument};
+
+use crate::{
+    db::{
+        Db, queries,
+        types::{GeneratedTransfer, Route, Stop, StopTime, Trip},
+    },
+    geo::PointGrid,
+    routing::MAX_ACCESS_WALK_M,
+};
+
+pub type StopIdx = usize;
+pub type PatternIdx = usize;
+pub type TripIdx = usize;
+
+/// Seconds since midnight at the start of the timetable's date.
+/// Can be negative (yesterday's late trips) or past a day.
+pub type Time = i32;
+
+pub const DAY: Time = 24 * 60 * 60;
+
+/// Average walking speed, about 4.7km/h.
+pub const WALK_SPEED_MPS: f64 = 1.3;
+
+/// Straight lines are optimistic, streets aren't. Distances get multiplied by this.
+pub const DETOUR_FACTOR: f64 = 1.3;
+
+/// How long walking a straight-line distance takes, with the detour factor applied.
+pub fn walk_time(distance_m: f64) -> Time {
+    (distance_m * DETOUR_FACTOR / WALK_SPEED_MPS).ceil() as Time
+}
+
+fn interval_secs(interval: &PgInterval) -> Time {
+    (interval.days * DAY) + (interval.microseconds / 1_000_000) as Time
+}
+
+#[derive(Debug, Clone)]
+pub struct StopInfo {
+    pub stop_id: String,
+    pub name: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub parent_station: Option<String>,
+}
+
+impl From<Stop> for StopInfo {
+    fn from(stop: Stop) -> StopInfo {
+        StopInfo {
+            stop_id: stop.stop_id,
+            name: stop.stop_name,
+            lat: stop.stop_lat,
+            lon: stop.stop_lon,
+            parent_station: stop.parent_station,
+        }
+    }
+}
+
+/// Everything about the network that is the same every day.
+pub struct Network {
+    pub stops: Vec<StopInfo>,
+    pub stop_index: HashMap<String, StopIdx>,
+    /// Platforms of each station.
+    pub children: HashMap<StopIdx, Vec<StopIdx>>,
+    pub routes: HashMap<String, Route>,
+    /// Walking connections between nearby stops, with how long they take.
+    pub footpaths: Vec<Vec<(StopIdx, Time)>>,
+    /// Indexes stops (by StopIdx) for nearby stop lookups.
+    pub stop_grid: PointGrid,
+    /// (lon, lat) points of each shape, in order.
+    pub shapes: HashMap<String, Vec<(f64, f64)>>,
+    /// The feed's timezone, which service days start and end in.
+    pub timezone: Tz,
+}
+
+impl Network {
+    #[instrument(skip(db))]
+    pub async fn load(db: &Db) -> Result<Network> {
+        info!("Loading routing network");
+        let stops = queries::get_stops(&db.0).await?;
+        let routes = queries::get_routes(&db.0).await?;
+        let transfers = queries::get_generated_transfers(&db.0).await?;
+
+        let mut shapes: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
+        for shape in queries::get_shapes(&db.0).await? {
+            shapes
+                .entry(shape.shape_id)
+                .or_default()
+                .push((shape.shape_pt_lon, shape.shape_pt_lat));
+        }
+
+        let timezone = db.timezone().await?;
+
+        let stops = stops.into_iter().map(StopInfo::from).collect();
+        let network = Network::new(stops, routes, transfers, shapes, timezone);
+        info!(
+            stops = network.stops.len(),
+            transfers = network.footpaths.iter().map(Vec::len).sum::<usize>(),
+            "Finished loading routing network"
+        );
+        Ok(network)
+    }
+
+    /// Indexes the stops, and turns the transfers into footpaths between them.
+    pub fn new(
+        stops: Vec<StopInfo>,
+        routes: Vec<Route>,
+        transfers: Vec<GeneratedTransfer>,
+        shapes: HashMap<String, Vec<(f64, f64)>>,
+        timezone: Tz,
+    ) -> Network {
+        let stop_index: HashMap<String, StopIdx> = stops
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.stop_id.clone(), i))
+            .collect();
+
+        let mut children: HashMap<StopIdx, Vec<StopIdx>> = HashMap::new();
+        for (i, stop) in stops.iter().enumerate() {
+            if let Some(parent) = stop.parent_station.as_ref().and_then(|p| stop_index.get(p)) {
+                children.entry(*parent).or_default().push(i);
+            }
+        }
+
+        // Stops without coordinates get parked far away, where nothing will find them.
+        let stop_grid = PointGrid::new(
+            stops
+                .iter()
+                .map(|s| (s.lat.unwrap_or(90.0), s.lon.unwrap_or(0.0)))
+                .collect(),
+            MAX_ACCESS_WALK_M,
+        );
+
+        let mut footpaths: Vec<Vec<(StopIdx, Time)>> = vec![vec![]; stops.len()];
+        for transfer in transfers {
+            if let (Some(&from), Some(&to)) = (
+                stop_index.get(&transfer.from_stop_id),
+                stop_index.get(&transfer.to_stop_id),
+            ) {
+                footpaths[from].push((to, transfer.walk_time));
+            }
+        }
+
+        Network {
+            stops,
+            stop_index,
+            children,
+            routes: routes
+                .into_iter()
+                .map(|r| (r.route_id.clone(), r))
+                .collect(),
+            footpaths,
+            stop_grid,
+            shapes,
+            timezone,
+        }
+    }
+
+    /// Today's date in the feed's timezone.
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.timezone).date_naive()
+    }
+
+    /// The stops a stop id stands for: itself, or a station's platforms.
+    pub fn resolve_stop(&self, stop_id: &str) -> Vec<StopIdx> {
+        let Some(&stop) = self.stop_index.get(stop_id) else {
+            return vec![];
+        };
+        match self.children.get(&stop) {
+            Some(children) => children.clone(),
+            None => vec![stop],
+        }
+    }
+
+    /// Stops within walking distance of a point, with how long the walk takes.
+    pub fn stops_near(&self, lat: f64, lon: f64, max_walk_m: f64) -> Vec<(StopIdx, Time)> {
+        self.stop_grid
+            .within(lat, lon, max_walk_m)
+            .into_iter()
+            .map(|(stop, distance)| (stop, walk_time(distance)))
+            .collect()
+    }
+}
+
+/// One trip's stop times, relative to the timetable's date.
+#[derive(Debug, Clone)]
+pub struct TripTimes {
+    pub trip_id: String,
+    /// The day the trip runs on. Late trips from yesterday run on yesterday.
+    pub service_date: NaiveDate,
+    pub route_id: String,
+    pub shape_id: Option<String>,
+    pub headsign: Option<String>,
+    pub stops: Vec<StopIdx>,
+    pub stop_sequences: Vec<i32>,
+    pub arrivals: Vec<Time>,
+    pub departures: Vec<Time>,
+    pub can_board: Vec<bool>,
+    pub can_alight: Vec<bool>,
+    /// Whether the times come from a realtime update rather than the schedule.
+    pub realtime: bool,
+}
+
+impl TripTimes {
+    /// Builds a trip from its stop times (ordered by sequence), shifted by offset seconds.
+    fn new(
+        trip: &Trip,
+        stop_times: &[StopTime],
+        service_date: NaiveDate,
+        offset: Time,
+        network: &Network,
+    ) -> Option<TripTimes> {
+        let mut times = TripTimes {
+            trip_id: trip.trip_id.clone(),
+            service_date,
+            route_id: trip.route_id.clone(),
+            shape_id: trip.shape_id.clone(),
+            headsign: trip.trip_headsign.clone(),
+            stops: vec![],
+            stop_sequences: vec![],
+            arrivals: vec![],
+            departures: vec![],
+            can_board: vec![],
+            can_alight: vec![],
+            realtime: false,
+        };
+        for stop_time in stop_times {
+            let departure = interval_secs(&stop_time.departure_time) + offset;
+            let arrival = stop_time
+                .arrival_time
+                .as_ref()
+                .map(|a| interval_secs(a) + offset)
+                .unwrap_or(departure);
+            times
+                .stops
+                .push(*network.stop_index.get(&stop_time.stop_id)?);
+            times.stop_sequences.push(stop_time.stop_sequence);
+            times.arrivals.push(arrival);
+            times.departures.push(departure);
+            // 1 means no pickup/drop off at all. Arranged ones we treat as available.
+            times.can_board.push(stop_time.pickup_type != 1);
+            times.can_alight.push(stop_time.drop_off_type != 1);
+        }
+        (times.stops.len() > 1).then_some(times)
+    }
+
+    /// True if this trip is never earlier than other at any stop, so they can share a pattern.
+    fn never_before(&self, other: &TripTimes) -> bool {
+        self.departures
+            .iter()
+            .zip(&other.departures)
+            .all(|(a, b)| a >= b)
+            && self
+                .arrivals
+                .iter()
+                .zip(&other.arrivals)
+                .all(|(a, b)| a >= b)
+    }
+}
+
+/// Trips that visit the same stops in the same order (a RAPTOR "route").
+/// Trips are ordered so none overtakes another, which lets boarding binary search.
+#[derive(Debug)]
+pub struct Pattern {
+    pub route_id: String,
+    pub stops: Vec<StopIdx>,
+    pub can_board: Vec<bool>,
+    pub can_alight: Vec<bool>,
+    pub trips: Vec<TripIdx>,
+}
+
+/// Trips with the same route, stops and pickup/drop off rules share a pattern.
+type PatternKey<'a> = (&'a str, &'a [StopIdx], &'a [bool], &'a [bool]);
+
+/// The trips running on a single day, grouped into patterns.
+pub struct Timetable {
+    pub date: NaiveDate,
+    pub network: Arc<Network>,
+    pub trips: Vec<TripTimes>,
+    /// Trips by trip_id and service date, as late trips from yesterday can share an id with today's.
+    pub trip_index: HashMap<(String, NaiveDate), TripIdx>,
+    pub patterns: Vec<Pattern>,
+    /// The patterns visiting each stop, and at which position.
+    pub stop_patterns: Vec<Vec<(PatternIdx, usize)>>,
+}
+
+impl Timetable {
+    /// Loads the trips running on date, plus yesterday's trips still running after midnight.
+    #[instrument(skip(db, network))]
+    pub async fn load(db: &Db, network: Arc<Network>, date: NaiveDate) -> Result<Timetable> {
+        info!("Loading timetable");
+        let mut trips = vec![];
+        for (day, offset, past_midnight_only) in
+            [(date, 0, false), (date - Days::new(1), -DAY, true)]
+        {
+            let service_ids = queries::get_active_service_ids(day, &db.0).await?;
+            let day_trips: HashMap<String, Trip> =
+                queries::get_trips_by_service(&service_ids, &db.0)
+                    .await?
+                    .into_iter()
+                    .map(|t| (t.trip_id.clone(), t))
+                    .collect();
+            let stop_times =
+                queries::get_stop_times_by_service(&service_ids, past_midnight_only, &db.0).await?;
+
+            for stop_times in stop_times.chunk_by(|a, b| a.trip_id == b.trip_id) {
+                let Some(trip) = day_trips.get(&stop_times[0].trip_id) else {
+                    continue;
+                };
+                trips.extend(TripTimes::new(trip, stop_times, day, offset, &network));
+            }
+        }
+
+        let timetable = Timetable::new(date, network, trips);
+        info!(
+            trips = timetable.trips.len(),
+            patterns = timetable.patterns.len(),
+            "Finished loading timetable"
+        );
+        Ok(timetable)
+    }
+
+    /// Groups trips into patterns and indexes them.
+    pub fn new(date: NaiveDate, network: Arc<Network>, trips: Vec<TripTimes>) -> Timetable {
+        let mut groups: BTreeMap<PatternKey, Vec<TripIdx>> = BTreeMap::new();
+        for (i, trip) in trips.iter().enumerate() {
+            groups
+                .entry((
+                    &trip.route_id,
+                    &trip.stops,
+                    &trip.can_board,
+                    &trip.can_alight,
+                ))
+                .or_default()
+                .push(i);
+        }
+
+        let mut patterns = vec![];
+        for ((route_id, stops, can_board, can_alight), mut group) in groups {
+            group.sort_by_key(|&t| trips[t].departures[0]);
+
+            // Split the group into lanes where no trip overtakes the one before it.
+            let mut lanes: Vec<Vec<TripIdx>> = vec![];
+            for trip in group {
+                match lanes
+                    .iter_mut()
+                    .find(|lane| trips[trip].never_before(&trips[*lane.last().unwrap()]))
+                {
+                    Some(lane) => lane.push(trip),
+                    None => lanes.push(vec![trip]),
+                }
+            }
+
+            patterns.extend(lanes.into_iter().map(|lane| Pattern {
+                route_id: route_id.to_owned(),
+                stops: stops.to_vec(),
+                can_board: can_board.to_vec(),
+                can_alight: can_alight.to_vec(),
+                trips: lane,
+            }));
+        }
+
+        let mut stop_patterns = vec![vec![]; network.stops.len()];
+        for (p, pattern) in patterns.iter().enumerate() {
+            for (position, &stop) in pattern.stops.iter().enumerate() {
+                stop_patterns[stop].push((p, position));
+            }
+        }
+
+        let trip_index = trips
+            .iter()
+            .enumerate()
+            .map(|(i, t)| ((t.trip_id.clone(), t.service_date), i))
+            .collect();
+
+        Timetable {
+            date,
+            network,
+            trips,
+            trip_index,
+            patterns,
+            stop_patterns,
+        }
+    }
+}
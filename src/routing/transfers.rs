@@ -0,0 +1,81 @@
+//! Walking transfers between nearby stops.
+//!
+//! The feed's own transfers.txt has nothing useful in it, so after each static
+//! import every pair of stops close enough together gets a footpath. Walks are
+//! straight lines stretched by the planner's detour factor, as there's no street
+//! network here.
+
+use anyhow::Result;
+use tracing::{info, instrument};
+
+use crate::{
+    db::{
+        Db, queries,
+        types::{GeneratedTransfer, Stop},
+    },
+    geo::PointGrid,
+    routing::timetable::{DETOUR_FACTOR, walk_time},
+    vars,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct TransferConfig {
+    /// Straight-line distance.
+    pub max_distance_m: f64,
+}
+
+impl TransferConfig {
+    pub fn from_env() -> TransferConfig {
+        TransferConfig {
+            max_distance_m: vars::transfer_max_distance_m(),
+        }
+    }
+}
+
+/// Transfers both ways between every pair of stops within max_distance_m of each other.
+/// Stations and entrances aren't boarded at, so only stops and platforms get them.
+pub fn generate(stops: &[Stop], config: &TransferConfig) -> Vec<GeneratedTransfer> {
+    let stops: Vec<(&str, f64, f64)> = stops
+        .iter()
+        .filter(|s| s.location_type.unwrap_or(0) == 0)
+        .filter_map(|s| Some((s.stop_id.as_str(), s.stop_lat?, s.stop_lon?)))
+        .collect();
+    let grid = PointGrid::new(
+        stops.iter().map(|&(_, lat, lon)| (lat, lon)).collect(),
+        config.max_distance_m,
+    );
+
+    let mut transfers = vec![];
+    for (i, &(from, lat, lon)) in stops.iter().enumerate() {
+        for (j, distance) in grid.within(lat, lon, config.max_distance_m) {
+            if i == j {
+                continue;
+            }
+            transfers.push(GeneratedTransfer {
+                from_stop_id: from.to_owned(),
+                to_stop_id: stops[j].0.to_owned(),
+                distance_m: distance * DETOUR_FACTOR,
+                walk_time: walk_time(distance),
+            });
+        }
+    }
+    transfers
+}
+
+/// Regenerates the stored transfers from the stops currently in the db.
+#[instrument(skip(db))]
+pub async fn refresh(db: &Db) -> Result<()> {
+    let config = TransferConfig::from_env();
+    let stops = queries::get_stops(&db.0).await?;
+    let transfers = generate(&stops, &config);
+
+    let mut tx = db.0.begin().await?;
+    queries::replace_generated_transfers(&transfers, &mut tx).await?;
+    tx.commit().await?;
+    info!(
+        transfers = transfers.len(),
+        ?config,
+        "Generated walking transfers"
+    );
+    Ok(())
+}
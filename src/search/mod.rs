@@ -0,0 +1,116 @@
+//! SEARCH
+//!
+//! Fuzzy stop and route search, backed by the trigram indexes.
+//! Platforms are grouped under their parent station, so searching
+//! "roma st" gives back the station once with all its platforms.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::db::{
+    Db, queries,
+    types::{Route, Stop},
+};
+
+/// How many stations and routes a single search returns at most.
+pub const MAX_RESULTS: usize = 20;
+
+/// How many raw stop matches to pull before grouping them into stations.
+/// Busy stations have a lot of platforms, so this is well above MAX_RESULTS.
+const MAX_STOP_MATCHES: i64 = 200;
+
+/// A matched station (or standalone stop) and its child platforms.
+#[derive(Debug, Serialize)]
+pub struct StationMatch {
+    pub station: Stop,
+    pub platforms: Vec<Stop>,
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteMatch {
+    pub route: Route,
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub stations: Vec<StationMatch>,
+    pub routes: Vec<RouteMatch>,
+}
+
+/// Searches stops and routes for the query, best matches first.
+#[instrument(skip(db))]
+pub async fn search(query: &str, db: &Db) -> Result<SearchResults> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(SearchResults {
+            stations: vec![],
+            routes: vec![],
+        });
+    }
+
+    let stations = search_stations(query, db).await?;
+    let routes = queries::search_routes(query, MAX_RESULTS as i64, &db.0)
+        .await?
+        .into_iter()
+        .map(|(route, score)| RouteMatch { route, score })
+        .collect();
+
+    Ok(SearchResults { stations, routes })
+}
+
+async fn search_stations(query: &str, db: &Db) -> Result<Vec<StationMatch>> {
+    let matches = queries::search_stops(query, MAX_STOP_MATCHES, &db.0).await?;
+
+    // Matches come back best first, so the first time we see a station
+    // is also its best score.
+    let mut order: Vec<String> = vec![];
+    let mut scores: HashMap<String, f32> = HashMap::new();
+    let mut stations: HashMap<String, Stop> = HashMap::new();
+    for (stop, score) in matches {
+        let station_id = stop
+            .parent_station
+            .clone()
+            .unwrap_or_else(|| stop.stop_id.clone());
+        if !scores.contains_key(&station_id) {
+            scores.insert(station_id.clone(), score);
+            order.push(station_id.clone());
+        }
+        if stop.stop_id == station_id {
+            stations.insert(station_id, stop);
+        }
+    }
+    order.truncate(MAX_RESULTS);
+
+    // Platforms may match without their station, so look those stations up.
+    let missing: Vec<String> = order
+        .iter()
+        .filter(|id| !stations.contains_key(*id))
+        .cloned()
+        .collect();
+    for stop in queries::get_stops_by_id(&missing, &db.0).await? {
+        stations.insert(stop.stop_id.clone(), stop);
+    }
+
+    let mut platforms: HashMap<String, Vec<Stop>> = HashMap::new();
+    for stop in queries::get_child_stops(&order, &db.0).await? {
+        if let Some(parent) = stop.parent_station.clone() {
+            platforms.entry(parent).or_default().push(stop);
+        }
+    }
+
+    Ok(order
+        .into_iter()
+        .filter_map(|id| {
+            Some(StationMatch {
+                station: stations.remove(&id)?,
+                platforms: platforms.remove(&id).unwrap_or_default(),
+                score: scores[&id],
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,218 @@
+//! Tile maths: projecting to web mercator tile space, clipping and simplifying.
+
+use std::f64::consts::PI;
+
+/// Tile coordinate space, as recommended by the MVT spec.
+pub const EXTENT: u32 = 4096;
+
+/// How far past the tile edge geometry is kept, so lines don't visibly
+/// end at tile boundaries.
+pub const BUFFER: f64 = 64.0;
+
+/// Highest zoom we are willing to render.
+pub const MAX_ZOOM: u8 = 22;
+
+/// A lon/lat bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BBox {
+    /// Smallest box around the given (lon, lat) points.
+    pub fn around(points: &[(f64, f64)]) -> BBox {
+        points.iter().fold(
+            BBox {
+                min_lon: f64::MAX,
+                min_lat: f64::MAX,
+                max_lon: f64::MIN,
+                max_lat: f64::MIN,
+            },
+            |b, &(lon, lat)| BBox {
+                min_lon: b.min_lon.min(lon),
+                min_lat: b.min_lat.min(lat),
+                max_lon: b.max_lon.max(lon),
+                max_lat: b.max_lat.max(lat),
+            },
+        )
+    }
+
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.min_lon <= other.max_lon
+            && other.min_lon <= self.max_lon
+            && self.min_lat <= other.max_lat
+            && other.min_lat <= self.max_lat
+    }
+
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        (self.min_lon..=self.max_lon).contains(&lon) && (self.min_lat..=self.max_lat).contains(&lat)
+    }
+}
+
+/// A z/x/y tile address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    /// Returns None if the tile doesn't exist at that zoom.
+    pub fn new(z: u8, x: u32, y: u32) -> Option<TileId> {
+        let tiles = 1u64 << z.min(MAX_ZOOM);
+        (z <= MAX_ZOOM && (x as u64) < tiles && (y as u64) < tiles).then_some(TileId { z, x, y })
+    }
+
+    fn tiles(&self) -> f64 {
+        (1u64 << self.z) as f64
+    }
+
+    /// Lon/lat bounds of the tile, grown to include the buffer.
+    pub fn bbox(&self) -> BBox {
+        let pad = BUFFER / EXTENT as f64;
+        let lon = |x: f64| x / self.tiles() * 360.0 - 180.0;
+        let lat = |y: f64| {
+            (PI * (1.0 - 2.0 * y / self.tiles()))
+                .sinh()
+                .atan()
+                .to_degrees()
+        };
+        BBox {
+            min_lon: lon(self.x as f64 - pad),
+            max_lon: lon(self.x as f64 + 1.0 + pad),
+            min_lat: lat(self.y as f64 + 1.0 + pad),
+            max_lat: lat(self.y as f64 - pad),
+        }
+    }
+
+    /// Projects a lon/lat into this tile's coordinate space (0..EXTENT, y down).
+    pub fn project(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let lat = lat.clamp(-85.051_128, 85.051_128).to_radians();
+        let x = (lon + 180.0) / 360.0;
+        let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0;
+        (
+            (x * self.tiles() - self.x as f64) * EXTENT as f64,
+            (y * self.tiles() - self.y as f64) * EXTENT as f64,
+        )
+    }
+}
+
+/// Douglas-Peucker simplification. Tolerance is in the same units as the points,
+/// so working in tile space gives per-zoom simplification for free.
+pub fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let (mut furthest, mut max_distance) = (start, 0.0);
+        for i in start + 1..end {
+            let distance = segment_distance(points[i], points[start], points[end]);
+            if distance > max_distance {
+                furthest = i;
+                max_distance = distance;
+            }
+        }
+        if max_distance > tolerance {
+            keep[furthest] = true;
+            stack.push((start, furthest));
+            stack.push((furthest, end));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(p, keep)| keep.then_some(*p))
+        .collect()
+}
+
+/// Distance from p to the segment a-b.
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = dx * dx + dy * dy;
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0)
+    };
+    ((p.0 - a.0 - t * dx).powi(2) + (p.1 - a.1 - t * dy).powi(2)).sqrt()
+}
+
+/// Clips a line to the square min..max, splitting it wherever it leaves and re-enters.
+pub fn clip_line(points: &[(f64, f64)], min: f64, max: f64) -> Vec<Vec<(f64, f64)>> {
+    let mut parts = vec![];
+    let mut current: Vec<(f64, f64)> = vec![];
+
+    for segment in points.windows(2) {
+        let Some((a, b)) = clip_segment(segment[0], segment[1], min, max) else {
+            if current.len() > 1 {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        };
+
+        if current.last() != Some(&a) {
+            if current.len() > 1 {
+                parts.push(std::mem::take(&mut current));
+            }
+            current = vec![a];
+        }
+        current.push(b);
+    }
+
+    if current.len() > 1 {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Liang-Barsky clipping of a single segment.
+fn clip_segment(
+    a: (f64, f64),
+    b: (f64, f64),
+    min: f64,
+    max: f64,
+) -> Option<((f64, f64), (f64, f64))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+
+    for (p, q) in [
+        (-dx, a.0 - min),
+        (dx, max - a.0),
+        (-dy, a.1 - min),
+        (dy, max - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| (a.0 + t * dx, a.1 + t * dy);
+    Some((
+        if t0 == 0.0 { a } else { at(t0) },
+        if t1 == 1.0 { b } else { at(t1) },
+    ))
+}
@@ -0,0 +1,192 @@
+//! TILES
+//!
+//! Mapbox vector tiles (MVT) of routes and stops, rendered on demand.
+//! Route geometry is pulled from the db once and kept in memory,
+//! then projected, clipped and simplified for each tile requested.
+
+pub mod geometry;
+pub mod mvt;
+#[cfg(test)]
+mod tests;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use prost::Message;
+use tokio::sync::RwLock;
+use tracing::{info, instrument};
+
+use crate::{
+    db::{Db, queries, types::Stop},
+    export::{RouteLine, css_color, route_lines},
+    tiles::{
+        geometry::{BBox, BUFFER, EXTENT, TileId, clip_line, simplify},
+        mvt::{LayerBuilder, Property, encode_lines, encode_points},
+    },
+    vector_tile::{Tile, tile::GeomType},
+};
+
+/// Stops are only drawn once zoomed in far enough to tell them apart.
+pub const STOPS_MIN_ZOOM: u8 = 13;
+
+/// Simplification tolerance in tile units. 4096 / 256 = 16 units per screen pixel.
+const SIMPLIFY_TOLERANCE: f64 = 8.0;
+
+/// A route line with its bounding box, so tiles can skip it cheaply.
+struct IndexedLine {
+    line: RouteLine,
+    bbox: BBox,
+}
+
+/// Everything the tiles are rendered from.
+pub struct TileData {
+    lines: Vec<IndexedLine>,
+    stops: Vec<Stop>,
+}
+
+impl TileData {
+    #[instrument(skip(db))]
+    pub async fn load(db: &Db) -> Result<TileData> {
+        info!("Loading tile data");
+        let lines = route_lines(db)
+            .await?
+            .into_iter()
+            .map(|line| IndexedLine {
+                bbox: BBox::around(&line.points),
+                line,
+            })
+            .collect();
+        let stops = queries::get_stops(&db.0).await?;
+        info!("Finished loading tile data");
+        Ok(TileData { lines, stops })
+    }
+}
+
+/// Lazily loaded, shared TileData.
+/// Invalidate it whenever the static feed changes.
+#[derive(Clone, Default)]
+pub struct TileCache(Arc<RwLock<Option<Arc<TileData>>>>);
+
+impl TileCache {
+    pub async fn get(&self, db: &Db) -> Result<Arc<TileData>> {
+        if let Some(data) = self.0.read().await.as_ref() {
+            return Ok(data.clone());
+        }
+
+        let mut cached = self.0.write().await;
+        // Someone else may have loaded it while we waited on the lock.
+        if let Some(data) = cached.as_ref() {
+            return Ok(data.clone());
+        }
+        let data = Arc::new(TileData::load(db).await?);
+        *cached = Some(data.clone());
+        Ok(data)
+    }
+
+    pub async fn invalidate(&self) {
+        *self.0.write().await = None;
+    }
+}
+
+/// Renders a tile to MVT bytes. Tiles with nothing in them are empty but valid.
+pub fn render(data: &TileData, tile: TileId) -> Vec<u8> {
+    let bbox = tile.bbox();
+    let mut layers = vec![];
+
+    let mut routes = LayerBuilder::new("routes");
+    for IndexedLine { line, .. } in data.lines.iter().filter(|l| l.bbox.intersects(&bbox)) {
+        let projected: Vec<(f64, f64)> = line
+            .points
+            .iter()
+            .map(|&(lon, lat)| tile.project(lon, lat))
+            .collect();
+        let parts: Vec<Vec<(i32, i32)>> = clip_line(
+            &simplify(&projected, SIMPLIFY_TOLERANCE),
+            -BUFFER,
+            EXTENT as f64 + BUFFER,
+        )
+        .into_iter()
+        .map(|part| {
+            let mut part: Vec<(i32, i32)> = part
+                .into_iter()
+                .map(|(x, y)| (x.round() as i32, y.round() as i32))
+                .collect();
+            part.dedup();
+            part
+        })
+        .filter(|part| part.len() > 1)
+        .collect();
+        if parts.is_empty() {
+            continue;
+        }
+
+        let route = &line.route;
+        routes.add(
+            GeomType::Linestring,
+            encode_lines(&parts),
+            vec![
+                ("route_id", Some(Property::String(route.route_id.clone()))),
+                ("shape_id", Some(Property::String(line.shape_id.clone()))),
+                (
+                    "route_short_name",
+                    route.route_short_name.clone().map(Property::String),
+                ),
+                (
+                    "route_long_name",
+                    route.route_long_name.clone().map(Property::String),
+                ),
+                ("route_type", Some(Property::Int(route.route_type.into()))),
+                (
+                    "route_color",
+                    css_color(&route.route_color).map(Property::String),
+                ),
+                (
+                    "route_text_color",
+                    css_color(&route.route_text_color).map(Property::String),
+                ),
+            ],
+        );
+    }
+    if !routes.is_empty() {
+        layers.push(routes.build());
+    }
+
+    if tile.z >= STOPS_MIN_ZOOM {
+        let mut stops = LayerBuilder::new("stops");
+        for stop in &data.stops {
+            let (Some(lon), Some(lat)) = (stop.stop_lon, stop.stop_lat) else {
+                continue;
+            };
+            if !bbox.contains(lon, lat) {
+                continue;
+            }
+            let (x, y) = tile.project(lon, lat);
+            stops.add(
+                GeomType::Point,
+                encode_points(&[(x.round() as i32, y.round() as i32)]),
+                vec![
+                    ("stop_id", Some(Property::String(stop.stop_id.clone()))),
+                    ("stop_code", stop.stop_code.clone().map(Property::String)),
+                    ("stop_name", stop.stop_name.clone().map(Property::String)),
+                    (
+                        "location_type",
+                        stop.location_type.map(|t| Property::Int(t.into())),
+                    ),
+                    (
+                        "parent_station",
+                        stop.parent_station.clone().map(Property::String),
+                    ),
+                    (
+                        "platform_code",
+                        stop.platform_code.clone().map(Property::String),
+                    ),
+                ],
+            );
+        }
+        if !stops.is_empty() {
+            layers.push(stops.build());
+        }
+    }
+
+    Tile { layers }.encode_to_vec()
+}
@@ -0,0 +1,142 @@
+//! Mapbox vector tile encoding, on top of the prost generated types.
+//! See https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+
+use std::collections::HashMap;
+
+use crate::vector_tile::tile::{Feature, GeomType, Layer, Value};
+
+use super::geometry::EXTENT;
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+/// Encodes points as a single (multi)point geometry.
+pub fn encode_points(points: &[(i32, i32)]) -> Vec<u32> {
+    let mut geometry = vec![command(MOVE_TO, points.len() as u32)];
+    let mut cursor = (0, 0);
+    for &(x, y) in points {
+        geometry.push(zigzag(x - cursor.0));
+        geometry.push(zigzag(y - cursor.1));
+        cursor = (x, y);
+    }
+    geometry
+}
+
+/// Encodes one or more lines as a single (multi)linestring geometry.
+/// Lines must already be deduplicated and have at least two points each.
+pub fn encode_lines(lines: &[Vec<(i32, i32)>]) -> Vec<u32> {
+    let mut geometry = vec![];
+    let mut cursor = (0, 0);
+    for line in lines {
+        for (i, &(x, y)) in line.iter().enumerate() {
+            match i {
+                0 => geometry.push(command(MOVE_TO, 1)),
+                1 => geometry.push(command(LINE_TO, line.len() as u32 - 1)),
+                _ => {}
+            }
+            geometry.push(zigzag(x - cursor.0));
+            geometry.push(zigzag(y - cursor.1));
+            cursor = (x, y);
+        }
+    }
+    geometry
+}
+
+/// Feature property values. Only the kinds we actually emit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Property {
+    String(String),
+    Int(i64),
+}
+
+impl From<Property> for Value {
+    fn from(property: Property) -> Value {
+        match property {
+            Property::String(s) => Value {
+                string_value: Some(s),
+                ..Default::default()
+            },
+            Property::Int(i) => Value {
+                sint_value: Some(i),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Builds a layer, deduplicating keys and values as it goes.
+pub struct LayerBuilder {
+    name: String,
+    features: Vec<Feature>,
+    keys: Vec<String>,
+    key_index: HashMap<String, u32>,
+    values: Vec<Property>,
+    value_index: HashMap<Property, u32>,
+}
+
+impl LayerBuilder {
+    pub fn new(name: &str) -> LayerBuilder {
+        LayerBuilder {
+            name: name.to_owned(),
+            features: vec![],
+            keys: vec![],
+            key_index: HashMap::new(),
+            values: vec![],
+            value_index: HashMap::new(),
+        }
+    }
+
+    /// Adds a feature. Properties that are None are left off.
+    pub fn add(
+        &mut self,
+        geom_type: GeomType,
+        geometry: Vec<u32>,
+        properties: Vec<(&str, Option<Property>)>,
+    ) {
+        let mut tags = vec![];
+        for (key, value) in properties {
+            let Some(value) = value else {
+                continue;
+            };
+            let key = *self.key_index.entry(key.to_owned()).or_insert_with(|| {
+                self.keys.push(key.to_owned());
+                self.keys.len() as u32 - 1
+            });
+            let value = *self.value_index.entry(value.clone()).or_insert_with(|| {
+                self.values.push(value);
+                self.values.len() as u32 - 1
+            });
+            tags.extend([key, value]);
+        }
+
+        self.features.push(Feature {
+            id: None,
+            tags,
+            r#type: Some(geom_type as i32),
+            geometry,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn build(self) -> Layer {
+        Layer {
+            version: 2,
+            name: self.name,
+            features: self.features,
+            keys: self.keys,
+            values: self.values.into_iter().map(Value::from).collect(),
+            extent: Some(EXTENT),
+        }
+    }
+}
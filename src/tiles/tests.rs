@@ -0,0 +1,54 @@
+//! Tile Tests
+//!
+//! Geometry and encoding checks, no db needed.
+
+use super::geometry::{TileId, clip_line, simplify};
+use super::mvt::{encode_lines, encode_points};
+
+#[test]
+fn test_tile_id_bounds() {
+    assert!(TileId::new(0, 0, 0).is_some());
+    assert!(TileId::new(2, 3, 3).is_some());
+    assert!(TileId::new(2, 4, 0).is_none());
+    assert!(TileId::new(23, 0, 0).is_none());
+}
+
+#[test]
+fn test_project_roundtrips_bbox() {
+    // Brisbane CBD at z14.
+    let tile = TileId::new(14, 15156, 9493).unwrap();
+    let (x, y) = tile.project(153.025, -27.47);
+    assert!((0.0..4096.0).contains(&x), "x = {x}");
+    assert!((0.0..4096.0).contains(&y), "y = {y}");
+    assert!(tile.bbox().contains(153.025, -27.47));
+}
+
+#[test]
+fn test_simplify_drops_collinear_points() {
+    let line = vec![(0.0, 0.0), (1.0, 0.1), (2.0, 0.0), (3.0, 5.0)];
+    assert_eq!(
+        simplify(&line, 0.5),
+        vec![(0.0, 0.0), (2.0, 0.0), (3.0, 5.0)]
+    );
+}
+
+#[test]
+fn test_clip_line_splits_on_exit() {
+    // Goes out the right side and comes back in.
+    let line = vec![(0.0, 0.0), (20.0, 0.0), (20.0, 5.0), (0.0, 5.0)];
+    let parts = clip_line(&line, -1.0, 10.0);
+    assert_eq!(
+        parts,
+        vec![vec![(0.0, 0.0), (10.0, 0.0)], vec![(10.0, 5.0), (0.0, 5.0)]]
+    );
+}
+
+#[test]
+fn test_encode_geometry() {
+    // Examples from section 4.3.5 of the MVT spec.
+    assert_eq!(encode_points(&[(25, 17)]), vec![9, 50, 34]);
+    assert_eq!(
+        encode_lines(&[vec![(2, 2), (2, 10), (10, 10)]]),
+        vec![9, 4, 4, 18, 0, 16, 16, 0]
+    );
+}
@@ -1,12 +1,25 @@
-use std::env::var;
+use std::{env::var, path::PathBuf};
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
 
 pub fn db_url() -> String {
     var("DATABASE_URL").expect("DATABASE_URL must be set")
 }
 
+/// Db connections kept free for polling and the API while an import stages its tables.
+pub fn db_spare_connections() -> u32 {
+    var("DB_SPARE_CONNECTIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10)
+}
+
 pub const REALTIME_URL: &str = "https://gtfsrt.api.translink.com.au/api/realtime";
 pub const REALTIME_ENDPOINTS: [&str; 3] = ["SEQ/TripUpdates", "SEQ/VehiclePositions", "SEQ/alerts"];
 pub const STATIC_URL: &str = "https://gtfsrt.api.translink.com.au/GTFS/SEQ_GTFS.zip";
+/// Timezone of the feed until one with an agency_timezone has been loaded.
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Australia::Brisbane;
 
 pub fn realtime_urls() -> Vec<String> {
     REALTIME_ENDPOINTS
@@ -14,3 +27,146 @@ pub fn realtime_urls() -> Vec<String> {
         .map(|ep| format!("{}/{}", REALTIME_URL, ep))
         .collect()
 }
+
+/// Address the HTTP API listens on.
+pub fn api_addr() -> String {
+    var("API_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_owned())
+}
+
+/// Seconds since the last successful realtime poll before the service isn't ready.
+pub fn realtime_max_age_secs() -> i64 {
+    var("REALTIME_MAX_AGE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300)
+}
+
+/// Directory to write GeoJSON into after each static import, if set.
+pub fn geojson_export_dir() -> Option<PathBuf> {
+    var("GEOJSON_EXPORT_DIR").ok().map(PathBuf::from)
+}
+
+/// Directory to write Parquet into after each static import and analytics run, if set.
+pub fn parquet_export_dir() -> Option<PathBuf> {
+    var("PARQUET_EXPORT_DIR").ok().map(PathBuf::from)
+}
+
+/// Directory to write a diff against the previous feed into after each static import, if set.
+pub fn feed_diff_dir() -> Option<PathBuf> {
+    var("FEED_DIFF_DIR").ok().map(PathBuf::from)
+}
+
+/// Directory to keep the exported GTFS zip in between imports, a temp dir by default.
+pub fn gtfs_cache_dir() -> PathBuf {
+    var("GTFS_CACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join("gtfs"))
+}
+
+/// Two feed zips, "old.zip,new.zip", to diff instead of running the server.
+pub fn diff_feeds() -> Option<(PathBuf, PathBuf)> {
+    let feeds = var("DIFF_FEEDS").ok()?;
+    let (old, new) = feeds.split_once(',')?;
+    Some((PathBuf::from(old), PathBuf::from(new)))
+}
+
+/// Other static feeds to merge into SEQ's on import, as "PREFIX=path or url" separated by commas.
+pub fn merge_feeds() -> Vec<(String, String)> {
+    var("MERGE_FEEDS")
+        .map(|feeds| {
+            feeds
+                .split(',')
+                .filter_map(|feed| feed.split_once('='))
+                .map(|(prefix, path)| (prefix.trim().to_owned(), path.trim().to_owned()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Furthest apart (in metres) two stops with the same name in merged feeds can be to count as one.
+pub fn merge_stop_distance_m() -> f64 {
+    var("MERGE_STOP_DISTANCE_M")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(25.0)
+}
+
+/// Furthest apart (straight-line, in metres) two stops can be to get a walking transfer.
+pub fn transfer_max_distance_m() -> f64 {
+    var("TRANSFER_MAX_DISTANCE_M")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(400.0)
+}
+
+/// Seconds early a stop can be left and still count as on time.
+pub fn on_time_early_secs() -> i32 {
+    var("ON_TIME_EARLY_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60)
+}
+
+/// Seconds late a stop can be left and still count as on time.
+pub fn on_time_late_secs() -> i32 {
+    var("ON_TIME_LATE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300)
+}
+
+/// Vehicles leaving a stop more than this many seconds closer together than scheduled are bunched.
+pub fn headway_bunching_secs() -> i32 {
+    var("HEADWAY_BUNCHING_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(120)
+}
+
+/// Vehicles leaving a stop more than this many times their scheduled headway apart leave a gap.
+pub fn headway_gap_factor() -> f64 {
+    var("HEADWAY_GAP_FACTOR")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2.0)
+}
+
+/// Headways are only checked where services are scheduled at most this many seconds apart.
+pub fn headway_max_scheduled_secs() -> i32 {
+    var("HEADWAY_MAX_SCHEDULED_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(900)
+}
+
+/// Directory to archive every raw realtime payload into, if set.
+pub fn realtime_archive_dir() -> Option<PathBuf> {
+    var("REALTIME_ARCHIVE_DIR").ok().map(PathBuf::from)
+}
+
+/// Days of raw realtime payloads to keep, including today.
+pub fn realtime_archive_days() -> u64 {
+    var("REALTIME_ARCHIVE_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30)
+}
+
+/// First day to replay from the realtime archive. When set, polls are
+/// replayed instead of fetched.
+pub fn replay_from() -> Option<NaiveDate> {
+    var("REPLAY_FROM").ok().and_then(|v| v.parse().ok())
+}
+
+/// Last day to replay, the same as REPLAY_FROM if not set.
+pub fn replay_to() -> Option<NaiveDate> {
+    var("REPLAY_TO").ok().and_then(|v| v.parse().ok())
+}
+
+/// How many times faster than real time to replay. 0 doesn't wait between polls at all.
+pub fn replay_speed() -> f64 {
+    var("REPLAY_SPEED")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60.0)
+}